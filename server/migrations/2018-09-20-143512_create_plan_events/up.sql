@@ -0,0 +1,12 @@
+CREATE TABLE plan_events (
+  id SERIAL PRIMARY KEY,
+  course_plan_id INTEGER NOT NULL,
+  user_id INTEGER NOT NULL,
+  kind VARCHAR NOT NULL,
+  before TEXT NULL,
+  after TEXT NULL,
+  undone_at TIMESTAMPTZ NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id),
+  FOREIGN KEY (user_id) REFERENCES users (id)
+);
@@ -0,0 +1,5 @@
+ALTER TABLE term_courses
+DROP COLUMN deleted_at;
+
+ALTER TABLE terms
+DROP COLUMN deleted_at;
@@ -0,0 +1,5 @@
+ALTER TABLE terms
+ADD COLUMN deleted_at TIMESTAMPTZ NULL;
+
+ALTER TABLE term_courses
+ADD COLUMN deleted_at TIMESTAMPTZ NULL;
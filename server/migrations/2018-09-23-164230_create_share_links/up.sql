@@ -0,0 +1,9 @@
+CREATE TABLE share_links (
+  id SERIAL PRIMARY KEY,
+  course_plan_id INTEGER NOT NULL,
+  token VARCHAR NOT NULL UNIQUE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NULL,
+  revoked_at TIMESTAMPTZ NULL,
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE sessions (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  jti VARCHAR NOT NULL UNIQUE,
+  refresh_token_hash VARCHAR NOT NULL UNIQUE,
+  previous_refresh_token_hash VARCHAR NULL,
+  user_agent VARCHAR NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL,
+  revoked_at TIMESTAMPTZ NULL,
+  FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX sessions_previous_refresh_token_hash ON sessions (previous_refresh_token_hash);
@@ -0,0 +1,8 @@
+ALTER TABLE users
+ADD COLUMN google_id VARCHAR UNIQUE NULL;
+
+UPDATE users SET google_id = identities.subject
+FROM identities
+WHERE identities.user_id = users.id AND identities.provider = 'google';
+
+DROP TABLE identities;
@@ -0,0 +1,18 @@
+CREATE TABLE identities (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  provider VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (user_id) REFERENCES users (id),
+  CONSTRAINT unique_identity_provider_subject UNIQUE (provider, subject)
+);
+
+CREATE INDEX identities_user_id ON identities (user_id);
+
+INSERT INTO identities (user_id, provider, subject, email)
+SELECT id, 'google', google_id, email FROM users WHERE google_id IS NOT NULL;
+
+ALTER TABLE users
+DROP COLUMN google_id;
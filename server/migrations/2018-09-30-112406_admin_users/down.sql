@@ -0,0 +1,6 @@
+ALTER TABLE course_plans
+DROP COLUMN template_id;
+
+ALTER TABLE users
+DROP COLUMN is_admin,
+DROP COLUMN disabled_at;
@@ -0,0 +1,44 @@
+DROP INDEX sessions_user_id;
+DROP INDEX share_links_course_plan_id;
+DROP INDEX plan_events_course_plan_id;
+DROP INDEX term_courses_term_id;
+DROP INDEX terms_course_plan_id;
+DROP INDEX course_plans_user_id;
+
+ALTER TABLE identities
+DROP CONSTRAINT identities_user_id_fkey,
+ADD CONSTRAINT identities_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE sessions
+DROP CONSTRAINT sessions_user_id_fkey,
+ADD CONSTRAINT sessions_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE share_links
+DROP CONSTRAINT share_links_course_plan_id_fkey,
+ADD CONSTRAINT share_links_course_plan_id_fkey
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id);
+
+ALTER TABLE plan_events
+DROP CONSTRAINT plan_events_course_plan_id_fkey,
+ADD CONSTRAINT plan_events_course_plan_id_fkey
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id),
+DROP CONSTRAINT plan_events_user_id_fkey,
+ADD CONSTRAINT plan_events_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE term_courses
+DROP CONSTRAINT term_courses_term_id_fkey,
+ADD CONSTRAINT term_courses_term_id_fkey
+  FOREIGN KEY (term_id) REFERENCES terms (id);
+
+ALTER TABLE terms
+DROP CONSTRAINT terms_course_plan_id_fkey,
+ADD CONSTRAINT terms_course_plan_id_fkey
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id);
+
+ALTER TABLE course_plans
+DROP CONSTRAINT course_plans_user_id_fkey,
+ADD CONSTRAINT course_plans_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id);
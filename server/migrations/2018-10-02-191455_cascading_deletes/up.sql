@@ -0,0 +1,49 @@
+-- Deleting a user removes everything that belongs to them, and deleting a course plan removes its
+-- terms, courses, history and share links
+
+ALTER TABLE course_plans
+DROP CONSTRAINT course_plans_user_id_fkey,
+ADD CONSTRAINT course_plans_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+ALTER TABLE terms
+DROP CONSTRAINT terms_course_plan_id_fkey,
+ADD CONSTRAINT terms_course_plan_id_fkey
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE;
+
+ALTER TABLE term_courses
+DROP CONSTRAINT term_courses_term_id_fkey,
+ADD CONSTRAINT term_courses_term_id_fkey
+  FOREIGN KEY (term_id) REFERENCES terms (id) ON DELETE CASCADE;
+
+ALTER TABLE plan_events
+DROP CONSTRAINT plan_events_course_plan_id_fkey,
+ADD CONSTRAINT plan_events_course_plan_id_fkey
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE,
+DROP CONSTRAINT plan_events_user_id_fkey,
+ADD CONSTRAINT plan_events_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+ALTER TABLE share_links
+DROP CONSTRAINT share_links_course_plan_id_fkey,
+ADD CONSTRAINT share_links_course_plan_id_fkey
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE;
+
+ALTER TABLE sessions
+DROP CONSTRAINT sessions_user_id_fkey,
+ADD CONSTRAINT sessions_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+ALTER TABLE identities
+DROP CONSTRAINT identities_user_id_fkey,
+ADD CONSTRAINT identities_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+-- Postgres does not index foreign keys automatically. Without these, every cascading delete (and
+-- every lookup of the records that belong to something) scans the whole table.
+CREATE INDEX course_plans_user_id ON course_plans (user_id);
+CREATE INDEX terms_course_plan_id ON terms (course_plan_id);
+CREATE INDEX term_courses_term_id ON term_courses (term_id);
+CREATE INDEX plan_events_course_plan_id ON plan_events (course_plan_id);
+CREATE INDEX share_links_course_plan_id ON share_links (course_plan_id);
+CREATE INDEX sessions_user_id ON sessions (user_id);
@@ -0,0 +1,2 @@
+ALTER TABLE course_plans
+DROP COLUMN revision;
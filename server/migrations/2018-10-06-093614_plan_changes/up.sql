@@ -0,0 +1,30 @@
+-- Terms are ordered by position (and then by ID) instead of only by when they were added so that
+-- they can be moved
+ALTER TABLE terms
+ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE terms
+SET position = numbered.position
+FROM (
+  SELECT id, (ROW_NUMBER() OVER (PARTITION BY course_plan_id ORDER BY id) - 1)::INTEGER AS position
+  FROM terms
+) AS numbered
+WHERE terms.id = numbered.id;
+
+-- Every change to the terms and courses of a course plan, in the order they were made, so that
+-- clients can catch up on the changes made since the revision they last saw. Terms and courses
+-- are not foreign keys since the changes outlive them once they are purged.
+CREATE TABLE plan_changes (
+  id SERIAL PRIMARY KEY,
+  course_plan_id INTEGER NOT NULL,
+  revision INTEGER NOT NULL,
+  kind VARCHAR NOT NULL,
+  term_id INTEGER NOT NULL,
+  term_course_id INTEGER NULL,
+  name VARCHAR NULL,
+  position INTEGER NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE
+);
+
+CREATE INDEX plan_changes_course_plan_id_revision ON plan_changes (course_plan_id, revision);
@@ -0,0 +1,11 @@
+-- This is the SQLite version of the migration with the same name in ../migrations. Every
+-- migration there has a counterpart here (except for diesel's PostgreSQL helper functions).
+--
+-- AUTOINCREMENT makes sure that IDs are never reused, just like SERIAL. Timestamps are stored as
+-- text in UTC (see sql_types.rs).
+CREATE TABLE users (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name VARCHAR NOT NULL,
+  email VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+)
@@ -0,0 +1,6 @@
+CREATE TABLE course_plans (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (user_id) REFERENCES users (id)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE terms (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  course_plan_id INTEGER NOT NULL,
+  name VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id)
+);
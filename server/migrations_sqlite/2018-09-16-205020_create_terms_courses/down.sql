@@ -0,0 +1 @@
+DROP TABLE term_courses;
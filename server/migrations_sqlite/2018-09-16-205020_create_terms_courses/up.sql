@@ -0,0 +1,7 @@
+CREATE TABLE term_courses (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  term_id INTEGER NOT NULL,
+  name VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (term_id) REFERENCES terms (id)
+)
@@ -0,0 +1,4 @@
+DROP INDEX users_google_id;
+
+ALTER TABLE users
+DROP COLUMN google_id;
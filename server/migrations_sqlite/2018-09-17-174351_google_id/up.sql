@@ -0,0 +1,5 @@
+-- SQLite cannot add a UNIQUE column, so the uniqueness is enforced by an index instead
+ALTER TABLE users
+ADD COLUMN google_id VARCHAR NULL;
+
+CREATE UNIQUE INDEX users_google_id ON users (google_id);
@@ -0,0 +1 @@
+DROP INDEX unique_user_email;
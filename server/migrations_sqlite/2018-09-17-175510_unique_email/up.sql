@@ -0,0 +1,2 @@
+-- SQLite cannot add constraints to an existing table, so this is a unique index instead
+CREATE UNIQUE INDEX unique_user_email ON users (email);
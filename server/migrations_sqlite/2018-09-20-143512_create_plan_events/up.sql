@@ -0,0 +1,12 @@
+CREATE TABLE plan_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  course_plan_id INTEGER NOT NULL,
+  user_id INTEGER NOT NULL,
+  kind VARCHAR NOT NULL,
+  before TEXT NULL,
+  after TEXT NULL,
+  undone_at TEXT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id),
+  FOREIGN KEY (user_id) REFERENCES users (id)
+);
@@ -0,0 +1,5 @@
+ALTER TABLE terms
+ADD COLUMN deleted_at TEXT NULL;
+
+ALTER TABLE term_courses
+ADD COLUMN deleted_at TEXT NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE share_links (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  course_plan_id INTEGER NOT NULL,
+  token VARCHAR NOT NULL UNIQUE,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  expires_at TEXT NULL,
+  revoked_at TEXT NULL,
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id)
+);
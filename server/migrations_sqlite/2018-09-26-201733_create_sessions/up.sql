@@ -0,0 +1,15 @@
+CREATE TABLE sessions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  jti VARCHAR NOT NULL UNIQUE,
+  refresh_token_hash VARCHAR NOT NULL UNIQUE,
+  previous_refresh_token_hash VARCHAR NULL,
+  user_agent VARCHAR NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  last_used_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  expires_at TEXT NOT NULL,
+  revoked_at TEXT NULL,
+  FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX sessions_previous_refresh_token_hash ON sessions (previous_refresh_token_hash);
@@ -0,0 +1,11 @@
+ALTER TABLE users
+ADD COLUMN google_id VARCHAR NULL;
+
+CREATE UNIQUE INDEX users_google_id ON users (google_id);
+
+UPDATE users SET google_id = (
+  SELECT identities.subject FROM identities
+  WHERE identities.user_id = users.id AND identities.provider = 'google'
+);
+
+DROP TABLE identities;
@@ -0,0 +1,21 @@
+CREATE TABLE identities (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  provider VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (user_id) REFERENCES users (id),
+  CONSTRAINT unique_identity_provider_subject UNIQUE (provider, subject)
+);
+
+CREATE INDEX identities_user_id ON identities (user_id);
+
+INSERT INTO identities (user_id, provider, subject, email)
+SELECT id, 'google', google_id, email FROM users WHERE google_id IS NOT NULL;
+
+-- SQLite cannot drop a column that is still indexed
+DROP INDEX users_google_id;
+
+ALTER TABLE users
+DROP COLUMN google_id;
@@ -0,0 +1,11 @@
+-- SQLite cannot drop a column that is still indexed
+DROP INDEX course_plans_template_id;
+
+ALTER TABLE course_plans
+DROP COLUMN template_id;
+
+ALTER TABLE users
+DROP COLUMN is_admin;
+
+ALTER TABLE users
+DROP COLUMN disabled_at;
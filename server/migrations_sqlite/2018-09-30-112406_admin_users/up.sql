@@ -0,0 +1,11 @@
+ALTER TABLE users
+ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT 0;
+
+ALTER TABLE users
+ADD COLUMN disabled_at TEXT NULL;
+
+-- The program template that the course plan was created from (if any)
+ALTER TABLE course_plans
+ADD COLUMN template_id VARCHAR NULL;
+
+CREATE INDEX course_plans_template_id ON course_plans (template_id);
@@ -0,0 +1,145 @@
+-- Deleting a user removes everything that belongs to them, and deleting a course plan removes its
+-- terms, courses, history and share links
+--
+-- SQLite cannot change the foreign keys of an existing table, so each table is copied into a new
+-- table with the right foreign keys. Foreign keys are not enforced while migrations run (they are
+-- only enabled by the application, see api::db), so dropping the old tables does not delete
+-- anything else.
+
+CREATE TABLE new_course_plans (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  template_id VARCHAR NULL,
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+INSERT INTO new_course_plans (id, user_id, created_at, template_id)
+SELECT id, user_id, created_at, template_id FROM course_plans;
+
+DROP TABLE course_plans;
+
+ALTER TABLE new_course_plans RENAME TO course_plans;
+
+CREATE INDEX course_plans_template_id ON course_plans (template_id);
+
+CREATE TABLE new_terms (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  course_plan_id INTEGER NOT NULL,
+  name VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  deleted_at TEXT NULL,
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE
+);
+
+INSERT INTO new_terms (id, course_plan_id, name, created_at, deleted_at)
+SELECT id, course_plan_id, name, created_at, deleted_at FROM terms;
+
+DROP TABLE terms;
+
+ALTER TABLE new_terms RENAME TO terms;
+
+CREATE TABLE new_term_courses (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  term_id INTEGER NOT NULL,
+  name VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  deleted_at TEXT NULL,
+  FOREIGN KEY (term_id) REFERENCES terms (id) ON DELETE CASCADE
+);
+
+INSERT INTO new_term_courses (id, term_id, name, created_at, deleted_at)
+SELECT id, term_id, name, created_at, deleted_at FROM term_courses;
+
+DROP TABLE term_courses;
+
+ALTER TABLE new_term_courses RENAME TO term_courses;
+
+CREATE TABLE new_plan_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  course_plan_id INTEGER NOT NULL,
+  user_id INTEGER NOT NULL,
+  kind VARCHAR NOT NULL,
+  before TEXT NULL,
+  after TEXT NULL,
+  undone_at TEXT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+INSERT INTO new_plan_events (id, course_plan_id, user_id, kind, before, after, undone_at, created_at)
+SELECT id, course_plan_id, user_id, kind, before, after, undone_at, created_at FROM plan_events;
+
+DROP TABLE plan_events;
+
+ALTER TABLE new_plan_events RENAME TO plan_events;
+
+CREATE TABLE new_share_links (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  course_plan_id INTEGER NOT NULL,
+  token VARCHAR NOT NULL UNIQUE,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  expires_at TEXT NULL,
+  revoked_at TEXT NULL,
+  FOREIGN KEY (course_plan_id) REFERENCES course_plans (id) ON DELETE CASCADE
+);
+
+INSERT INTO new_share_links (id, course_plan_id, token, created_at, expires_at, revoked_at)
+SELECT id, course_plan_id, token, created_at, expires_at, revoked_at FROM share_links;
+
+DROP TABLE share_links;
+
+ALTER TABLE new_share_links RENAME TO share_links;
+
+CREATE TABLE new_sessions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  jti VARCHAR NOT NULL UNIQUE,
+  refresh_token_hash VARCHAR NOT NULL UNIQUE,
+  previous_refresh_token_hash VARCHAR NULL,
+  user_agent VARCHAR NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  last_used_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  expires_at TEXT NOT NULL,
+  revoked_at TEXT NULL,
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+INSERT INTO new_sessions (id, user_id, jti, refresh_token_hash, previous_refresh_token_hash, user_agent, created_at, last_used_at, expires_at, revoked_at)
+SELECT id, user_id, jti, refresh_token_hash, previous_refresh_token_hash, user_agent, created_at, last_used_at, expires_at, revoked_at FROM sessions;
+
+DROP TABLE sessions;
+
+ALTER TABLE new_sessions RENAME TO sessions;
+
+CREATE INDEX sessions_previous_refresh_token_hash ON sessions (previous_refresh_token_hash);
+
+CREATE TABLE new_identities (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  provider VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+  CONSTRAINT unique_identity_provider_subject UNIQUE (provider, subject)
+);
+
+INSERT INTO new_identities (id, user_id, provider, subject, email, created_at)
+SELECT id, user_id, provider, subject, email, created_at FROM identities;
+
+DROP TABLE identities;
+
+ALTER TABLE new_identities RENAME TO identities;
+
+CREATE INDEX identities_user_id ON identities (user_id);
+
+-- SQLite does not index foreign keys automatically. Without these, every cascading delete (and
+-- every lookup of the records that belong to something) scans the whole table.
+CREATE INDEX course_plans_user_id ON course_plans (user_id);
+CREATE INDEX terms_course_plan_id ON terms (course_plan_id);
+CREATE INDEX term_courses_term_id ON term_courses (term_id);
+CREATE INDEX plan_events_course_plan_id ON plan_events (course_plan_id);
+CREATE INDEX share_links_course_plan_id ON share_links (course_plan_id);
+CREATE INDEX sessions_user_id ON sessions (user_id);
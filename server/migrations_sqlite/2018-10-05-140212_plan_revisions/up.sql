@@ -0,0 +1,4 @@
+-- Incremented by every change to the course plan so that clients can tell when their copy of it
+-- is out of date
+ALTER TABLE course_plans
+ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
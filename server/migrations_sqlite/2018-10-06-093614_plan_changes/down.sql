@@ -0,0 +1,4 @@
+DROP TABLE plan_changes;
+
+ALTER TABLE terms
+DROP COLUMN position;
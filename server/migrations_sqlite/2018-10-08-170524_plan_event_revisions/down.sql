@@ -0,0 +1,2 @@
+ALTER TABLE plan_events
+DROP COLUMN revision;
@@ -0,0 +1,5 @@
+-- The revision of the course plan that each event is part of, so that every event of a single
+-- change (e.g. a batch of edits or switching templates) can be undone together. Events recorded
+-- before this are NULL and are undone one at a time.
+ALTER TABLE plan_events
+ADD COLUMN revision INTEGER NULL;
@@ -0,0 +1,249 @@
+//! Logging in with the identities verified by authentication providers.
+//!
+//! Every provider (see api::providers) ends up here, so the rules for linking an identity to a user
+//! account are enforced in exactly one place. In order of precedence:
+//!
+//!   1. If the identity (provider + subject) is already linked to a user, that user is logged in.
+//!      The email of the identity does not matter at all and the email of the user is never
+//!      updated, so changing your email with a provider does not change which account you get.
+//!   2. An identity whose email has not been verified by its provider is never linked to an
+//!      existing user or used to create a new one. Otherwise anyone could take over (or reserve)
+//!      an account by setting its email on a provider that does not check it.
+//!   3. If a user with the same email exists and does not already have an identity with the same
+//!      provider, the identity is linked to that user. This is how logging in with a second
+//!      provider joins the same account.
+//!   4. If that user already has a different identity with the same provider, login fails. A user
+//!      has at most one identity per provider.
+//!   5. Otherwise, a new user is created and the identity is linked to it.
+//!
+//! Whichever rule applies, logging in to an account that has been disabled by an admin fails.
+//!
+//! ## Edge Case
+//!
+//! 1. User logs in with GitHub, subject: 123, email: a@b
+//! 2. Same user logs in with Google, subject: abc, email: b@c (alternate email)
+//! 3. User changes their GitHub email to b@c
+//! 4. Logging in with GitHub still returns the account created in 1 because of rule 1, even though
+//!    the account created in 2 now has the same email.
+
+use std::fmt;
+
+use diesel::Connection;
+use diesel::result::Error as QueryError;
+
+use api::db::DbConnection;
+use models::{users::{self, User}, identities::{self, Identity}};
+
+/// The identity of someone logging in, as verified by an authentication provider
+#[derive(Debug, Clone)]
+pub struct VerifiedIdentity {
+    /// The name of the provider (e.g. "google")
+    pub provider: &'static str,
+    /// The unique ID of the account with the provider
+    pub subject: String,
+    pub email: String,
+    /// True if the provider has verified that the person owns the email
+    pub email_verified: bool,
+    /// The name used for a new user created from this identity
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub enum LoginError {
+    /// The identity is not linked to any user and its email has not been verified
+    UnverifiedEmail,
+    /// The user with the same email already has a different identity with the same provider
+    ProviderAlreadyLinked,
+    /// The account has been disabled by an admin
+    Disabled,
+    Query(QueryError),
+}
+
+impl From<QueryError> for LoginError {
+    fn from(err: QueryError) -> Self {
+        LoginError::Query(err)
+    }
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::UnverifiedEmail => write!(f, "The email of this account has not been verified"),
+            LoginError::ProviderAlreadyLinked => write!(f, "The account with this email is already linked to a different account with the same provider"),
+            LoginError::Disabled => write!(f, "This account has been disabled"),
+            LoginError::Query(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// What happens to a user when logging in with an identity
+#[derive(Debug, PartialEq, Eq)]
+enum LoginAction {
+    /// The identity is already linked to the user with this ID
+    Existing(i32),
+    /// Link the identity to the user with this ID
+    Link(i32),
+    /// Create a new user for the identity
+    Create,
+}
+
+/// Applies the linking rules (see the module documentation) given the identity being used to log
+/// in, the identity already stored for it (if any), and the user with the same email along with
+/// their identities (if any)
+fn decide(
+    identity: &VerifiedIdentity,
+    linked: Option<&Identity>,
+    email_user: Option<(&User, &[Identity])>,
+) -> Result<LoginAction, LoginError> {
+    if let Some(linked) = linked {
+        return Ok(LoginAction::Existing(linked.user_id));
+    }
+
+    if !identity.email_verified {
+        return Err(LoginError::UnverifiedEmail);
+    }
+
+    match email_user {
+        Some((_, user_identities)) if user_identities.iter().any(|i| i.provider == identity.provider) => {
+            Err(LoginError::ProviderAlreadyLinked)
+        },
+        Some((user, _)) => Ok(LoginAction::Link(user.id)),
+        None => Ok(LoginAction::Create),
+    }
+}
+
+/// Finds, links or creates the user for the given identity
+pub fn login(conn: &DbConnection, identity: &VerifiedIdentity) -> Result<User, LoginError> {
+    conn.transaction(|| {
+        let linked = identities::find(conn, identity.provider, &identity.subject)?;
+        let email_user = match linked {
+            Some(_) => None,
+            None => users::get_by_email(conn, &identity.email)?,
+        };
+        let email_user_identities = match email_user {
+            Some(ref user) => identities::all(conn, user)?,
+            None => Vec::new(),
+        };
+
+        let action = decide(
+            identity,
+            linked.as_ref(),
+            email_user.as_ref().map(|user| (user, &email_user_identities[..])),
+        )?;
+        let user = match action {
+            LoginAction::Existing(user_id) | LoginAction::Link(user_id) => users::get(conn, user_id)?,
+            LoginAction::Create => users::create(conn, &identity.name, &identity.email)?,
+        };
+        if user.disabled_at.is_some() {
+            return Err(LoginError::Disabled);
+        }
+
+        match linked {
+            Some(ref linked) if linked.email != identity.email => {
+                identities::update_email(conn, linked.id, &identity.email)?;
+            },
+            Some(_) => {},
+            None => {
+                identities::create(conn, &user, identity.provider, &identity.subject, &identity.email)?;
+            },
+        }
+
+        Ok(user)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    fn verified(provider: &'static str, subject: &str, email: &str) -> VerifiedIdentity {
+        VerifiedIdentity {
+            provider,
+            subject: subject.to_string(),
+            email: email.to_string(),
+            email_verified: true,
+            name: "Test User".to_string(),
+        }
+    }
+
+    fn user(id: i32, email: &str) -> User {
+        User {
+            id,
+            name: "Test User".to_string(),
+            email: email.to_string(),
+            created_at: Utc::now(),
+            is_admin: false,
+            disabled_at: None,
+        }
+    }
+
+    fn identity(user_id: i32, provider: &str, subject: &str, email: &str) -> Identity {
+        Identity {
+            id: 1,
+            user_id,
+            provider: provider.to_string(),
+            subject: subject.to_string(),
+            email: email.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn creates_user_for_new_identity() {
+        let action = decide(&verified("github", "123", "a@b.c"), None, None).unwrap();
+        assert_eq!(action, LoginAction::Create);
+    }
+
+    #[test]
+    fn linked_identity_wins_over_email() {
+        // The identity is linked to user 1 but user 2 now has the same email (see Edge Case)
+        let linked = identity(1, "github", "123", "a@b.c");
+        let other = user(2, "b@c.d");
+        let action = decide(&verified("github", "123", "b@c.d"), Some(&linked), Some((&other, &[]))).unwrap();
+        assert_eq!(action, LoginAction::Existing(1));
+    }
+
+    #[test]
+    fn linked_identity_does_not_need_verified_email() {
+        let linked = identity(1, "github", "123", "a@b.c");
+        let unverified = VerifiedIdentity {email_verified: false, ..verified("github", "123", "a@b.c")};
+        let action = decide(&unverified, Some(&linked), None).unwrap();
+        assert_eq!(action, LoginAction::Existing(1));
+    }
+
+    #[test]
+    fn links_verified_email_to_existing_user() {
+        let existing = user(1, "a@b.c");
+        let existing_identities = [identity(1, "google", "abc", "a@b.c")];
+        let action = decide(&verified("github", "123", "a@b.c"), None, Some((&existing, &existing_identities))).unwrap();
+        assert_eq!(action, LoginAction::Link(1));
+    }
+
+    #[test]
+    fn rejects_unverified_email() {
+        let unverified = VerifiedIdentity {email_verified: false, ..verified("github", "123", "a@b.c")};
+        let existing = user(1, "a@b.c");
+        match decide(&unverified, None, Some((&existing, &[]))) {
+            Err(LoginError::UnverifiedEmail) => {},
+            res => panic!("expected unverified email, got: {:?}", res),
+        }
+        // Not even a new user can be created, otherwise the email would be reserved by someone
+        // who may not own it
+        match decide(&unverified, None, None) {
+            Err(LoginError::UnverifiedEmail) => {},
+            res => panic!("expected unverified email, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_second_identity_with_same_provider() {
+        let existing = user(1, "a@b.c");
+        let existing_identities = [identity(1, "google", "abc", "a@b.c")];
+        match decide(&verified("google", "def", "a@b.c"), None, Some((&existing, &existing_identities))) {
+            Err(LoginError::ProviderAlreadyLinked) => {},
+            res => panic!("expected provider already linked, got: {:?}", res),
+        }
+    }
+}
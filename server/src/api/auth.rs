@@ -8,35 +8,82 @@ use rocket::{
 use rocket_contrib::Json;
 use chrono::{DateTime, Utc, Duration};
 use jwt::{self, Header, Validation};
+use ring::digest;
 
-use api::db;
-use models::users;
+use api::db::{self, DbConnection};
+use api::providers::{Providers, ProviderError};
+use api::rate_limit::AuthRateLimit;
+use accounts::{self, LoginError};
+use logging;
+use models::{users, sessions};
+use random;
 
 #[derive(Debug, Deserialize)]
 pub struct Auth {
-    name: String,
-    email: String,
-    #[serde(rename = "googleId")]
-    google_id: String,
+    /// The ID token returned by Google Sign-In. The identity of the user is only ever taken from
+    /// the verified claims of this token, never from anything else the client sends.
+    #[serde(rename = "idToken")]
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProviderAuth {
+    /// The credential issued to the client by the provider (e.g. a GitHub authorization code)
+    credential: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Refresh {
+    #[serde(rename = "refreshToken")]
+    refresh_token: String,
 }
 
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
-    token: String,
+    /// The short-lived access token used in the Authorization header
+    pub token: String,
+    /// The expiry date of the access token
+    #[serde(rename = "expiresAt")]
+    expires_at: DateTime<Utc>,
+    /// Used once to get a new access token (and a new refresh token) from /refresh
+    #[serde(rename = "refreshToken")]
+    refresh_token: String,
 }
 
 #[derive(Debug)]
 pub struct SecretKey(pub String);
 
+/// How long tokens are valid for. Access tokens are short-lived and clients use their refresh
+/// token to get a new access token when it expires.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenLifetimes {
+    pub access: Duration,
+    /// How long a session lasts without being refreshed
+    pub refresh: Duration,
+}
+
 /// Represents the current session of a logged in user
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Session {
     /// The user ID of the currently logged in user
     pub user_id: i32,
-    /// The expiry date of this session
+    /// The JWT ID of the session stored in the database that this token was issued for
+    pub jti: String,
+    /// The expiry date of this token
     pub expiry: DateTime<Utc>,
 }
 
+/// The User-Agent header of the request (if any), used to describe a session to its user
+pub struct UserAgent(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(UserAgent(request.headers().get_one("User-Agent").map(String::from)))
+    }
+}
+
 // Defines that Session can be used as a request guard
 impl<'a, 'r> FromRequest<'a, 'r> for Session {
     type Error = &'static str;
@@ -56,6 +103,10 @@ impl<'a, 'r> FromRequest<'a, 'r> for Session {
     // If none of these error cases is encountered, the session is successfully decoded and
     // returned. Doing this as a request guard encapsulates all this logic in one place and makes
     // creating methods that require authentication as easy as using Session as a parameter.
+    //
+    // This guard briefly takes a database connection of its own, so routes should list Session
+    // before their own db::Connection. Otherwise a pool with a single connection (as used in
+    // tests) would never have a connection available for it.
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
         let SecretKey(ref secret_key) = *request.guard::<State<SecretKey>>()
             .map_failure(|_| (Status::InternalServerError, "Application not configured correctly"))?;
@@ -87,36 +138,219 @@ impl<'a, 'r> FromRequest<'a, 'r> for Session {
             return Outcome::Failure((Status::Forbidden, "Invalid/expired token"));
         }
 
+        // The session must still be active in the database so that logging out takes effect
+        // immediately, even though the token itself has not expired yet
+        let conn = request.guard::<db::Connection>()
+            .map_failure(|_| (Status::ServiceUnavailable, "Unable to connect to the database"))?;
+        match sessions::get_active(&conn, &session.jti) {
+            Ok(Some(active)) => if sessions::touch(&conn, &active).is_err() {
+                return Outcome::Failure((Status::InternalServerError, "Unable to update session"));
+            },
+            Ok(None) => return Outcome::Failure((Status::Forbidden, "Invalid/expired token")),
+            Err(_) => return Outcome::Failure((Status::InternalServerError, "Unable to check session")),
+        }
+
         Outcome::Success(session)
     }
 }
 
+/// The session of a logged in user with the admin role. Using this as a request guard restricts
+/// a route to admins.
+#[derive(Debug)]
+pub struct AdminSession {
+    pub session: Session,
+    pub user: users::User,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminSession {
+    type Error = &'static str;
+
+    // Fails with the same errors as Session, or 403 Forbidden if the user is not an admin
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let session = request.guard::<Session>()?;
+        let conn = request.guard::<db::Connection>()
+            .map_failure(|_| (Status::ServiceUnavailable, "Unable to connect to the database"))?;
+        let user = match users::get(&conn, session.user_id) {
+            Ok(user) => user,
+            Err(_) => return Outcome::Failure((Status::Forbidden, "Invalid/expired token")),
+        };
+
+        if !user.is_admin {
+            return Outcome::Failure((Status::Forbidden, "Only admins can access this"));
+        }
+
+        Outcome::Success(AdminSession {session, user})
+    }
+}
+
+/// The user ID in the access token of the request, if it has a validly signed one. Unlike the
+/// Session guard, this does not check whether the session is still active, so it must only be
+/// used for things like logging and never to decide what a request is allowed to do.
+pub fn token_user_id(request: &Request) -> Option<i32> {
+    let secret_key = request.guard::<State<SecretKey>>().succeeded()?;
+    let SecretKey(ref secret_key) = *secret_key;
+    let token = request.headers().get_one("Authorization")?.trim().split(' ').nth(1)?;
+    jwt::decode::<Session>(token, secret_key.as_ref(), &Validation::default()).ok()
+        .map(|data| data.claims.user_id)
+}
+
+/// Hashes a refresh token so that refresh tokens never need to be stored
+fn hash_token(token: &str) -> String {
+    digest::digest(&digest::SHA256, token.as_bytes()).as_ref().iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Creates a new access token for the given session
+fn issue_tokens(
+    secret_key: &SecretKey,
+    lifetimes: &TokenLifetimes,
+    session: &sessions::Session,
+    refresh_token: String,
+) -> Result<AuthResponse, Failure> {
+    let SecretKey(ref secret_key) = *secret_key;
+    let expires_at = Utc::now() + lifetimes.access;
+    let claims = Session {
+        user_id: session.user_id,
+        jti: session.jti.clone(),
+        expiry: expires_at,
+    };
+    let token = jwt::encode(&Header::default(), &claims, secret_key.as_ref())
+        .map_err(|_| Failure(Status::InternalServerError))?;
+
+    Ok(AuthResponse {
+        token,
+        expires_at,
+        refresh_token,
+    })
+}
+
+/// Starts a new session for the given user and returns its tokens
+pub fn start_session(
+    conn: &DbConnection,
+    secret_key: &SecretKey,
+    lifetimes: &TokenLifetimes,
+    user: &users::User,
+    UserAgent(user_agent): UserAgent,
+) -> Result<AuthResponse, Failure> {
+    let jti = random::token().map_err(|_| Failure(Status::InternalServerError))?;
+    let refresh_token = random::token().map_err(|_| Failure(Status::InternalServerError))?;
+    let expires_at = Utc::now() + lifetimes.refresh;
+    let session = sessions::create(conn, user, jti, hash_token(&refresh_token), user_agent, expires_at)
+        .map_err(|_| Failure(Status::InternalServerError))?;
+
+    issue_tokens(secret_key, lifetimes, &session, refresh_token)
+}
+
+/// Logs in with the given provider, creating or linking the user's account as needed
+fn login(
+    conn: &db::Connection,
+    secret_key: &SecretKey,
+    lifetimes: &TokenLifetimes,
+    providers: &Providers,
+    provider: &str,
+    credential: &str,
+    user_agent: UserAgent,
+) -> Result<AuthResponse, Failure> {
+    let provider = providers.get(provider).ok_or(Failure(Status::NotFound))?;
+    let identity = provider.authenticate(credential).map_err(|err| match err {
+        ProviderError::Unavailable(err) => {
+            logging::warn("Unable to log in with provider")
+                .field("provider", provider.name())
+                .field("error", err.to_string())
+                .log();
+            Failure(Status::ServiceUnavailable)
+        },
+        ProviderError::InvalidCredential(_) | ProviderError::EmailNotVerified => Failure(Status::Unauthorized),
+    })?;
+    let user = accounts::login(conn, &identity).map_err(|err| match err {
+        LoginError::UnverifiedEmail => Failure(Status::Forbidden),
+        LoginError::ProviderAlreadyLinked => Failure(Status::Conflict),
+        LoginError::Disabled => Failure(Status::Forbidden),
+        LoginError::Query(_) => Failure(Status::InternalServerError),
+    })?;
+
+    start_session(conn, secret_key, lifetimes, &user, user_agent)
+}
+
 #[post("/google_auth", data = "<auth>")]
 pub fn google_auth(
+    _rate_limit: AuthRateLimit,
     conn: db::Connection,
     secret_key: State<SecretKey>,
+    lifetimes: State<TokenLifetimes>,
+    providers: State<Providers>,
+    user_agent: UserAgent,
     auth: Json<Auth>,
 ) -> Result<Json<AuthResponse>, Failure> {
-    let user = users::get_or_create(&conn, &auth.name, &auth.email, &auth.google_id)
-        .map_err(|_| Failure(Status::InternalServerError))?;
+    Ok(Json(login(&conn, &secret_key, &lifetimes, &providers, "google", &auth.id_token, user_agent)?))
+}
 
-    let SecretKey(ref secret_key) = *secret_key;
-    let session = Session {
-        user_id: user.id,
-        expiry: Utc::now() + Duration::days(30),
+/// Logs in with any of the configured providers (e.g. /auth/github)
+#[post("/auth/<provider>", data = "<auth>")]
+pub fn provider_auth(
+    _rate_limit: AuthRateLimit,
+    conn: db::Connection,
+    secret_key: State<SecretKey>,
+    lifetimes: State<TokenLifetimes>,
+    providers: State<Providers>,
+    user_agent: UserAgent,
+    provider: String,
+    auth: Json<ProviderAuth>,
+) -> Result<Json<AuthResponse>, Failure> {
+    Ok(Json(login(&conn, &secret_key, &lifetimes, &providers, &provider, &auth.credential, user_agent)?))
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token. Each refresh token
+/// can only be used once.
+#[post("/refresh", data = "<refresh>")]
+pub fn refresh(
+    _rate_limit: AuthRateLimit,
+    conn: db::Connection,
+    secret_key: State<SecretKey>,
+    lifetimes: State<TokenLifetimes>,
+    refresh: Json<Refresh>,
+) -> Result<Json<AuthResponse>, Failure> {
+    let token_hash = hash_token(&refresh.refresh_token);
+    let session = match sessions::get_by_refresh_token(&conn, &token_hash) {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            // A refresh token that was already replaced has been used again. Either the client or
+            // someone else has an old copy of it, so the session can no longer be trusted.
+            if let Ok(Some(session)) = sessions::get_by_previous_refresh_token(&conn, &token_hash) {
+                logging::warn("Refresh token reused, revoking session")
+                    .field("user_id", session.user_id)
+                    .field("session_id", session.id)
+                    .log();
+                sessions::revoke(&conn, session.id)
+                    .map_err(|_| Failure(Status::InternalServerError))?;
+            }
+            return Err(Failure(Status::Unauthorized));
+        },
+        Err(_) => return Err(Failure(Status::InternalServerError)),
     };
-    let token = jwt::encode(&Header::default(), &session, secret_key.as_ref())
-        .map_err(|_| Failure(Status::InternalServerError))?;
 
-    Ok(Json(AuthResponse {
-        token,
-    }))
+    if session.revoked_at.is_some() || session.expires_at < Utc::now() {
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    let refresh_token = random::token().map_err(|_| Failure(Status::InternalServerError))?;
+    let expires_at = Utc::now() + lifetimes.refresh;
+    let session = sessions::rotate(&conn, &session, hash_token(&refresh_token), expires_at)
+        .map_err(|_| Failure(Status::InternalServerError))?
+        // Another request already used this refresh token
+        .ok_or(Failure(Status::Unauthorized))?;
+
+    Ok(Json(issue_tokens(&secret_key, &lifetimes, &session, refresh_token)?))
 }
 
+/// Revokes the current session so that its tokens can no longer be used
 #[post("/logout")]
-pub fn logout(_session: Session) {
-    // Does nothing for now except getting the current session to make sure this route is only
-    // called if the user is logged in. This route could one day be used to invalidate sessions.
-    // TODO: Currently, since sessions are not invalided, tokens are actually valid until they
-    // expire and logout does not do anything if the token is still stored.
+pub fn logout(session: Session, conn: db::Connection) -> Result<(), Failure> {
+    if let Some(active) = sessions::get_active(&conn, &session.jti)
+        .map_err(|_| Failure(Status::InternalServerError))? {
+        sessions::revoke(&conn, active.id)
+            .map_err(|_| Failure(Status::InternalServerError))?;
+    }
+    Ok(())
 }
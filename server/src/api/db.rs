@@ -1,5 +1,5 @@
 //! A shared database connection pool that allows sharing database connections across
-//! rocket routes. The default PgConnection is not thread-safe.
+//! rocket routes. The default database connection is not thread-safe.
 //!
 //! This file is adapted from the following:
 //! https://github.com/ghotiphud/rust-web-starter/blob/master/api_server/src/pg_pool.rs
@@ -9,23 +9,148 @@ use std::ops::Deref;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest};
 use rocket::{Request, State, Outcome};
-use diesel::pg::PgConnection;
+use diesel;
+use diesel::prelude::*;
+use diesel::query_builder::InsertStatement;
+use diesel::query_dsl::LoadQuery;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+#[cfg(feature = "sqlite")]
+use diesel::dsl::{Find, Limit};
+#[cfg(feature = "sqlite")]
+use diesel::query_builder::{QueryFragment, QueryId};
+#[cfg(feature = "sqlite")]
+use diesel::query_dsl::methods::{FindDsl, LimitDsl};
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::Sqlite;
 
-/// The connection type being abstracted
-type ConnectionType = PgConnection;
+#[cfg(all(feature = "postgres", feature = "sqlite"))]
+compile_error!("Only one of the postgres and sqlite features can be enabled");
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("Either the postgres or the sqlite feature must be enabled");
+
+/// The connection type of the database backend selected by the cargo features. Code outside of
+/// this module should always use this instead of naming a particular backend.
+#[cfg(feature = "postgres")]
+pub type DbConnection = ::diesel::pg::PgConnection;
+#[cfg(feature = "sqlite")]
+pub type DbConnection = ::diesel::sqlite::SqliteConnection;
 
 /// A pool of database connections
-pub type DBPool = Pool<ConnectionManager<ConnectionType>>;
+pub type DBPool = Pool<ConnectionManager<DbConnection>>;
+
+/// Create a new database pool with up to the given number of connections
+pub fn connect(database_url: &str, pool_size: u32) -> DBPool {
+    let manager = ConnectionManager::<DbConnection>::new(database_url);
+    Pool::builder()
+        .max_size(pool_size)
+        .connection_customizer(Box::new(Configure))
+        .build(manager)
+        .expect("Failed to create database pool")
+}
+
+/// Create a pool with a single connection that never commits anything, for tests. Everything
+/// done through the pool happens in one transaction that is rolled back when the pool is dropped,
+/// so each test sees only its own data.
+#[cfg(test)]
+pub fn connect_test(database_url: &str) -> DBPool {
+    let manager = ConnectionManager::<DbConnection>::new(database_url);
+    Pool::builder()
+        .max_size(1)
+        // The connection must never be replaced, or the data from the transaction would be lost
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connection_customizer(Box::new(TestTransaction))
+        .build(manager)
+        .expect("Failed to create test database pool")
+}
+
+/// Applies the settings that have to be set on every connection rather than once for the database
+#[cfg(feature = "postgres")]
+fn configure(_conn: &DbConnection) -> QueryResult<()> {
+    Ok(())
+}
+
+/// Applies the settings that have to be set on every connection rather than once for the database
+#[cfg(feature = "sqlite")]
+fn configure(conn: &DbConnection) -> QueryResult<()> {
+    // Foreign keys (and so ON DELETE CASCADE) are only enforced if this is enabled. This has no
+    // effect inside of a transaction, so it must happen before anything else.
+    conn.execute("PRAGMA foreign_keys = ON")?;
+    // Wait for other connections to finish writing instead of failing right away
+    conn.execute("PRAGMA busy_timeout = 5000")?;
+    Ok(())
+}
 
-/// Create a new database pool
-pub fn connect(database_url: &str) -> DBPool {
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    Pool::new(manager).expect("Failed to create database pool")
+/// Configures every new connection
+#[derive(Debug)]
+struct Configure;
+
+impl ::diesel::r2d2::CustomizeConnection<DbConnection, ::diesel::r2d2::Error> for Configure {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), ::diesel::r2d2::Error> {
+        configure(conn).map_err(::diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Configures every new connection and then starts a test transaction on it
+#[cfg(test)]
+#[derive(Debug)]
+struct TestTransaction;
+
+#[cfg(test)]
+impl ::diesel::r2d2::CustomizeConnection<DbConnection, ::diesel::r2d2::Error> for TestTransaction {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), ::diesel::r2d2::Error> {
+        configure(conn)
+            .and_then(|()| ::diesel::Connection::begin_test_transaction(conn))
+            .map_err(::diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Inserts a single record and returns it as it was stored, including the values filled in by the
+/// database (e.g. the ID and the creation date)
+#[cfg(feature = "postgres")]
+pub fn insert<T, V, U>(conn: &DbConnection, table: T, values: V) -> QueryResult<U>
+    where T: Table,
+          V: Insertable<T>,
+          InsertStatement<T, V::Values>: LoadQuery<DbConnection, U>,
+{
+    diesel::insert_into(table)
+        .values(values)
+        .get_result(conn)
+}
+
+#[cfg(feature = "sqlite")]
+no_arg_sql_function!(last_insert_rowid, ::diesel::sql_types::Integer);
+
+/// Inserts a single record and returns it as it was stored, including the values filled in by the
+/// database (e.g. the ID and the creation date)
+#[cfg(feature = "sqlite")]
+pub fn insert<T, V, U>(conn: &DbConnection, table: T, values: V) -> QueryResult<U>
+    where T: Table + Copy + FindDsl<i32>,
+          V: Insertable<T>,
+          InsertStatement<T, V::Values>: QueryFragment<Sqlite> + QueryId,
+          Find<T, i32>: LimitDsl + RunQueryDsl<DbConnection>,
+          Limit<Find<T, i32>>: LoadQuery<DbConnection, U>,
+{
+    // SQLite cannot return the inserted row (RETURNING), so it is loaded again using its ID
+    diesel::insert_into(table)
+        .values(values)
+        .execute(conn)?;
+    let id = diesel::select(last_insert_rowid)
+        .get_result::<i32>(conn)?;
+    table.find(id)
+        .first(conn)
 }
 
 /// Connection request guard type for rocket: a wrapper around the connection pool
-pub struct Connection(PooledConnection<ConnectionManager<ConnectionType>>);
+pub struct Connection(PooledConnection<ConnectionManager<DbConnection>>);
+
+impl Connection {
+    /// Wraps a connection taken directly from the pool, for tests that run outside of a request
+    #[cfg(test)]
+    pub fn new(conn: PooledConnection<ConnectionManager<DbConnection>>) -> Self {
+        Connection(conn)
+    }
+}
 
 impl<'a, 'r> FromRequest<'a, 'r> for Connection {
     type Error = ();
@@ -42,9 +167,9 @@ impl<'a, 'r> FromRequest<'a, 'r> for Connection {
     }
 }
 
-// For the convenience of using an &Connection as an &ConnectionType.
+// For the convenience of using an &Connection as an &DbConnection.
 impl Deref for Connection {
-    type Target = ConnectionType;
+    type Target = DbConnection;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -0,0 +1,169 @@
+//! Routes for downloading a course plan in formats that can be used outside of the application
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use rocket::{
+    Response,
+    State,
+    http::{Status, ContentType, RawStr},
+    request::FromFormValue,
+    response::Failure,
+};
+use chrono::Utc;
+use diesel::result::Error as QueryError;
+
+use api::db;
+use api::auth::Session;
+use models::{users, course_plans};
+use template::{Templates, TemplateError};
+use diff::{self, DiffError, Side};
+use export;
+use logging;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Markdown,
+}
+
+impl<'v> FromFormValue<'v> for ExportFormat {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "md" => Ok(ExportFormat::Markdown),
+            _ => Err(value),
+        }
+    }
+}
+
+#[derive(Debug, FromForm)]
+pub struct ExportParams {
+    format: ExportFormat,
+}
+
+/// Creates a response that the browser will download as a file with the given name
+fn attachment(content_type: ContentType, filename: String, body: Vec<u8>) -> Response<'static> {
+    Response::build()
+        .header(content_type)
+        .raw_header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+        .sized_body(Cursor::new(body))
+        .finalize()
+}
+
+/// Exports a course plan of the currently logged in user. The json format can be imported back
+/// into a course plan.
+#[get("/plans/<id>/export?<params>")]
+fn export_plan(
+    session: Session,
+    conn: db::Connection,
+    id: i32,
+    params: ExportParams,
+) -> Result<Response<'static>, Failure> {
+    let user = users::get(&conn, session.user_id)
+        .map_err(|_| Failure(Status::Forbidden))?;
+    let course_plan = course_plans::get(&conn, id, &user)
+        .map_err(|_| Failure(Status::NotFound))?;
+    let plan = export::template(&conn, &course_plan)
+        .map_err(|_| Failure(Status::InternalServerError))?;
+
+    let today = Utc::today().naive_utc();
+    let filename = format!("course-plan-{}", course_plan.id);
+    Ok(match params.format {
+        ExportFormat::Json => attachment(
+            ContentType::JSON,
+            filename + ".json",
+            export::to_json(&plan).map_err(|_| Failure(Status::InternalServerError))?,
+        ),
+        ExportFormat::Csv => attachment(
+            ContentType::new("text", "csv"),
+            filename + ".csv",
+            export::to_csv(&plan, today).map_err(|_| Failure(Status::InternalServerError))?,
+        ),
+        ExportFormat::Markdown => attachment(
+            ContentType::new("text", "markdown"),
+            filename + ".md",
+            export::to_markdown(&plan, today),
+        ),
+    })
+}
+
+/// What a course plan is compared against: `plan:<id>`, `template:<id>` or `source` for the
+/// template that the course plan was created from
+#[derive(Debug, Clone)]
+pub enum DiffTarget {
+    Source,
+    Side(Side),
+}
+
+impl<'v> FromFormValue<'v> for DiffTarget {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        let decoded = value.url_decode().map_err(|_| value)?;
+        if decoded == "source" {
+            return Ok(DiffTarget::Source);
+        }
+        if decoded.starts_with("plan:") {
+            return decoded["plan:".len()..].parse()
+                .map(|id| DiffTarget::Side(Side::CoursePlan(id)))
+                .map_err(|_| value);
+        }
+        if decoded.starts_with("template:") {
+            return Ok(DiffTarget::Side(Side::Template(decoded["template:".len()..].to_string())));
+        }
+        Err(value)
+    }
+}
+
+#[derive(Debug, FromForm)]
+pub struct DiffParams {
+    against: DiffTarget,
+}
+
+/// Logs an unexpected error while comparing course plans
+fn internal_error(error: String) -> Failure {
+    logging::error("Unable to compare course plans").field("error", error).log();
+    Failure(Status::InternalServerError)
+}
+
+/// Lists what changed in a course plan of the currently logged in user compared to another one of
+/// their course plans or a template, as Markdown with the differences in each term
+#[get("/plans/<id>/diff?<params>")]
+fn diff_plan(
+    session: Session,
+    conn: db::Connection,
+    templates: State<Arc<Templates>>,
+    id: i32,
+    params: DiffParams,
+) -> Result<Response<'static>, Failure> {
+    let user = users::get(&conn, session.user_id)
+        .map_err(|_| Failure(Status::Forbidden))?;
+    let left = match params.against {
+        DiffTarget::Side(side) => side,
+        DiffTarget::Source => diff::source_template(&conn, &user, id)
+            .map_err(|err| match err {
+                QueryError::NotFound => Failure(Status::NotFound),
+                err => internal_error(err.to_string()),
+            })?
+            .ok_or(Failure(Status::NotFound))?,
+    };
+    let right = Side::CoursePlan(id);
+
+    let load = |side: &Side| diff::load(&conn, &user, &templates, side).map_err(|err| match err {
+        DiffError::Query(QueryError::NotFound) | DiffError::Template(TemplateError::Unknown(_)) => Failure(Status::NotFound),
+        DiffError::Query(err) => internal_error(err.to_string()),
+        DiffError::Template(err) => internal_error(err.to_string()),
+    });
+    let plan_diff = diff::diff(&load(&left)?, &load(&right)?);
+
+    Ok(attachment(
+        ContentType::new("text", "markdown"),
+        format!("course-plan-{}-diff.md", id),
+        diff::to_markdown(&plan_diff, &left.to_string(), &right.to_string()),
+    ))
+}
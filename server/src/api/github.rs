@@ -0,0 +1,212 @@
+//! Logging in with GitHub using the OAuth web application flow.
+//!
+//! See: https://developer.github.com/apps/building-oauth-apps/authorizing-oauth-apps/
+//!
+//! The client redirects the user to GitHub, which redirects back to the client with an
+//! authorization code. The client sends that code here, where it is exchanged for an access token
+//! (using the client secret, which never leaves the server) that is then used to look up the user.
+
+use reqwest::{self, header};
+use serde::de::DeserializeOwned;
+
+use accounts::VerifiedIdentity;
+use super::providers::{AuthProvider, ProviderError};
+
+/// The URL that authorization codes are exchanged at
+const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+/// The base URL of the GitHub API
+const GITHUB_API_URL: &str = "https://api.github.com";
+/// GitHub rejects API requests without a User-Agent
+const USER_AGENT: &str = "uwcourseplan";
+
+/// The response when exchanging an authorization code. GitHub reports errors (e.g. an expired
+/// code) with a successful status code and an error field instead of the token.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: u64,
+    login: String,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// Verifies GitHub authorization codes issued for a particular OAuth application
+#[derive(Debug)]
+pub struct GitHubProvider {
+    client_id: String,
+    client_secret: String,
+    token_url: String,
+    api_url: String,
+    client: reqwest::Client,
+}
+
+impl GitHubProvider {
+    pub fn new(client_id: String, client_secret: String) -> Self {
+        Self::with_urls(client_id, client_secret, GITHUB_TOKEN_URL.to_string(), GITHUB_API_URL.to_string())
+    }
+
+    /// Uses a different server in place of GitHub (e.g. a mock server for testing)
+    pub fn with_urls(client_id: String, client_secret: String, token_url: String, api_url: String) -> Self {
+        GitHubProvider {
+            client_id,
+            client_secret,
+            token_url,
+            api_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn access_token(&self, code: &str) -> Result<String, ProviderError> {
+        let res: TokenResponse = self.client.post(&self.token_url)
+            .header(header::ACCEPT, "application/json")
+            .header(header::USER_AGENT, USER_AGENT)
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code", code),
+            ])
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json())
+            .map_err(unavailable)?;
+
+        match res {
+            TokenResponse {access_token: Some(token), ..} => Ok(token),
+            TokenResponse {error, error_description, ..} => Err(ProviderError::InvalidCredential(
+                error_description.or(error).unwrap_or_else(|| "no access token returned".to_string())
+            )),
+        }
+    }
+
+    fn api_get<T: DeserializeOwned>(&self, path: &str, access_token: &str) -> Result<T, ProviderError> {
+        self.client.get(&format!("{}{}", self.api_url, path))
+            .header(header::ACCEPT, "application/json")
+            .header(header::USER_AGENT, USER_AGENT)
+            .header(header::AUTHORIZATION, format!("token {}", access_token))
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json())
+            .map_err(unavailable)
+    }
+}
+
+fn unavailable(err: reqwest::Error) -> ProviderError {
+    ProviderError::Unavailable(err.to_string())
+}
+
+impl AuthProvider for GitHubProvider {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    /// The credential is the authorization code that GitHub redirected back to the client with
+    fn authenticate(&self, credential: &str) -> Result<VerifiedIdentity, ProviderError> {
+        let access_token = self.access_token(credential)?;
+        let user: GitHubUser = self.api_get("/user", &access_token)?;
+        // The email on the profile is optional and may be hidden, so the primary email is taken
+        // from the list of emails (requires the user:email scope)
+        let emails: Vec<GitHubEmail> = self.api_get("/user/emails", &access_token)?;
+        let email = emails.into_iter()
+            .find(|email| email.primary)
+            .ok_or(ProviderError::EmailNotVerified)?;
+
+        Ok(VerifiedIdentity {
+            provider: self.name(),
+            subject: user.id.to_string(),
+            name: user.name.unwrap_or(user.login),
+            email: email.email,
+            email_verified: email.verified,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use api::mock_oauth::{MockOAuthServer, MockResponse};
+
+    fn provider(server: &MockOAuthServer) -> GitHubProvider {
+        GitHubProvider::with_urls(
+            "test-client-id".to_string(),
+            "test-client-secret".to_string(),
+            format!("{}/login/oauth/access_token", server.url()),
+            server.url().to_string(),
+        )
+    }
+
+    fn github(emails: &'static str) -> MockOAuthServer {
+        MockOAuthServer::start(vec![
+            MockResponse::ok("POST", "/login/oauth/access_token", r#"{"access_token": "gho_test", "token_type": "bearer", "scope": "user:email"}"#),
+            MockResponse::ok("GET", "/user", r#"{"id": 583231, "login": "octocat", "name": "The Octocat"}"#),
+            MockResponse::ok("GET", "/user/emails", emails),
+        ])
+    }
+
+    #[test]
+    fn authenticates_with_code() {
+        let server = github(r#"[
+            {"email": "octocat@users.noreply.github.com", "primary": false, "verified": true},
+            {"email": "octocat@github.com", "primary": true, "verified": true}
+        ]"#);
+        let identity = provider(&server).authenticate("test-code").expect("valid code was rejected");
+        assert_eq!(identity.provider, "github");
+        assert_eq!(identity.subject, "583231");
+        assert_eq!(identity.email, "octocat@github.com");
+        assert!(identity.email_verified);
+        assert_eq!(identity.name, "The Octocat");
+
+        let requests = server.requests();
+        let token_request = requests.iter()
+            .find(|req| req.path == "/login/oauth/access_token")
+            .expect("code was not exchanged");
+        assert!(token_request.body.contains("code=test-code"));
+        assert!(token_request.body.contains("client_secret=test-client-secret"));
+        assert!(requests.iter().any(|req| req.path == "/user"
+            && req.headers.iter().any(|h| h.to_lowercase() == "authorization: token gho_test")));
+    }
+
+    #[test]
+    fn reports_unverified_primary_email() {
+        let server = github(r#"[{"email": "octocat@github.com", "primary": true, "verified": false}]"#);
+        let identity = provider(&server).authenticate("test-code").unwrap();
+        assert!(!identity.email_verified);
+    }
+
+    #[test]
+    fn rejects_invalid_code() {
+        // GitHub reports a bad code with a successful status code
+        let server = MockOAuthServer::start(vec![
+            MockResponse::ok("POST", "/login/oauth/access_token", r#"{"error": "bad_verification_code", "error_description": "The code passed is incorrect or expired."}"#),
+        ]);
+        match provider(&server).authenticate("expired-code") {
+            Err(ProviderError::InvalidCredential(_)) => {},
+            res => panic!("expected invalid credential, got: {:?}", res),
+        }
+        assert!(server.requests().iter().all(|req| req.path != "/user"));
+    }
+
+    #[test]
+    fn reports_api_errors_as_unavailable() {
+        let server = MockOAuthServer::start(vec![
+            MockResponse::ok("POST", "/login/oauth/access_token", r#"{"access_token": "gho_test"}"#),
+            MockResponse {status: 500, ..MockResponse::ok("GET", "/user", "{}")},
+        ]);
+        match provider(&server).authenticate("test-code") {
+            Err(ProviderError::Unavailable(_)) => {},
+            res => panic!("expected unavailable, got: {:?}", res),
+        }
+    }
+}
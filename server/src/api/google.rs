@@ -0,0 +1,435 @@
+//! Verification of the ID tokens that Google issues when a user signs in.
+//!
+//! See: https://developers.google.com/identity/sign-in/web/backend-auth
+//!
+//! ID tokens are JWTs signed with RS256 by one of the keys in Google's published key set (JWKS).
+//! The key set is cached until it expires according to the response headers. It can also be
+//! loaded from a local file instead (e.g. so tests can run against a stand-in key set).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64;
+use jwt::{self, Algorithm, Validation};
+use reqwest;
+use serde_json;
+
+/// The URL of the key set used by Google to sign ID tokens
+const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+/// Google uses either of these values for the iss claim
+const GOOGLE_ISSUERS: &[&str] = &["accounts.google.com", "https://accounts.google.com"];
+/// How long to cache the key set (in seconds) if the response does not say how long it is valid
+const DEFAULT_KEYS_MAX_AGE_SECS: u64 = 60 * 60;
+/// The minimum time between fetches of the key set (in seconds), even when a token uses an
+/// unknown key
+const MIN_REFETCH_INTERVAL_SECS: u64 = 60;
+/// Allowance for clock skew between Google and this server when checking expiry
+const LEEWAY_SECS: i64 = 60;
+
+/// The OAuth client ID of the application, used by clients to sign in with Google
+#[derive(Debug)]
+pub struct GoogleClientId(pub String);
+
+/// The claims of a Google ID token that are used by this application
+#[derive(Debug, Deserialize)]
+pub struct GoogleClaims {
+    pub iss: String,
+    pub aud: String,
+    /// The unique Google account ID of the user
+    pub sub: String,
+    pub email: String,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Only provided if the profile scope was requested
+    pub name: Option<String>,
+    pub exp: i64,
+}
+
+#[derive(Debug)]
+pub enum GoogleAuthError {
+    /// The token was malformed, had an invalid signature or had expired
+    InvalidToken(jwt::errors::Error),
+    /// The token was not signed by any of the keys in the key set
+    UnknownKey,
+    /// The token was not issued by Google
+    InvalidIssuer(String),
+    /// The token was issued for a different application
+    InvalidAudience(String),
+    /// Google has not verified that the user owns their email
+    EmailNotVerified,
+    /// The key set could not be loaded
+    KeySet(String),
+}
+
+impl fmt::Display for GoogleAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoogleAuthError::InvalidToken(err) => write!(f, "Invalid Google ID token: {}", err),
+            GoogleAuthError::UnknownKey => write!(f, "Google ID token signed with an unknown key"),
+            GoogleAuthError::InvalidIssuer(iss) => write!(f, "Google ID token issued by unexpected issuer: {}", iss),
+            GoogleAuthError::InvalidAudience(aud) => write!(f, "Google ID token issued for unexpected audience: {}", aud),
+            GoogleAuthError::EmailNotVerified => write!(f, "Google account email has not been verified"),
+            GoogleAuthError::KeySet(err) => write!(f, "Unable to load Google key set: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonWebKeySet {
+    keys: Vec<JsonWebKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonWebKey {
+    kid: String,
+    kty: String,
+    /// The modulus of the RSA public key (base64url encoded)
+    n: String,
+    /// The exponent of the RSA public key (base64url encoded)
+    e: String,
+}
+
+/// Where the key set is loaded from
+#[derive(Debug)]
+enum KeySource {
+    Url(String),
+    File(PathBuf),
+}
+
+/// Public keys (DER encoded) by key ID
+type Keys = HashMap<String, Vec<u8>>;
+
+#[derive(Debug)]
+struct CachedKeys {
+    keys: Keys,
+    fetched_at: Instant,
+    /// None if the keys never expire
+    expires_at: Option<Instant>,
+}
+
+/// Verifies Google ID tokens issued for a particular client ID
+#[derive(Debug)]
+pub struct GoogleVerifier {
+    client_id: String,
+    source: KeySource,
+    cache: Mutex<Option<CachedKeys>>,
+}
+
+impl GoogleVerifier {
+    /// Verifies tokens against the key set published by Google
+    pub fn new(client_id: String) -> Self {
+        Self::with_source(client_id, KeySource::Url(GOOGLE_JWKS_URL.to_string()))
+    }
+
+    /// Verifies tokens against the key set (JWKS) stored in the given file
+    pub fn from_file<P: Into<PathBuf>>(client_id: String, path: P) -> Self {
+        Self::with_source(client_id, KeySource::File(path.into()))
+    }
+
+    fn with_source(client_id: String, source: KeySource) -> Self {
+        GoogleVerifier {
+            client_id,
+            source,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Verifies the signature, issuer, audience and expiry of the given ID token. Only the claims
+    /// returned from this method should be trusted.
+    pub fn verify(&self, id_token: &str) -> Result<GoogleClaims, GoogleAuthError> {
+        let header = jwt::decode_header(id_token).map_err(GoogleAuthError::InvalidToken)?;
+        let kid = header.kid.ok_or(GoogleAuthError::UnknownKey)?;
+        let key = self.key(&kid)?;
+
+        let validation = Validation {
+            algorithms: vec![Algorithm::RS256],
+            leeway: LEEWAY_SECS,
+            validate_exp: true,
+            ..Validation::default()
+        };
+        let claims: GoogleClaims = jwt::decode(id_token, &key, &validation)
+            .map_err(GoogleAuthError::InvalidToken)?
+            .claims;
+
+        if !GOOGLE_ISSUERS.contains(&claims.iss.as_str()) {
+            return Err(GoogleAuthError::InvalidIssuer(claims.iss));
+        }
+        if claims.aud != self.client_id {
+            return Err(GoogleAuthError::InvalidAudience(claims.aud));
+        }
+        if !claims.email_verified {
+            return Err(GoogleAuthError::EmailNotVerified);
+        }
+
+        Ok(claims)
+    }
+
+    /// Finds the public key with the given key ID, refreshing the cached key set if it has
+    /// expired or if the key is unknown (Google rotates its keys regularly)
+    fn key(&self, kid: &str) -> Result<Vec<u8>, GoogleAuthError> {
+        let mut cache = self.cache.lock().map_err(|_| key_set_error("key cache poisoned"))?;
+        let now = Instant::now();
+        let needs_refresh = match *cache {
+            None => true,
+            Some(ref cached) => {
+                let expired = cached.expires_at.map_or(false, |expires_at| now >= expires_at);
+                let unknown = !cached.keys.contains_key(kid)
+                    && cached.expires_at.is_some()
+                    && now - cached.fetched_at >= Duration::from_secs(MIN_REFETCH_INTERVAL_SECS);
+                expired || unknown
+            },
+        };
+
+        if needs_refresh {
+            *cache = Some(self.load()?);
+        }
+
+        cache.as_ref()
+            .and_then(|cached| cached.keys.get(kid))
+            .cloned()
+            .ok_or(GoogleAuthError::UnknownKey)
+    }
+
+    fn load(&self) -> Result<CachedKeys, GoogleAuthError> {
+        match self.source {
+            KeySource::Url(ref url) => {
+                let mut res = reqwest::get(url).map_err(key_set_error)?;
+                let max_age = res.headers().get(reqwest::header::CACHE_CONTROL)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_max_age)
+                    .unwrap_or(Duration::from_secs(DEFAULT_KEYS_MAX_AGE_SECS));
+                let jwks: JsonWebKeySet = res.json().map_err(key_set_error)?;
+                let now = Instant::now();
+                Ok(CachedKeys {
+                    keys: decode_keys(jwks)?,
+                    fetched_at: now,
+                    expires_at: Some(now + max_age),
+                })
+            },
+            KeySource::File(ref path) => {
+                let file = File::open(path).map_err(key_set_error)?;
+                let jwks: JsonWebKeySet = serde_json::from_reader(file).map_err(key_set_error)?;
+                Ok(CachedKeys {
+                    keys: decode_keys(jwks)?,
+                    fetched_at: Instant::now(),
+                    expires_at: None,
+                })
+            },
+        }
+    }
+}
+
+fn key_set_error<E: fmt::Display>(err: E) -> GoogleAuthError {
+    GoogleAuthError::KeySet(err.to_string())
+}
+
+/// Extracts the max-age directive from a Cache-Control header value
+fn parse_max_age(cache_control: &str) -> Option<Duration> {
+    cache_control.split(',')
+        .filter_map(|directive| {
+            let mut parts = directive.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("max-age"), Some(secs)) => secs.trim().parse().ok(),
+                _ => None,
+            }
+        })
+        .next()
+        .map(Duration::from_secs)
+}
+
+/// Converts each RSA key in the key set into the DER encoded public key expected by jsonwebtoken
+fn decode_keys(jwks: JsonWebKeySet) -> Result<Keys, GoogleAuthError> {
+    let mut keys = HashMap::new();
+    for key in jwks.keys {
+        if key.kty != "RSA" {
+            continue;
+        }
+
+        let n = base64::decode_config(&key.n, base64::URL_SAFE_NO_PAD)
+            .map_err(key_set_error)?;
+        let e = base64::decode_config(&key.e, base64::URL_SAFE_NO_PAD)
+            .map_err(key_set_error)?;
+        keys.insert(key.kid, rsa_public_key_der(&n, &e));
+    }
+
+    Ok(keys)
+}
+
+/// Encodes an RSA public key as the DER encoding of the PKCS#1 RSAPublicKey structure:
+///
+///     RSAPublicKey ::= SEQUENCE {
+///         modulus           INTEGER,  -- n
+///         publicExponent    INTEGER   -- e
+///     }
+fn rsa_public_key_der(n: &[u8], e: &[u8]) -> Vec<u8> {
+    let mut body = der_integer(n);
+    body.extend(der_integer(e));
+
+    let mut der = vec![0x30];
+    der.extend(der_length(body.len()));
+    der.extend(body);
+    der
+}
+
+/// Encodes an unsigned big-endian integer as a DER INTEGER
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let mut value: Vec<u8> = bytes.iter().cloned().skip_while(|&b| b == 0).collect();
+    // DER integers are signed, so a leading zero is needed if the high bit is set
+    if value.first().map_or(true, |&b| b & 0x80 != 0) {
+        value.insert(0, 0);
+    }
+
+    let mut der = vec![0x02];
+    der.extend(der_length(value.len()));
+    der.extend(value);
+    der
+}
+
+fn der_length(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        return vec![len as u8];
+    }
+
+    let mut bytes = Vec::new();
+    let mut remaining = len;
+    while remaining > 0 {
+        bytes.insert(0, (remaining & 0xff) as u8);
+        remaining >>= 8;
+    }
+    bytes.insert(0, 0x80 | bytes.len() as u8);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Utc, Duration as ChronoDuration};
+    use jwt::Header;
+
+    const CLIENT_ID: &str = "test-client-id.apps.googleusercontent.com";
+    const TEST_KEY: &[u8] = include_bytes!("../../tests/fixtures/google_test_key.der");
+    const OTHER_KEY: &[u8] = include_bytes!("../../tests/fixtures/google_other_key.der");
+
+    #[derive(Debug, Serialize)]
+    struct TestClaims<'a> {
+        iss: &'a str,
+        aud: &'a str,
+        sub: &'a str,
+        email: &'a str,
+        email_verified: bool,
+        name: &'a str,
+        exp: i64,
+    }
+
+    impl<'a> Default for TestClaims<'a> {
+        fn default() -> Self {
+            TestClaims {
+                iss: "https://accounts.google.com",
+                aud: CLIENT_ID,
+                sub: "110169484474386276334",
+                email: "test@example.com",
+                email_verified: true,
+                name: "Test User",
+                exp: (Utc::now() + ChronoDuration::hours(1)).timestamp(),
+            }
+        }
+    }
+
+    fn verifier() -> GoogleVerifier {
+        GoogleVerifier::from_file(CLIENT_ID.to_string(),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/google_jwks.json"))
+    }
+
+    fn sign(claims: &TestClaims, kid: &str, key: &[u8]) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        jwt::encode(&header, claims, key).expect("unable to sign test token")
+    }
+
+    #[test]
+    fn accepts_valid_token() {
+        let token = sign(&TestClaims::default(), "test-key-1", TEST_KEY);
+        let claims = verifier().verify(&token).expect("valid token was rejected");
+        assert_eq!(claims.sub, "110169484474386276334");
+        assert_eq!(claims.email, "test@example.com");
+        assert_eq!(claims.name, Some("Test User".to_string()));
+    }
+
+    #[test]
+    fn accepts_issuer_without_scheme() {
+        let claims = TestClaims {iss: "accounts.google.com", ..TestClaims::default()};
+        let token = sign(&claims, "test-key-1", TEST_KEY);
+        assert!(verifier().verify(&token).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_signature() {
+        // Signed with a different key but claiming to be signed with a known key
+        let token = sign(&TestClaims::default(), "test-key-1", OTHER_KEY);
+        match verifier().verify(&token) {
+            Err(GoogleAuthError::InvalidToken(_)) => {},
+            res => panic!("expected invalid token, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_key() {
+        let token = sign(&TestClaims::default(), "test-key-2", OTHER_KEY);
+        match verifier().verify(&token) {
+            Err(GoogleAuthError::UnknownKey) => {},
+            res => panic!("expected unknown key, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        let claims = TestClaims {exp: (Utc::now() - ChronoDuration::hours(1)).timestamp(), ..TestClaims::default()};
+        let token = sign(&claims, "test-key-1", TEST_KEY);
+        match verifier().verify(&token) {
+            Err(GoogleAuthError::InvalidToken(_)) => {},
+            res => panic!("expected invalid token, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_issuer() {
+        let claims = TestClaims {iss: "https://evil.example.com", ..TestClaims::default()};
+        let token = sign(&claims, "test-key-1", TEST_KEY);
+        match verifier().verify(&token) {
+            Err(GoogleAuthError::InvalidIssuer(_)) => {},
+            res => panic!("expected invalid issuer, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_audience() {
+        let claims = TestClaims {aud: "some-other-app.apps.googleusercontent.com", ..TestClaims::default()};
+        let token = sign(&claims, "test-key-1", TEST_KEY);
+        match verifier().verify(&token) {
+            Err(GoogleAuthError::InvalidAudience(_)) => {},
+            res => panic!("expected invalid audience, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_unverified_email() {
+        let claims = TestClaims {email_verified: false, ..TestClaims::default()};
+        let token = sign(&claims, "test-key-1", TEST_KEY);
+        match verifier().verify(&token) {
+            Err(GoogleAuthError::EmailNotVerified) => {},
+            res => panic!("expected unverified email, got: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn parses_max_age() {
+        assert_eq!(parse_max_age("public, max-age=19954, must-revalidate, no-transform"),
+            Some(Duration::from_secs(19954)));
+        assert_eq!(parse_max_age("no-cache"), None);
+    }
+}
@@ -1,20 +1,63 @@
+use std::io::{ErrorKind, Read};
+use std::sync::Arc;
+use std::time::Instant;
+
 use rocket::{
+    Data,
+    Outcome,
+    Request,
     State,
+    data::{self, FromData},
     http::Status,
-    response::{content, Failure},
+    response::{content, status, Failure},
 };
-use juniper_rocket::{GraphQLRequest, GraphQLResponse};
+use juniper::{self, InputValue, GraphQLType, RootNode};
+use serde_json::{self, Value};
 
 use api::db;
+use api::metrics::Metrics;
+use api::rate_limit::ApiRateLimit;
 use api::auth::Session;
+use api::request_log::RequestId;
+#[cfg(not(debug_assertions))]
+use api::auth::AdminSession;
+use api::google::GoogleClientId;
+use logging;
+use query_cost::{self, QueryCost, QueryCostError, QueryLimits};
+use template::Templates;
 
 use models::users;
 
-/// Opens the graphiql interface, not available when application compiled with --release
-///TODO: Guard to only admin users and then make available even when compiled with --release
+/// Opens the sign in form for graphiql. A browser cannot send the Authorization header when it
+/// navigates to a page, so once the user signs in, the form loads /graphiql with the access token
+/// and replaces itself with it.
+#[get("/graphiql/login")]
+fn graphiql_login(google_client_id: State<GoogleClientId>) -> content::Html<String> {
+    let GoogleClientId(ref google_client_id) = *google_client_id;
+    // Only show the form for logging in as a test user if the dev provider is compiled in
+    let dev_login_display = if cfg!(any(debug_assertions, feature = "dev-login")) { "flex" } else { "none" };
+    content::Html(include_str!("graphiql_login.html")
+        .replace("{graphiql_url}", "/graphiql")
+        .replace("{google_client_id}", google_client_id)
+        .replace("{dev_login_display}", dev_login_display)
+    )
+}
+
+/// Opens the graphiql interface: for anyone in debug builds
 #[cfg(debug_assertions)]
 #[get("/graphiql")]
 fn graphiql() -> content::Html<String> {
+    graphiql_page()
+}
+
+/// Opens the graphiql interface: only for admins in release builds
+#[cfg(not(debug_assertions))]
+#[get("/graphiql")]
+fn graphiql(_admin: AdminSession) -> content::Html<String> {
+    graphiql_page()
+}
+
+fn graphiql_page() -> content::Html<String> {
     // GraphiQL source code was literally being returned as an HTML string. See this file:
     // https://github.com/graphql-rust/juniper/blob/50a9fa31b673a8de9c12c457787d4251ef0f4af6/juniper/src/http/graphiql.rs
     // In order to add auth support, we took that HTML, customized it as needed, and made our own
@@ -22,26 +65,157 @@ fn graphiql() -> content::Html<String> {
     // with Juniper. You should go update the versions of the cdn fetched JS as needed.
     content::Html(include_str!("graphiql.html")
         .replace("{graphql_url}", "/graphql")
-        .replace("{google_client_id}", "988574817320-upn9d65cbmqvnol3h7fgro1cd7lo4l9h.apps.googleusercontent.com")
+        .replace("{login_url}", "/graphiql/login")
     )
 }
-#[cfg(not(debug_assertions))]
-#[get("/graphiql")]
-fn graphiql() -> &'static str {
-    ""
-}
 
 #[get("/graphql")]
 fn get_graphql() -> Failure {
     Failure(Status::MethodNotAllowed)
 }
 
+/// The largest request body that is read, in bytes (the same as Rocket's own limit for JSON)
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// The body of a GraphQL request as sent by clients
+#[derive(Debug, Deserialize)]
+struct RawGraphQLRequest {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+/// A GraphQL request. This replaces the request type of juniper_rocket so that the name of the
+/// operation is available for metrics and the query can be measured before it is executed.
+pub struct GraphQLRequest {
+    /// The name of the operation, or "anonymous" if the client did not name it
+    operation: String,
+    cost: Result<QueryCost, QueryCostError>,
+    request: juniper::http::GraphQLRequest,
+}
+
+/// The body of a response to a request that was rejected without being executed, in the same
+/// format as the errors returned by juniper
+#[derive(Debug, Serialize)]
+struct Rejection {
+    errors: Vec<RejectionError>,
+}
+
+#[derive(Debug, Serialize)]
+struct RejectionError {
+    message: String,
+    extensions: RejectionExtensions,
+}
+
+#[derive(Debug, Serialize)]
+struct RejectionExtensions {
+    code: &'static str,
+}
+
+/// The JSON response to a GraphQL request
+pub type GraphQLResponse = status::Custom<content::Json<String>>;
+
+impl FromData for GraphQLRequest {
+    type Error = String;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let max_depth = match request.guard::<State<QueryLimits>>() {
+            Outcome::Success(limits) => limits.max_depth,
+            _ => return Outcome::Failure((Status::InternalServerError, "Query limits are not configured".to_string())),
+        };
+
+        // One byte more than the limit is read to find out if the body is too large
+        let mut body = String::new();
+        match data.open().take(MAX_BODY_SIZE + 1).read_to_string(&mut body) {
+            Ok(size) if size as u64 > MAX_BODY_SIZE => {
+                return Outcome::Failure((Status::PayloadTooLarge, "Request body is too large".to_string()));
+            },
+            Ok(_) => {},
+            Err(ref err) if err.kind() == ErrorKind::InvalidData => {
+                return Outcome::Failure((Status::BadRequest, err.to_string()));
+            },
+            Err(err) => return Outcome::Failure((Status::InternalServerError, err.to_string())),
+        }
+
+        match serde_json::from_str::<RawGraphQLRequest>(&body) {
+            Ok(RawGraphQLRequest {query, operation_name, variables}) => Outcome::Success(GraphQLRequest {
+                operation: operation_name.clone().unwrap_or_else(|| "anonymous".to_string()),
+                cost: query_cost::analyze(&query, max_depth),
+                request: juniper::http::GraphQLRequest::new(query, operation_name, variables),
+            }),
+            Err(err) => Outcome::Failure((Status::BadRequest, err.to_string())),
+        }
+    }
+}
+
+impl GraphQLRequest {
+    /// Executes the request unless it is too expensive, recording and logging how long it took and
+    /// the codes of any errors it returned
+    pub fn execute<Q, M>(
+        &self,
+        schema: &RootNode<Q, M>,
+        context: &::graphql::Context,
+        metrics: &Metrics,
+        limits: &QueryLimits,
+    ) -> Result<GraphQLResponse, Failure>
+        where Q: GraphQLType<Context=::graphql::Context>,
+              M: GraphQLType<Context=::graphql::Context>,
+    {
+        let start = Instant::now();
+        let rejection = match self.cost {
+            Ok(ref cost) => limits.check(cost).err().map(|message| (message, "QUERY_TOO_COMPLEX")),
+            Err(ref err @ QueryCostError::TooDeep(_)) => Some((err.to_string(), "QUERY_TOO_COMPLEX")),
+            Err(ref err) => Some((err.to_string(), "VALIDATION")),
+        };
+        let (status, body) = match rejection {
+            Some((message, code)) => {
+                let rejection = Rejection {
+                    errors: vec![RejectionError {message, extensions: RejectionExtensions {code}}],
+                };
+                (Status::BadRequest, serde_json::to_value(&rejection))
+            },
+            None => {
+                // juniper only fails the whole response if nothing was executed (e.g. the query is
+                // invalid or names an operation that it does not contain)
+                let response = self.request.execute(schema, context);
+                let status = if response.is_ok() { Status::Ok } else { Status::BadRequest };
+                (status, serde_json::to_value(&response))
+            },
+        };
+        let executed = status == Status::Ok;
+        let body = body.map_err(|_| Failure(Status::InternalServerError))?;
+
+        let error_codes: Vec<_> = body.get("errors")
+            .and_then(Value::as_array)
+            .map(|errors| errors.iter()
+                .map(|error| error.pointer("/extensions/code").and_then(Value::as_str).unwrap_or("UNKNOWN").to_string())
+                .collect())
+            .unwrap_or_default();
+        let elapsed = start.elapsed();
+        metrics.observe_operation(if executed { Some(self.operation.as_str()) } else { None }, elapsed, &error_codes);
+        logging::info("graphql")
+            .field("request_id", context.request_id.as_str())
+            .field("operation", self.operation.as_str())
+            .field("duration_ms", elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64)
+            .field("errors", error_codes)
+            .log();
+
+        Ok(status::Custom(status, content::Json(body.to_string())))
+    }
+}
+
 /// In GraphQL, everything is sent to a single endpoint via POST requests
 #[post("/graphql", format = "application/json", data = "<request>")]
 fn graphql(
+    _rate_limit: ApiRateLimit,
+    session: Session,
     conn: db::Connection,
     schema: State<::graphql::Schema>,
-    session: Session,
+    templates: State<Arc<Templates>>,
+    metrics: State<Metrics>,
+    limits: State<QueryLimits>,
+    request_id: RequestId,
     request: GraphQLRequest,
 ) -> Result<GraphQLResponse, Failure> {
     // Fetch the currently logged in user. This should not fail unless something went horribly
@@ -49,8 +223,13 @@ fn graphql(
     // a 500 Internal Server Error
     let user = users::get(&conn, session.user_id)
         .map_err(|_| Failure(Status::Forbidden))?;
-    Ok(request.execute(&schema, &::graphql::Context {
+    request.execute(&schema, &::graphql::Context {
         conn,
         user,
-    }))
+        share_link: None,
+        session_jti: Some(session.jti),
+        templates: templates.clone(),
+        loader: Default::default(),
+        request_id: request_id.0,
+    }, &metrics, &limits)
 }
@@ -0,0 +1,51 @@
+//! Probes used by the deployment to check on the server. Neither requires authentication.
+//!
+//! * /healthz succeeds as long as the process is up and handling requests
+//! * /readyz only succeeds if the server can actually serve requests right now: a database
+//!   connection is available, the database answers a trivial query and the program templates can
+//!   be read
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::{self, RunQueryDsl};
+use rocket::{
+    State,
+    http::Status,
+    response::status,
+};
+
+use api::db::DBPool;
+use template::Templates;
+
+/// How long /readyz waits for a database connection before reporting that none are available
+const READY_CONNECTION_TIMEOUT_MS: u64 = 1000;
+
+#[get("/healthz")]
+fn healthz() -> &'static str {
+    "ok"
+}
+
+#[get("/readyz")]
+fn readyz(pool: State<DBPool>, templates: State<Arc<Templates>>) -> status::Custom<String> {
+    let mut failures = Vec::new();
+
+    match pool.get_timeout(Duration::from_millis(READY_CONNECTION_TIMEOUT_MS)) {
+        Ok(conn) => if let Err(err) = diesel::sql_query("SELECT 1").execute(&*conn) {
+            failures.push(format!("database query failed: {}", err));
+        },
+        Err(err) => failures.push(format!("no database connection available: {}", err)),
+    }
+
+    match templates.ids() {
+        Ok(ref ids) if ids.is_empty() => failures.push("no program templates found".to_string()),
+        Ok(_) => {},
+        Err(err) => failures.push(format!("unable to read program templates: {}", err)),
+    }
+
+    if failures.is_empty() {
+        status::Custom(Status::Ok, "ok".to_string())
+    } else {
+        status::Custom(Status::ServiceUnavailable, failures.join("\n"))
+    }
+}
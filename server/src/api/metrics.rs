@@ -0,0 +1,233 @@
+//! Request metrics exposed at /metrics in the Prometheus text format.
+//!
+//! Every request is counted and timed by the route that handled it (e.g. "/auth/<provider>"
+//! rather than "/auth/github") so that the number of series stays small. GraphQL requests are also
+//! timed by the name of their operation, and the errors they return are counted by code (see
+//! error::Error). Operation names are chosen by clients, so only the first MAX_OPERATIONS names
+//! that are executed get their own series.
+//!
+//! The metrics are not secret but /metrics is not authenticated either, so deployments should only
+//! expose it to the monitoring system.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::{
+    Data,
+    Request,
+    Response,
+    State,
+    fairing::{Fairing, Info, Kind},
+    http::{ContentType, Header},
+    response::content,
+};
+
+use api::db::DBPool;
+
+/// The upper bounds (in seconds) of the buckets of each latency histogram
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The most operation names that are timed separately. Any other operations are timed together
+/// as "other", and requests that were rejected before an operation was executed as "rejected".
+const MAX_OPERATIONS: usize = 100;
+
+/// The header used to remember when a request started. Rocket has no other place to keep data
+/// between the request and response callbacks of a fairing. Any value sent by the client is
+/// replaced.
+const START_HEADER: &str = "X-Request-Start";
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The number of observations less than or equal to each of the BUCKETS
+    buckets: [u64; 11],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, &bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (count, bound) in self.buckets.iter().zip(BUCKETS.iter()) {
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct MetricsData {
+    /// The number of responses by method, route and status code
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// The time taken to respond by method and route
+    request_durations: BTreeMap<(String, String), Histogram>,
+    /// The time taken to execute each GraphQL operation by name
+    operation_durations: BTreeMap<String, Histogram>,
+    /// The number of errors returned from GraphQL fields by code
+    graphql_errors: BTreeMap<String, u64>,
+}
+
+/// Collects the metrics of the server. Cloning this shares the same metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    data: Arc<Mutex<MetricsData>>,
+}
+
+impl Metrics {
+    fn lock(&self) -> MutexGuard<MetricsData> {
+        // Metrics are only ever added to, so they are still usable after a panic
+        self.data.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a GraphQL request along with the codes of the errors that it returned. The operation
+    /// is None if the request was rejected without executing anything.
+    pub fn observe_operation(&self, operation: Option<&str>, duration: Duration, error_codes: &[String]) {
+        let mut data = self.lock();
+        let label = match operation {
+            Some(operation) if data.operation_durations.contains_key(operation)
+                || data.operation_durations.len() < MAX_OPERATIONS => operation,
+            Some(_) => "other",
+            None => "rejected",
+        };
+        data.operation_durations.entry(label.to_string()).or_insert_with(Histogram::default)
+            .observe(seconds(duration));
+        for code in error_codes {
+            *data.graphql_errors.entry(code.clone()).or_insert(0) += 1;
+        }
+    }
+
+    /// Renders all of the metrics, including the current state of the database pool
+    pub fn render(&self, pool: &DBPool) -> String {
+        let data = self.lock();
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total The number of HTTP responses by route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (&(ref method, ref route, status), count) in &data.requests {
+            let _ = writeln!(out, "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, escape(route), status, count);
+        }
+
+        out.push_str("# HELP http_request_duration_seconds The time taken to respond to HTTP requests by route.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (&(ref method, ref route), histogram) in &data.request_durations {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+            histogram.render(&mut out, "http_request_duration_seconds", &labels);
+        }
+
+        out.push_str("# HELP graphql_operation_duration_seconds The time taken to execute GraphQL operations by name.\n");
+        out.push_str("# TYPE graphql_operation_duration_seconds histogram\n");
+        for (operation, histogram) in &data.operation_durations {
+            let labels = format!("operation=\"{}\"", escape(operation));
+            histogram.render(&mut out, "graphql_operation_duration_seconds", &labels);
+        }
+
+        out.push_str("# HELP graphql_errors_total The number of errors returned from GraphQL fields by code.\n");
+        out.push_str("# TYPE graphql_errors_total counter\n");
+        for (code, count) in &data.graphql_errors {
+            let _ = writeln!(out, "graphql_errors_total{{code=\"{}\"}} {}", escape(code), count);
+        }
+
+        let state = pool.state();
+        out.push_str("# HELP db_pool_connections The number of open database connections.\n");
+        out.push_str("# TYPE db_pool_connections gauge\n");
+        let _ = writeln!(out, "db_pool_connections {}", state.connections);
+        out.push_str("# HELP db_pool_idle_connections The number of database connections not in use.\n");
+        out.push_str("# TYPE db_pool_idle_connections gauge\n");
+        let _ = writeln!(out, "db_pool_idle_connections {}", state.idle_connections);
+        out.push_str("# HELP db_pool_max_connections The maximum number of database connections.\n");
+        out.push_str("# TYPE db_pool_max_connections gauge\n");
+        let _ = writeln!(out, "db_pool_max_connections {}", pool.max_size());
+
+        out
+    }
+}
+
+impl Fairing for Metrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.replace_header(Header::new(START_HEADER, micros_since_epoch().to_string()));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let method = request.method().as_str().to_string();
+        let route = request.route().map_or_else(|| "unmatched".to_string(), |route| route.uri.to_string());
+        let elapsed = elapsed_seconds(request);
+
+        let mut data = self.lock();
+        *data.requests.entry((method.clone(), route.clone(), response.status().code)).or_insert(0) += 1;
+        if let Some(elapsed) = elapsed {
+            data.request_durations.entry((method, route)).or_insert_with(Histogram::default)
+                .observe(elapsed);
+        }
+    }
+}
+
+/// The time (in seconds) since the request started, if it was seen by the Metrics fairing
+pub fn elapsed_seconds(request: &Request) -> Option<f64> {
+    request.headers().get_one(START_HEADER)
+        .and_then(|start| start.parse::<u64>().ok())
+        .map(|start| micros_since_epoch().saturating_sub(start) as f64 / 1e6)
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn micros_since_epoch() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_secs() * 1_000_000 + now.subsec_micros() as u64
+}
+
+/// Escapes a label value for the Prometheus text format
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[get("/metrics")]
+fn metrics(metrics: State<Metrics>, pool: State<DBPool>) -> content::Content<String> {
+    let content_type = ContentType::with_params("text", "plain", ("version", "0.0.4"));
+    content::Content(content_type, metrics.render(&pool))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Metrics, MAX_OPERATIONS};
+
+    #[test]
+    fn operation_names_are_limited() {
+        let metrics = Metrics::default();
+        for i in 0..MAX_OPERATIONS + 10 {
+            metrics.observe_operation(Some(&format!("Operation{}", i)), Duration::from_millis(1), &[]);
+        }
+        metrics.observe_operation(Some("Operation0"), Duration::from_millis(1), &[]);
+        metrics.observe_operation(None, Duration::from_millis(1), &[]);
+
+        let data = metrics.lock();
+        assert_eq!(data.operation_durations.len(), MAX_OPERATIONS + 2);
+        assert_eq!(data.operation_durations["Operation0"].count, 2);
+        assert_eq!(data.operation_durations["other"].count, 10);
+        assert_eq!(data.operation_durations["rejected"].count, 1);
+        assert!(!data.operation_durations.contains_key(&format!("Operation{}", MAX_OPERATIONS)));
+    }
+}
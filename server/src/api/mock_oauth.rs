@@ -0,0 +1,131 @@
+//! A minimal local HTTP server that stands in for an OAuth provider in tests.
+//!
+//! Each response is matched by method and path (ignoring the query string) and every request is
+//! recorded so tests can check what was sent to the provider. Only one request is handled per
+//! connection, which is enough for a blocking HTTP client.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A canned response to requests with a particular method and path
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub status: u16,
+    pub body: &'static str,
+}
+
+impl MockResponse {
+    /// A 200 OK response with the given JSON body
+    pub fn ok(method: &'static str, path: &'static str, body: &'static str) -> Self {
+        MockResponse {method, path, status: 200, body}
+    }
+}
+
+/// A request received by the mock server
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Each header as it was sent (e.g. "Accept: application/json")
+    pub headers: Vec<String>,
+    pub body: String,
+}
+
+pub struct MockOAuthServer {
+    url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockOAuthServer {
+    /// Starts the server on a random local port. The server runs until the test process exits.
+    pub fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("unable to bind mock server");
+        let url = format!("http://{}", listener.local_addr().expect("mock server has no address"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => handle(stream, &responses, &recorded),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        MockOAuthServer {url, requests}
+    }
+
+    /// The base URL of the server (e.g. http://127.0.0.1:41234)
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// All of the requests received so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().expect("mock server poisoned").clone()
+    }
+}
+
+fn handle(stream: TcpStream, responses: &[MockResponse], recorded: &Mutex<Vec<RecordedRequest>>) {
+    let mut reader = BufReader::new(stream);
+    let request = match read_request(&mut reader) {
+        Some(request) => request,
+        None => return,
+    };
+
+    let (status, body) = responses.iter()
+        .find(|res| res.method == request.method && res.path == request.path)
+        .map_or((404, "{}"), |res| (res.status, res.body));
+    recorded.lock().expect("mock server poisoned").push(request);
+
+    let response = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body,
+    );
+    let mut stream = reader.into_inner();
+    let _ = stream.write_all(response.as_bytes());
+    let _ = stream.flush();
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> Option<RecordedRequest> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_right();
+        if line.is_empty() {
+            break;
+        }
+
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+        headers.push(line.to_string());
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
@@ -1,7 +1,25 @@
 pub mod db;
 
 mod auth;
+#[cfg(any(debug_assertions, feature = "dev-login"))]
+pub mod dev;
+mod export;
+mod github;
+mod google;
 mod graphql;
+mod health;
+mod metrics;
+#[cfg(test)]
+mod mock_oauth;
+mod providers;
+mod purge;
+mod rate_limit;
+mod request_log;
+mod share;
+#[cfg(test)]
+mod tests;
+
+use std::sync::Arc;
 
 use rocket::{
     self,
@@ -9,7 +27,16 @@ use rocket::{
 };
 use rocket_cors::{Cors, AllowedOrigins, AllowedHeaders};
 
-use self::auth::SecretKey;
+use config::Config;
+use query_cost::QueryLimits;
+use template::Templates;
+use self::auth::{SecretKey, TokenLifetimes};
+use self::google::{GoogleVerifier, GoogleClientId};
+use self::github::GitHubProvider;
+use self::metrics::Metrics;
+use self::providers::Providers;
+use self::rate_limit::{RateLimits, RateLimiter};
+use self::request_log::RequestLog;
 
 #[get("/")]
 fn index() -> &'static str {
@@ -17,11 +44,34 @@ fn index() -> &'static str {
     ""
 }
 
-pub fn run_server(database_url: &str, secret_key: String, allowed_origins: &[&str]) {
-    let conn = db::connect(database_url);
+pub fn run_server(config: Config) {
+    let conn = db::connect(&config.database.url, config.database.pool_size);
+    purge::spawn(conn.clone(), config.plans.deleted_retention);
+
+    app(config, conn).launch();
+}
+
+/// Builds the server with all of its routes and managed state without starting it, so that tests
+/// can run it against their own database pool
+fn app(config: Config, conn: db::DBPool) -> rocket::Rocket {
+    let Config {auth, cors, templates, limits, ..} = config;
+
+    let mut providers = Providers::default();
+    let google_client_id = auth.google.client_id;
+    providers.add(match auth.google.jwks_file {
+        // Verify Google ID tokens against a local key set instead of the one published by Google
+        Some(path) => GoogleVerifier::from_file(google_client_id.clone(), path),
+        None => GoogleVerifier::new(google_client_id.clone()),
+    });
+    if let Some(github) = auth.github {
+        providers.add(GitHubProvider::new(github.client_id, github.client_secret));
+    }
+    #[cfg(any(debug_assertions, feature = "dev-login"))]
+    providers.add(dev::DevProvider);
 
-    let (allowed_origins, failed_origins) = AllowedOrigins::some(allowed_origins);
-    assert!(failed_origins.is_empty());
+    let allowed_origins: Vec<&str> = cors.allowed_origins.iter().map(String::as_str).collect();
+    // Every origin was already parsed successfully by Config::validate
+    let (allowed_origins, _) = AllowedOrigins::some(&allowed_origins);
 
     let options = Cors {
         allowed_origins: allowed_origins,
@@ -31,18 +81,49 @@ pub fn run_server(database_url: &str, secret_key: String, allowed_origins: &[&st
         ..Default::default()
     };
 
+    let metrics = Metrics::default();
+
     rocket::ignite()
         .manage(conn)
         .manage(::graphql::schema())
-        .manage(SecretKey(secret_key))
+        .manage(::graphql::shared_schema())
+        .manage(SecretKey(auth.secret_key))
+        .manage(TokenLifetimes {
+            access: auth.access_token_lifetime,
+            refresh: auth.refresh_token_lifetime,
+        })
+        .manage(Arc::new(Templates::new(templates.dir)))
+        .manage(providers)
+        .manage(GoogleClientId(google_client_id))
+        .manage(metrics.clone())
+        .manage(RateLimits {
+            api: RateLimiter::new(limits.requests_per_minute),
+            auth: RateLimiter::new(limits.auth_requests_per_minute),
+        })
+        .manage(QueryLimits {
+            max_depth: limits.max_query_depth,
+            max_complexity: limits.max_query_complexity,
+        })
         .mount("/", routes![
             index,
+            health::healthz,
+            health::readyz,
+            metrics::metrics,
             auth::google_auth,
+            auth::provider_auth,
+            auth::refresh,
             auth::logout,
+            graphql::graphiql_login,
             graphql::graphiql,
             graphql::get_graphql,
             graphql::graphql,
+            share::shared_plan,
+            share::shared_graphql,
+            export::export_plan,
+            export::diff_plan,
         ])
+        .catch(errors![rate_limit::too_many_requests])
         .attach(options)
-        .launch();
+        .attach(metrics)
+        .attach(RequestLog)
 }
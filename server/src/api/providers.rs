@@ -0,0 +1,80 @@
+//! Authentication providers that users can log in with.
+//!
+//! Each provider turns a credential sent by the client (e.g. a Google ID token or a GitHub OAuth
+//! authorization code) into an identity that the provider has verified. What happens with that
+//! identity is decided by the accounts module, the same way for every provider.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use accounts::VerifiedIdentity;
+use super::google::{GoogleVerifier, GoogleAuthError};
+
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The credential was rejected or could not be verified
+    InvalidCredential(String),
+    /// The provider has not verified the email of the account
+    EmailNotVerified,
+    /// The provider could not be reached or responded with something unexpected
+    Unavailable(String),
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::InvalidCredential(err) => write!(f, "Invalid credential: {}", err),
+            ProviderError::EmailNotVerified => write!(f, "Account email has not been verified"),
+            ProviderError::Unavailable(err) => write!(f, "Authentication provider unavailable: {}", err),
+        }
+    }
+}
+
+/// A service that verifies who is logging in
+pub trait AuthProvider: Send + Sync {
+    /// The name of the provider, used in URLs and stored with every identity (e.g. "google")
+    fn name(&self) -> &'static str;
+
+    /// Verifies the credential sent by the client and returns the identity it belongs to
+    fn authenticate(&self, credential: &str) -> Result<VerifiedIdentity, ProviderError>;
+}
+
+/// All of the configured providers by name
+#[derive(Default)]
+pub struct Providers {
+    providers: HashMap<&'static str, Box<AuthProvider>>,
+}
+
+impl Providers {
+    pub fn add<P: AuthProvider + 'static>(&mut self, provider: P) {
+        self.providers.insert(provider.name(), Box::new(provider));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AuthProvider> {
+        self.providers.get(name).map(|provider| &**provider)
+    }
+}
+
+impl AuthProvider for GoogleVerifier {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    /// The credential is the ID token returned by Google Sign-In
+    fn authenticate(&self, credential: &str) -> Result<VerifiedIdentity, ProviderError> {
+        let claims = self.verify(credential).map_err(|err| match err {
+            GoogleAuthError::EmailNotVerified => ProviderError::EmailNotVerified,
+            GoogleAuthError::KeySet(err) => ProviderError::Unavailable(err),
+            err => ProviderError::InvalidCredential(err.to_string()),
+        })?;
+
+        Ok(VerifiedIdentity {
+            provider: self.name(),
+            // The name is only provided if the profile scope was requested
+            name: claims.name.unwrap_or_else(|| claims.email.clone()),
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+        })
+    }
+}
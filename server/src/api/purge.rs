@@ -0,0 +1,65 @@
+//! A background task that permanently removes terms and courses that were deleted longer ago than
+//! the configured retention period. Until then, deleted records can still be restored.
+
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use chrono::{Utc, Duration};
+use diesel::Connection;
+use diesel::result::QueryResult;
+
+use api::db::DBPool;
+use logging;
+use models::{terms, term_courses, plan_events};
+
+/// How often the purge runs
+const PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Spawns a thread that periodically purges deleted records older than the given retention period
+pub fn spawn(pool: DBPool, retention: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        purge(&pool, retention);
+        thread::sleep(StdDuration::from_secs(PURGE_INTERVAL_SECS));
+    })
+}
+
+/// Purges the records deleted longer ago than the given retention period, along with any history
+/// of the course plans that refers to them
+pub fn purge(pool: &DBPool, retention: Duration) {
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(err) => {
+            logging::error("Unable to get a connection to purge deleted records")
+                .field("error", err.to_string())
+                .log();
+            return;
+        },
+    };
+
+    let deleted_before = Utc::now() - retention;
+    // Courses are purged first so that the terms purged next only need to remove the courses that
+    // were still part of them when they were deleted
+    let result: QueryResult<()> = conn.transaction(|| {
+        // The IDs of the purged terms and courses of each course plan
+        let mut purged: BTreeMap<i32, (Vec<i32>, Vec<i32>)> = BTreeMap::new();
+        for (course, term) in term_courses::purge_deleted(&conn, deleted_before)? {
+            purged.entry(term.course_plan_id).or_default().1.push(course.id);
+        }
+        for (term, courses) in terms::purge_deleted(&conn, deleted_before)? {
+            let (term_ids, course_ids) = purged.entry(term.course_plan_id).or_default();
+            term_ids.push(term.id);
+            course_ids.extend(courses.iter().map(|course| course.id));
+        }
+
+        // The history of each course plan must not refer to anything that was purged, or undoing
+        // it would fail
+        for (course_plan_id, (term_ids, course_ids)) in purged {
+            plan_events::purge_referring_to(&conn, course_plan_id, &term_ids, &course_ids)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        logging::error("Unable to purge deleted records").field("error", err.to_string()).log();
+    }
+}
@@ -0,0 +1,207 @@
+//! Limits how often each client can make requests, using a token bucket per client.
+//!
+//! Each bucket holds up to a minute's worth of requests and refills continuously, so a client can
+//! make a short burst of requests and is then slowed down to the configured rate. GraphQL requests
+//! are limited per user (or per client IP when not logged in) and logging in or refreshing a
+//! session is limited per client IP.
+//!
+//! Requests over the limit get 429 Too Many Requests with a Retry-After header (in seconds) and
+//! the same number of seconds in the JSON body:
+//!
+//! ```json
+//! {"error": "Too many requests", "retryAfter": 3}
+//! ```
+//!
+//! The client IP is the address of the connection, so a deployment behind a proxy will limit all
+//! clients that are not logged in together.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use rocket::{
+    Outcome,
+    Request,
+    Response,
+    State,
+    http::{ContentType, Status},
+    request::{self, FromRequest},
+    response::{self, Responder},
+};
+use serde_json;
+
+use api::auth;
+
+/// Buckets that are full again are removed once there are this many, since a full bucket is the
+/// same as no bucket at all
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket for each client
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {per_minute, buckets: Mutex::new(HashMap::new())}
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<String, Bucket>> {
+        // Every bucket is always left in a valid state, so they are still usable after a panic
+        self.buckets.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Adds the tokens earned since the bucket was last updated
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.updated);
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let capacity = self.per_minute as f64;
+        bucket.tokens = (bucket.tokens + elapsed * capacity / 60.0).min(capacity);
+        bucket.updated = now;
+    }
+
+    /// The number of seconds until a bucket has a whole token again, rounded up
+    fn wait_secs(&self, bucket: &Bucket) -> u64 {
+        ((1.0 - bucket.tokens) * 60.0 / self.per_minute as f64).ceil().max(1.0) as u64
+    }
+
+    /// Takes a token from the bucket of the given client. Returns the number of seconds to wait
+    /// before trying again if the bucket is empty.
+    pub fn check(&self, key: &str) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.lock();
+        if buckets.len() >= MAX_BUCKETS {
+            let capacity = self.per_minute as f64;
+            buckets.retain(|_, bucket| {
+                self.refill(bucket, now);
+                bucket.tokens < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string())
+            .or_insert_with(|| Bucket {tokens: self.per_minute as f64, updated: now});
+        self.refill(bucket, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.wait_secs(bucket))
+        }
+    }
+
+    /// The number of seconds until the given client can make another request, or zero if it can
+    /// make one now. Unlike check, this does not use up a token.
+    pub fn retry_after(&self, key: &str) -> u64 {
+        let mut buckets = self.lock();
+        match buckets.get_mut(key) {
+            Some(bucket) => {
+                self.refill(bucket, Instant::now());
+                if bucket.tokens >= 1.0 { 0 } else { self.wait_secs(bucket) }
+            },
+            None => 0,
+        }
+    }
+}
+
+/// The rate limiters of the server
+#[derive(Debug)]
+pub struct RateLimits {
+    /// Limits GraphQL requests
+    pub api: RateLimiter,
+    /// Limits logging in and refreshing sessions
+    pub auth: RateLimiter,
+}
+
+/// The address of the client, used when there is no user to limit
+fn client_key(request: &Request) -> String {
+    match request.remote() {
+        Some(address) => format!("ip:{}", address.ip()),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// The user making the request, or the address of the client if there is no user. The token is
+/// only checked to be validly signed since the Session guard checks the rest.
+fn user_key(request: &Request) -> String {
+    match auth::token_user_id(request) {
+        Some(user_id) => format!("user:{}", user_id),
+        None => client_key(request),
+    }
+}
+
+/// A request guard that succeeds if the user has not used up their GraphQL requests. List it
+/// before any other guard so that requests over the limit are turned away before doing any work.
+pub struct ApiRateLimit;
+
+impl<'a, 'r> FromRequest<'a, 'r> for ApiRateLimit {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let limits = request.guard::<State<RateLimits>>()
+            .map_failure(|_| (Status::InternalServerError, ()))?;
+        match limits.api.check(&user_key(request)) {
+            Ok(()) => Outcome::Success(ApiRateLimit),
+            Err(_) => Outcome::Failure((Status::TooManyRequests, ())),
+        }
+    }
+}
+
+/// A request guard that succeeds if the client has not used up their login and refresh requests
+pub struct AuthRateLimit;
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthRateLimit {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let limits = request.guard::<State<RateLimits>>()
+            .map_failure(|_| (Status::InternalServerError, ()))?;
+        match limits.auth.check(&client_key(request)) {
+            Ok(()) => Outcome::Success(AuthRateLimit),
+            Err(_) => Outcome::Failure((Status::TooManyRequests, ())),
+        }
+    }
+}
+
+/// The response to a request that was over a rate limit
+#[derive(Debug, Serialize)]
+struct TooManyRequests {
+    error: &'static str,
+    /// The number of seconds to wait before trying again
+    #[serde(rename = "retryAfter")]
+    retry_after: u64,
+}
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let body = serde_json::to_string(&self).map_err(|_| Status::InternalServerError)?;
+        Response::build()
+            .status(Status::TooManyRequests)
+            .header(ContentType::JSON)
+            .raw_header("Retry-After", self.retry_after.to_string())
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+/// Responds to requests that were over a rate limit. A guard cannot add headers to the response
+/// when it fails, so the time to wait is looked up again here.
+#[error(429)]
+fn too_many_requests(request: &Request) -> TooManyRequests {
+    let retry_after = request.guard::<State<RateLimits>>().succeeded()
+        .map_or(1, |limits| {
+            let api = limits.api.retry_after(&user_key(request));
+            let auth = limits.auth.retry_after(&client_key(request));
+            api.max(auth).max(1)
+        });
+
+    TooManyRequests {error: "Too many requests", retry_after}
+}
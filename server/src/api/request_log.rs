@@ -0,0 +1,90 @@
+//! Gives every request an ID and logs each request once it has been responded to.
+//!
+//! The ID is returned to the client in the X-Request-Id header and is included in every event
+//! logged about the request, so that a client reporting a problem can point to exactly what
+//! happened. A proxy in front of the server can set X-Request-Id itself to have its own logs line
+//! up with ours.
+
+use rocket::{
+    Data,
+    Outcome,
+    Request,
+    Response,
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    request::{self, FromRequest},
+};
+
+use api::auth;
+use api::metrics;
+use logging;
+use random;
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The number of characters in a generated request ID
+const REQUEST_ID_LENGTH: usize = 16;
+
+/// The ID of the current request. Every request has one since it is assigned by the RequestLog
+/// fairing before any guard runs.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let id = request.headers().get_one(REQUEST_ID_HEADER).unwrap_or("unknown");
+        Outcome::Success(RequestId(id.to_string()))
+    }
+}
+
+/// Assigns request IDs and logs requests. Rocket has no other place to keep data for a request,
+/// so the ID is stored in the X-Request-Id header of the request itself.
+pub struct RequestLog;
+
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        // An ID sent by the client is kept as long as it cannot garble the logs
+        let keep = request.headers().get_one(REQUEST_ID_HEADER).map_or(false, is_valid_id);
+        if !keep {
+            let id = random::token()
+                .map(|token| token[..REQUEST_ID_LENGTH].to_string())
+                .unwrap_or_else(|_| "unknown".to_string());
+            request.replace_header(Header::new(REQUEST_ID_HEADER, id));
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let RequestId(id) = request.guard::<RequestId>().succeeded()
+            .unwrap_or_else(|| RequestId("unknown".to_string()));
+        response.set_header(Header::new(REQUEST_ID_HEADER, id.clone()));
+
+        let route = request.route().map_or_else(|| "unmatched".to_string(), |route| route.uri.to_string());
+        let mut event = logging::info("request")
+            .field("request_id", id)
+            .field("method", request.method().as_str())
+            .field("route", route)
+            .field("path", request.uri().path())
+            .field("status", response.status().code);
+        if let Some(elapsed) = metrics::elapsed_seconds(request) {
+            event = event.field("latency_ms", (elapsed * 1000.0).round() as u64);
+        }
+        if let Some(user_id) = auth::token_user_id(request) {
+            event = event.field("user_id", user_id);
+        }
+        event.log();
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
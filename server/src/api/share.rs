@@ -0,0 +1,115 @@
+//! Routes that give read-only access to a course plan through a share link. These routes do not
+//! require the Session guard since the people a course plan is shared with may not have accounts.
+
+use std::sync::Arc;
+
+use rocket::{
+    State,
+    http::Status,
+    response::Failure,
+};
+use rocket_contrib::Json;
+use chrono::{DateTime, Utc};
+
+use api::db;
+use api::graphql::{GraphQLRequest, GraphQLResponse};
+use api::metrics::Metrics;
+use api::rate_limit::ApiRateLimit;
+use api::request_log::RequestId;
+use models::{users, course_plans, terms, term_courses, share_links};
+use query_cost::QueryLimits;
+use template::Templates;
+
+/// A read-only view of a shared course plan
+#[derive(Debug, Serialize)]
+pub struct SharedPlan {
+    id: i32,
+    terms: Vec<SharedTerm>,
+    #[serde(rename = "expiresAt")]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedTerm {
+    id: i32,
+    name: String,
+    courses: Vec<SharedTermCourse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedTermCourse {
+    id: i32,
+    name: String,
+}
+
+/// Finds the share link for the given token, failing with 404 Not Found if the token is unknown,
+/// expired or revoked so that nothing about the link is revealed
+fn active_share_link(conn: &db::Connection, token: &str) -> Result<share_links::ShareLink, Failure> {
+    match share_links::get_active(conn, token) {
+        Ok(Some(share_link)) => Ok(share_link),
+        Ok(None) => Err(Failure(Status::NotFound)),
+        Err(_) => Err(Failure(Status::InternalServerError)),
+    }
+}
+
+/// Loads the course plan of the given share link along with the user that owns it
+fn shared_course_plan(
+    conn: &db::Connection,
+    share_link: &share_links::ShareLink,
+) -> Result<(users::User, course_plans::CoursePlan), Failure> {
+    let course_plan = course_plans::find(conn, share_link.course_plan_id)
+        .map_err(|_| Failure(Status::InternalServerError))?;
+    let owner = users::get(conn, course_plan.user_id)
+        .map_err(|_| Failure(Status::InternalServerError))?;
+    Ok((owner, course_plan))
+}
+
+#[get("/shared/<token>")]
+fn shared_plan(conn: db::Connection, token: String) -> Result<Json<SharedPlan>, Failure> {
+    let share_link = active_share_link(&conn, &token)?;
+    let (_, course_plan) = shared_course_plan(&conn, &share_link)?;
+
+    let mut shared_terms = Vec::new();
+    for term in terms::all(&conn, &course_plan).map_err(|_| Failure(Status::InternalServerError))? {
+        let courses = term_courses::all(&conn, &term).map_err(|_| Failure(Status::InternalServerError))?;
+        shared_terms.push(SharedTerm {
+            id: term.id,
+            name: term.name,
+            courses: courses.into_iter()
+                .map(|course| SharedTermCourse {id: course.id, name: course.name})
+                .collect(),
+        });
+    }
+
+    Ok(Json(SharedPlan {
+        id: course_plan.id,
+        terms: shared_terms,
+        expires_at: share_link.expires_at,
+    }))
+}
+
+/// A restricted GraphQL endpoint that can only read the shared course plan
+#[post("/shared/<token>/graphql", format = "application/json", data = "<request>")]
+fn shared_graphql(
+    _rate_limit: ApiRateLimit,
+    conn: db::Connection,
+    schema: State<::graphql::SharedSchema>,
+    templates: State<Arc<Templates>>,
+    metrics: State<Metrics>,
+    limits: State<QueryLimits>,
+    request_id: RequestId,
+    token: String,
+    request: GraphQLRequest,
+) -> Result<GraphQLResponse, Failure> {
+    let share_link = active_share_link(&conn, &token)?;
+    let (owner, _) = shared_course_plan(&conn, &share_link)?;
+    request.execute(&schema, &::graphql::Context {
+        conn,
+        user: owner,
+        share_link: Some(share_link),
+        session_jti: None,
+        templates: templates.clone(),
+        loader: Default::default(),
+        request_id: request_id.0,
+    }, &metrics, &limits)
+}
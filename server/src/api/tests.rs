@@ -0,0 +1,827 @@
+//! End-to-end tests that run GraphQL documents through the /graphql route of the full server.
+//!
+//! These tests require TEST_DATABASE_URL to point to a database with all of the migrations
+//! applied. Each test runs in its own transaction that is never committed (see db::connect_test),
+//! so tests do not affect each other or leave anything behind. Every test is ignored by default and
+//! runs with `cargo test -- --ignored`, which fails if TEST_DATABASE_URL is not set.
+
+use std::env;
+use std::path::PathBuf;
+
+use chrono::Duration;
+use diesel;
+use diesel::prelude::*;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::Client;
+use serde_json::{self, Value};
+
+use config::{Config, DatabaseConfig, AuthConfig, GoogleConfig, CorsConfig, TemplatesConfig, PlansConfig, LoggingConfig, LimitsConfig};
+use logging::LogFormat;
+use models::users;
+use super::app;
+use super::auth::{self, SecretKey, TokenLifetimes, UserAgent};
+use super::db::{self, DBPool};
+use super::purge;
+
+const SECRET_KEY: &str = "test secret key";
+
+#[derive(Serialize)]
+struct GraphQLRequest<'a> {
+    query: &'a str,
+}
+
+/// The server along with direct access to its (single connection) database pool
+struct TestApp {
+    client: Client,
+    pool: DBPool,
+}
+
+/// A user with a session that can be used to make requests
+struct TestUser {
+    user: users::User,
+    token: String,
+}
+
+impl TestApp {
+    /// Starts the server against the test database
+    fn start() -> Self {
+        Self::start_with(|_| {})
+    }
+
+    /// Starts the server with changes to the configuration used by tests
+    fn start_with<F: FnOnce(&mut Config)>(configure: F) -> Self {
+        let database_url = env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL must be set to run the GraphQL integration tests");
+        let pool = db::connect_test(&database_url);
+
+        let mut config = Config {
+            database: DatabaseConfig {url: database_url, pool_size: 1},
+            auth: AuthConfig {
+                secret_key: SECRET_KEY.to_string(),
+                access_token_lifetime: Duration::minutes(15),
+                refresh_token_lifetime: Duration::days(30),
+                google: GoogleConfig {client_id: "test-client-id".to_string(), jwks_file: None},
+                github: None,
+            },
+            cors: CorsConfig {allowed_origins: Vec::new()},
+            templates: TemplatesConfig {dir: PathBuf::from("templates")},
+            plans: PlansConfig {deleted_retention: Duration::days(30)},
+            logging: LoggingConfig {format: LogFormat::Text},
+            // Every request in tests comes from the same (unknown) client IP
+            limits: LimitsConfig {
+                requests_per_minute: 10_000,
+                auth_requests_per_minute: 10_000,
+                max_query_depth: 15,
+                max_query_complexity: 300,
+            },
+        };
+        configure(&mut config);
+        let client = Client::new(app(config, pool.clone())).expect("invalid server instance");
+
+        TestApp {client, pool}
+    }
+
+    /// Creates a user and starts a session for them
+    fn user(&self, name: &str) -> TestUser {
+        let conn = self.pool.get().expect("unable to connect to the test database");
+        let email = format!("{}@example.com", name.to_lowercase());
+        let user = users::create(&conn, name, &email).expect("unable to create user");
+        drop(conn);
+        self.session(&user)
+    }
+
+    /// Starts another session for an existing user
+    fn session(&self, user: &users::User) -> TestUser {
+        let conn = self.pool.get().expect("unable to connect to the test database");
+        let lifetimes = TokenLifetimes {access: Duration::minutes(15), refresh: Duration::days(30)};
+        let tokens = auth::start_session(&conn, &SecretKey(SECRET_KEY.to_string()), &lifetimes, user, UserAgent(None))
+            .expect("unable to start session");
+
+        TestUser {user: user.clone(), token: tokens.token}
+    }
+
+    /// Creates a user with the admin role and starts a session for them
+    fn admin(&self, name: &str) -> TestUser {
+        use schema::users::dsl::{users, is_admin};
+
+        let admin = self.user(name);
+        let conn = self.pool.get().expect("unable to connect to the test database");
+        diesel::update(users.find(admin.user.id))
+            .set(is_admin.eq(true))
+            .execute(&conn)
+            .expect("unable to make user an admin");
+        admin
+    }
+
+    /// Runs a GraphQL document as the given user, returning the HTTP status and the JSON response
+    fn graphql(&self, user: &TestUser, query: &str) -> (Status, Value) {
+        let body = serde_json::to_string(&GraphQLRequest {query}).expect("unable to serialize request");
+        let mut response = self.client.post("/graphql")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", user.token)))
+            .body(body)
+            .dispatch();
+
+        let status = response.status();
+        let body = response.body_string().unwrap_or_default();
+        (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    /// Runs a GraphQL document that is expected to succeed and returns its data
+    fn ok(&self, user: &TestUser, query: &str) -> Value {
+        let (status, response) = self.graphql(user, query);
+        assert_eq!(status, Status::Ok, "unexpected status for {}: {}", query, response);
+        assert!(response.get("errors").is_none(), "unexpected errors for {}: {}", query, response);
+        response["data"].clone()
+    }
+
+    /// Runs a GraphQL document that is expected to fail and returns the code of its first error
+    fn error_code(&self, user: &TestUser, query: &str) -> String {
+        let (_, response) = self.graphql(user, query);
+        match response["errors"][0]["extensions"]["code"].as_str() {
+            Some(code) => code.to_string(),
+            None => panic!("expected an error with a code for {}, got: {}", query, response),
+        }
+    }
+}
+
+/// The names of the terms in the default course plan of the user along with their courses
+fn plan_terms(app: &TestApp, user: &TestUser) -> Vec<(String, Vec<String>)> {
+    let data = app.ok(user, "{ coursePlan(default: true) { terms { name courses { name } } } }");
+    data["coursePlan"]["terms"].as_array().expect("terms is not a list").iter().map(|term| {
+        let courses = term["courses"].as_array().expect("courses is not a list").iter()
+            .map(|course| course["name"].as_str().unwrap_or_default().to_string())
+            .collect();
+        (term["name"].as_str().unwrap_or_default().to_string(), courses)
+    }).collect()
+}
+
+fn json_strings(strings: &[&str]) -> Value {
+    Value::Array(strings.iter().map(|string| Value::String(string.to_string())).collect())
+}
+
+fn id(value: &Value) -> i64 {
+    value["id"].as_i64().unwrap_or_else(|| panic!("expected an id in: {}", value))
+}
+
+#[test]
+#[ignore]
+fn requests_without_a_session_are_rejected() {
+    let app = TestApp::start();
+
+    let response = app.client.post("/graphql")
+        .header(ContentType::JSON)
+        .body(r#"{"query": "{ me { id } }"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let invalid = TestUser {user: app.user("Nobody").user, token: "not-a-token".to_string()};
+    let (status, _) = app.graphql(&invalid, "{ me { id } }");
+    assert_eq!(status, Status::Forbidden);
+}
+
+#[test]
+#[ignore]
+fn me_returns_the_logged_in_user() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+
+    let data = app.ok(&alice, "{ me { id name email isAdmin sessions { current } } }");
+    assert_eq!(data["me"]["id"].as_i64(), Some(alice.user.id as i64));
+    assert_eq!(data["me"]["email"], "alice@example.com");
+    assert_eq!(data["me"]["isAdmin"], false);
+    assert_eq!(data["me"]["sessions"][0]["current"], true);
+}
+
+#[test]
+#[ignore]
+fn create_course_plan() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+
+    app.ok(&alice, r#"mutation { createCoursePlan(params: {program: "uw-software-engineering_2018-2019_stream-8"}) { id } }"#);
+    assert!(!plan_terms(&app, &alice).is_empty());
+
+    // Only one course plan is allowed per user
+    let code = app.error_code(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    assert_eq!(code, "CONFLICT");
+
+    let bob = app.user("Bob");
+    let code = app.error_code(&bob, r#"mutation { createCoursePlan(params: {program: "../Cargo"}) { id } }"#);
+    assert_eq!(code, "VALIDATION");
+    let code = app.error_code(&bob, r#"mutation { createCoursePlan(params: {startTerm: "F26"}) { id } }"#);
+    assert_eq!(code, "VALIDATION");
+    let code = app.error_code(&bob, r#"mutation { createCoursePlan(params: {program: "uw-software-engineering_2018-2019_stream-8", startTerm: "Fall"}) { id } }"#);
+    assert_eq!(code, "VALIDATION");
+
+    // A program can start in any term
+    app.ok(&bob, r#"mutation { createCoursePlan(params: {program: "uw-software-engineering_2018-2019_stream-8", startTerm: "F26"}) { id } }"#);
+    let terms: Vec<_> = plan_terms(&app, &bob).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(&terms[..3], &["1A F26".to_string(), "1B W27".to_string(), "Co-op 1".to_string()]);
+    let code = app.error_code(&bob, "{ coursePlan(default: false) { id } }");
+    assert_eq!(code, "VALIDATION");
+}
+
+#[test]
+#[ignore]
+fn create_and_delete_terms_and_courses() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+
+    let term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, plan_id));
+    let term_id = id(&term["createTerm"]);
+    let course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 137") {{ id termId }} }}"#, term_id));
+    let course_id = id(&course["createTermCourse"]);
+    assert_eq!(course["createTermCourse"]["termId"].as_i64(), Some(term_id));
+    assert_eq!(plan_terms(&app, &alice), vec![("1A".to_string(), vec!["CS 137".to_string()])]);
+
+    app.ok(&alice, &format!("mutation {{ deleteTermCourse(termCourseId: {}) {{ id }} }}", course_id));
+    assert_eq!(plan_terms(&app, &alice), vec![("1A".to_string(), vec![])]);
+    app.ok(&alice, &format!("mutation {{ restoreTermCourse(termCourseId: {}) {{ id }} }}", course_id));
+    assert_eq!(plan_terms(&app, &alice), vec![("1A".to_string(), vec!["CS 137".to_string()])]);
+
+    let deleted = app.ok(&alice, &format!("mutation {{ deleteTerm(termId: {}) {{ id courses {{ name }} }} }}", term_id));
+    assert_eq!(deleted["deleteTerm"]["courses"][0]["name"], "CS 137");
+    assert!(plan_terms(&app, &alice).is_empty());
+    app.ok(&alice, &format!("mutation {{ restoreTerm(termId: {}) {{ id }} }}", term_id));
+    assert_eq!(plan_terms(&app, &alice), vec![("1A".to_string(), vec!["CS 137".to_string()])]);
+
+    // Restoring something that is not deleted fails
+    let code = app.error_code(&alice, &format!("mutation {{ restoreTerm(termId: {}) {{ id }} }}", term_id));
+    assert_eq!(code, "NOT_FOUND");
+}
+
+#[test]
+#[ignore]
+fn delete_course_plan() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, r#"mutation { createCoursePlan(params: {program: "uw-software-engineering_2018-2019_stream-8"}) { id } }"#);
+    let plan_id = id(&plan["createCoursePlan"]);
+    app.ok(&alice, &format!("mutation {{ createShareLink(coursePlanId: {}) {{ id }} }}", plan_id));
+
+    // Terms, courses, history and share links are all deleted along with the course plan
+    let data = app.ok(&alice, &format!("mutation {{ deleteCoursePlan(coursePlanId: {}) }}", plan_id));
+    assert_eq!(data["deleteCoursePlan"].as_i64(), Some(plan_id));
+    assert_eq!(app.error_code(&alice, "{ coursePlan(default: true) { id } }"), "NOT_FOUND");
+
+    let code = app.error_code(&alice, &format!("mutation {{ deleteCoursePlan(coursePlanId: {}) }}", plan_id));
+    assert_eq!(code, "NOT_FOUND");
+    // A new course plan can be created once the old one is gone
+    app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+}
+
+#[test]
+#[ignore]
+fn undo_and_restore_revision() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+
+    let code = app.error_code(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    assert_eq!(code, "CONFLICT");
+
+    app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, plan_id));
+    let history = app.ok(&alice, "{ coursePlan(default: true) { history { id kind } } }");
+    let revision_id = id(&history["coursePlan"]["history"][0]);
+    app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1B") {{ id }} }}"#, plan_id));
+    app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "2A") {{ id }} }}"#, plan_id));
+
+    app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    let names: Vec<_> = plan_terms(&app, &alice).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["1A", "1B"]);
+
+    app.ok(&alice, &format!("mutation {{ restoreRevision(revisionId: {}) {{ id }} }}", revision_id));
+    let names: Vec<_> = plan_terms(&app, &alice).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["1A"]);
+}
+
+#[test]
+#[ignore]
+fn undo_after_purging_deleted_records() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+
+    let term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, plan_id));
+    let term_id = id(&term["createTerm"]);
+    let course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 137") {{ id }} }}"#, term_id));
+    app.ok(&alice, &format!("mutation {{ deleteTermCourse(termCourseId: {}) {{ id }} }}", id(&course["createTermCourse"])));
+    let deleted_term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1B") {{ id }} }}"#, plan_id));
+    app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 138") {{ id }} }}"#, id(&deleted_term["createTerm"])));
+    app.ok(&alice, &format!("mutation {{ deleteTerm(termId: {}) {{ id }} }}", id(&deleted_term["createTerm"])));
+
+    // Everything deleted so far is purged, along with every event that refers to it
+    purge::purge(&app.pool, Duration::minutes(-1));
+    let history = app.ok(&alice, "{ coursePlan(default: true) { history { kind } } }");
+    let kinds: Vec<_> = history["coursePlan"]["history"].as_array().expect("history is not a list").iter()
+        .map(|event| event["kind"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(kinds, vec!["CREATE_TERM"]);
+
+    app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    assert!(plan_terms(&app, &alice).is_empty());
+    let code = app.error_code(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    assert_eq!(code, "CONFLICT");
+}
+
+#[test]
+#[ignore]
+fn plan_revisions() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id revision } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+    assert_eq!(plan["createCoursePlan"]["revision"], 0);
+
+    let revision = |app: &TestApp| {
+        app.ok(&alice, "{ coursePlan(default: true) { revision } }")["coursePlan"]["revision"].as_i64().unwrap()
+    };
+
+    // Every mutation increments the revision once, whether or not it says which revision it expects
+    let term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A", expectedRevision: 0) {{ id }} }}"#, plan_id));
+    let term_id = id(&term["createTerm"]);
+    assert_eq!(revision(&app), 1);
+    let course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 137") {{ id }} }}"#, term_id));
+    let course_id = id(&course["createTermCourse"]);
+    assert_eq!(revision(&app), 2);
+
+    // Moving a course to the term it is already in changes nothing, but still checks the revision
+    let move_course = |expected: i64| format!("mutation {{ moveTermCourse(termCourseId: {}, termId: {}, expectedRevision: {}) {{ id }} }}",
+        course_id, term_id, expected);
+    assert_eq!(app.error_code(&alice, &move_course(1)), "CONFLICT");
+    app.ok(&alice, &move_course(2));
+    assert_eq!(revision(&app), 2);
+    let data = r"term,course\n1B,MATH 119\n1B,CS 138";
+    app.ok(&alice, &format!(r#"mutation {{ importIntoCoursePlan(coursePlanId: {}, data: "{}", mode: APPEND, expectedRevision: 2) {{ addedTerms }} }}"#, plan_id, data));
+    assert_eq!(revision(&app), 3);
+
+    // A change based on an old revision is rejected without changing anything
+    let code = app.error_code(&alice, &format!("mutation {{ deleteTerm(termId: {}, expectedRevision: 2) {{ id }} }}", term_id));
+    assert_eq!(code, "CONFLICT");
+    assert_eq!(revision(&app), 3);
+    assert_eq!(plan_terms(&app, &alice).len(), 2);
+
+    let undone = app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}, expectedRevision: 3) {{ revision }} }}", plan_id));
+    assert_eq!(undone["undo"]["revision"], 4);
+
+    // A failed change does not use up a revision
+    for _ in 0..4 {
+        app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    }
+    let code = app.error_code(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    assert_eq!(code, "CONFLICT");
+    assert_eq!(revision(&app), 8);
+}
+
+#[test]
+#[ignore]
+fn plan_changes() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+
+    let changes = |app: &TestApp, revision: i32| {
+        let data = app.ok(&alice, &format!("{{ coursePlan(default: true) {{ changesSince(revision: {}) {{ revision kind termId termCourseId name position }} }} }}", revision));
+        data["coursePlan"]["changesSince"].as_array().cloned().unwrap_or_default()
+    };
+
+    let first = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, plan_id));
+    let first_id = id(&first["createTerm"]);
+    let second = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1B") {{ id }} }}"#, plan_id));
+    let second_id = id(&second["createTerm"]);
+    let course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 137") {{ id }} }}"#, first_id));
+    let course_id = id(&course["createTermCourse"]);
+
+    let all = changes(&app, 0);
+    let kinds: Vec<_> = all.iter().map(|change| change["kind"].as_str().unwrap().to_string()).collect();
+    assert_eq!(kinds, vec!["TERM_ADDED", "TERM_ADDED", "COURSE_ADDED"]);
+    assert_eq!(all[1]["position"], 1);
+    assert_eq!(all[2]["termCourseId"], course_id);
+    assert_eq!(changes(&app, 3).len(), 0);
+
+    app.ok(&alice, &format!(r#"mutation {{ renameTerm(termId: {}, name: "Fall 2018") {{ name }} }}"#, first_id));
+    app.ok(&alice, &format!("mutation {{ moveTerm(termId: {}, position: 0) {{ revision }} }}", second_id));
+    app.ok(&alice, &format!("mutation {{ moveTermCourse(termCourseId: {}, termId: {}) {{ termId }} }}", course_id, second_id));
+    assert_eq!(plan_terms(&app, &alice), vec![
+        ("1B".to_string(), vec!["CS 137".to_string()]),
+        ("Fall 2018".to_string(), vec![]),
+    ]);
+
+    let recent = changes(&app, 3);
+    assert_eq!(recent.len(), 3);
+    assert_eq!((recent[0]["kind"].as_str(), recent[0]["name"].as_str()), (Some("TERM_RENAMED"), Some("Fall 2018")));
+    assert_eq!((recent[1]["kind"].as_str(), recent[1]["position"].as_i64()), (Some("TERM_MOVED"), Some(0)));
+    assert_eq!((recent[2]["kind"].as_str(), recent[2]["termId"].as_i64()), (Some("COURSE_MOVED"), Some(second_id)));
+
+    // Undoing a change is reported as the change that reverses it
+    app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    let undone = changes(&app, 6);
+    assert_eq!(undone.len(), 1);
+    assert_eq!((undone[0]["kind"].as_str(), undone[0]["termId"].as_i64()), (Some("COURSE_MOVED"), Some(first_id)));
+
+    let code = app.error_code(&alice, "{ coursePlan(default: true) { changesSince(revision: 100) { kind } } }");
+    assert_eq!(code, "VALIDATION");
+}
+
+#[test]
+#[ignore]
+fn apply_plan_changes() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+    let term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, plan_id));
+    let term_id = id(&term["createTerm"]);
+    let course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 137") {{ id }} }}"#, term_id));
+    let course_id = id(&course["createTermCourse"]);
+
+    // Later operations can refer to the term and course created by earlier ones
+    let data = app.ok(&alice, &format!(r#"mutation {{ applyPlanChanges(coursePlanId: {}, expectedRevision: 2, operations: [
+        {{kind: CREATE_TERM, name: "1B", position: 0}},
+        {{kind: MOVE_COURSE, termCourseId: {}, termOperation: 0}},
+        {{kind: CREATE_COURSE, termOperation: 0, name: "MATH 135"}},
+        {{kind: UPDATE_COURSE, termCourseOperation: 2, name: "MATH 145"}},
+        {{kind: UPDATE_TERM, termId: {}, name: "Fall 2018"}},
+    ]) {{ coursePlan {{ revision }} results {{ kind term {{ name }} termCourse {{ name }} }} }} }}"#, plan_id, course_id, term_id));
+    let applied = &data["applyPlanChanges"];
+    assert_eq!(applied["coursePlan"]["revision"], 3);
+    let results = applied["results"].as_array().expect("results is not a list");
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0]["term"]["name"], "1B");
+    assert_eq!(results[3]["termCourse"]["name"], "MATH 145");
+    assert_eq!(plan_terms(&app, &alice), vec![
+        ("1B".to_string(), vec!["CS 137".to_string(), "MATH 145".to_string()]),
+        ("Fall 2018".to_string(), vec![]),
+    ]);
+
+    // If any operation fails, none of them are applied
+    let code = app.error_code(&alice, &format!(r#"mutation {{ applyPlanChanges(coursePlanId: {}, operations: [
+        {{kind: DELETE_COURSE, termCourseId: {}}},
+        {{kind: DELETE_TERM, termId: 0}},
+    ]) {{ results {{ kind }} }} }}"#, plan_id, course_id));
+    assert_eq!(code, "NOT_FOUND");
+    let code = app.error_code(&alice, &format!(r#"mutation {{ applyPlanChanges(coursePlanId: {}, operations: [
+        {{kind: DELETE_COURSE, termCourseId: {}}},
+        {{kind: CREATE_COURSE, termOperation: 3, name: "CS 138"}},
+    ]) {{ results {{ kind }} }} }}"#, plan_id, course_id));
+    assert_eq!(code, "VALIDATION");
+    let operations = vec![r#"{kind: CREATE_TERM, name: "Co-op"}"#; 201].join(", ");
+    let code = app.error_code(&alice, &format!("mutation {{ applyPlanChanges(coursePlanId: {}, operations: [{}]) {{ results {{ kind }} }} }}", plan_id, operations));
+    assert_eq!(code, "VALIDATION");
+    assert_eq!(plan_terms(&app, &alice).len(), 2);
+    assert_eq!(plan_terms(&app, &alice)[0].1.len(), 2);
+
+    // Every operation of the list is undone together
+    app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    assert_eq!(plan_terms(&app, &alice), vec![("1A".to_string(), vec!["CS 137".to_string()])]);
+
+    // Another user's terms cannot be changed
+    let bob = app.user("Bob");
+    let bob_plan = app.ok(&bob, "mutation { createCoursePlan(params: {}) { id } }");
+    let bob_term = app.ok(&bob, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, id(&bob_plan["createCoursePlan"])));
+    let bob_term_id = id(&bob_term["createTerm"]);
+    let bob_plan = app.ok(&bob, "{ coursePlan(default: true) { id } }");
+    let code = app.error_code(&bob, &format!("mutation {{ applyPlanChanges(coursePlanId: {}, operations: [{{kind: DELETE_TERM, termId: {}}}]) {{ results {{ kind }} }} }}", id(&bob_plan["coursePlan"]), term_id));
+    assert_eq!(code, "NOT_FOUND");
+}
+
+#[test]
+#[ignore]
+fn plan_diff() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, r#"mutation { createCoursePlan(params: {program: "uw-software-engineering_2018-2019_stream-8"}) { id terms { id name courses { id name } } } }"#);
+    let plan_id = id(&plan["createCoursePlan"]);
+    let terms = plan["createCoursePlan"]["terms"].as_array().expect("terms is not a list").clone();
+    let course = terms[0]["courses"].as_array().and_then(|courses| courses.iter().find(|course| course["name"] == "CS 137"))
+        .expect("the template has no CS 137 in its first term").clone();
+
+    let query = format!("{{ planDiff(right: {{coursePlanId: {}}}) {{ terms {{ leftName rightName addedCourses removedCourses movedCourses {{ name fromTerm }} }} }} }}", plan_id);
+    assert_eq!(app.ok(&alice, &query)["planDiff"]["terms"].as_array().map(Vec::len), Some(0));
+
+    app.ok(&alice, &format!("mutation {{ moveTermCourse(termCourseId: {}, termId: {}) {{ id }} }}", id(&course), id(&terms[1])));
+    app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "PHYS 121") {{ id }} }}"#, id(&terms[0])));
+    let diff = app.ok(&alice, &query);
+    let diff_terms = diff["planDiff"]["terms"].as_array().expect("terms is not a list");
+    assert_eq!(diff_terms.len(), 2);
+    assert_eq!(diff_terms[0]["addedCourses"][0], "PHYS 121");
+    assert_eq!(diff_terms[1]["movedCourses"][0]["name"], "CS 137");
+    assert_eq!(diff_terms[1]["movedCourses"][0]["fromTerm"], terms[0]["name"]);
+
+    // The course plan on the left side and the template on the right is the same diff backwards
+    let reversed = app.ok(&alice, &format!(r#"{{ planDiff(left: {{coursePlanId: {}}}, right: {{templateId: "uw-software-engineering_2018-2019_stream-8"}}) {{ terms {{ removedCourses }} }} }}"#, plan_id));
+    assert_eq!(reversed["planDiff"]["terms"][0]["removedCourses"], diff_terms[0]["addedCourses"]);
+    let code = app.error_code(&alice, r#"{ planDiff(right: {coursePlanId: 1, templateId: "x"}) { terms { leftName } } }"#);
+    assert_eq!(code, "VALIDATION");
+
+    let mut response = app.client.get(format!("/plans/{}/diff?against=source", plan_id))
+        .header(Header::new("Authorization", format!("Bearer {}", alice.token)))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let markdown = response.body_string().unwrap_or_default();
+    assert!(markdown.contains("- Added PHYS 121"), "unexpected diff: {}", markdown);
+    assert!(markdown.contains("- Moved CS 137 from"), "unexpected diff: {}", markdown);
+
+    let bob = app.user("Bob");
+    let response = app.client.get(format!("/plans/{}/diff?against=source", plan_id))
+        .header(Header::new("Authorization", format!("Bearer {}", bob.token)))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[ignore]
+fn remap_course_plan() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+    let data = r"term,course\n1A F18,CS 137\n1B W40,CS 138\n1B W40,PHIL 145";
+    app.ok(&alice, &format!(r#"mutation {{ importIntoCoursePlan(coursePlanId: {}, data: "{}", mode: APPEND) {{ addedTerms }} }}"#, plan_id, data));
+    let before = plan_terms(&app, &alice);
+
+    let remapped = app.ok(&alice, &format!(r#"mutation {{ remapCoursePlan(coursePlanId: {}, targetTemplate: "uw-software-engineering_2018-2019_stream-8") {{
+        keptTerms removedTerms addedTerms carriedOverCourses droppedCourses
+    }} }}"#, plan_id));
+    let summary = &remapped["remapCoursePlan"];
+    assert_eq!(summary["keptTerms"], json_strings(&["1A F18"]));
+    assert_eq!(summary["removedTerms"], json_strings(&["1B W40"]));
+    assert_eq!(summary["addedTerms"][0], "1B W19");
+    assert_eq!(summary["carriedOverCourses"], json_strings(&["CS 138 (1B W19)"]));
+    assert_eq!(summary["droppedCourses"], json_strings(&["PHIL 145 (1B W40)"]));
+
+    let terms = plan_terms(&app, &alice);
+    assert_eq!(terms[0], ("1A F18".to_string(), vec!["CS 137".to_string()]));
+    assert_eq!(terms[1].0, "1B W19");
+    assert!(terms[1].1.contains(&"CS 138".to_string()));
+    let template = app.ok(&alice, "{ coursePlan(default: true) { templateId } }");
+    assert_eq!(template["coursePlan"]["templateId"], "uw-software-engineering_2018-2019_stream-8");
+
+    // Undo reverts every term and course that the remap changed at once
+    app.ok(&alice, &format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id));
+    assert_eq!(plan_terms(&app, &alice), before);
+
+    let code = app.error_code(&alice, &format!(r#"mutation {{ remapCoursePlan(coursePlanId: {}, targetTemplate: "../Cargo") {{ keptTerms }} }}"#, plan_id));
+    assert_eq!(code, "VALIDATION");
+}
+
+#[test]
+#[ignore]
+fn import_into_course_plan() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+
+    let data = app.ok(&alice, &format!(
+        r#"mutation {{ importIntoCoursePlan(coursePlanId: {}, data: "term,course\n1A,CS 137\n1A,MATH 135\n1B,CS 138", mode: APPEND) {{ addedTerms addedCourses }} }}"#,
+        plan_id,
+    ));
+    assert_eq!(data["importIntoCoursePlan"]["addedTerms"].as_array().map(Vec::len), Some(2));
+    assert_eq!(data["importIntoCoursePlan"]["addedCourses"].as_array().map(Vec::len), Some(3));
+    assert_eq!(plan_terms(&app, &alice), vec![
+        ("1A".to_string(), vec!["CS 137".to_string(), "MATH 135".to_string()]),
+        ("1B".to_string(), vec!["CS 138".to_string()]),
+    ]);
+
+    let code = app.error_code(&alice, &format!(
+        r#"mutation {{ importIntoCoursePlan(coursePlanId: {}, data: "{{not json", mode: APPEND) {{ addedTerms }} }}"#,
+        plan_id,
+    ));
+    assert_eq!(code, "VALIDATION");
+}
+
+#[test]
+#[ignore]
+fn share_links() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+
+    let code = app.error_code(&alice, &format!("mutation {{ createShareLink(coursePlanId: {}, expiresInDays: 0) {{ id }} }}", plan_id));
+    assert_eq!(code, "VALIDATION");
+    let code = app.error_code(&alice, &format!("mutation {{ createShareLink(coursePlanId: {}, expiresInDays: 2000000000) {{ id }} }}", plan_id));
+    assert_eq!(code, "VALIDATION");
+
+    let link = app.ok(&alice, &format!("mutation {{ createShareLink(coursePlanId: {}) {{ id token revoked }} }}", plan_id));
+    let link_id = id(&link["createShareLink"]);
+    assert_eq!(link["createShareLink"]["revoked"], false);
+    let token = link["createShareLink"]["token"].as_str().expect("share link has no token").to_string();
+    assert_eq!(app.client.get(format!("/shared/{}", token)).dispatch().status(), Status::Ok);
+
+    // The history and change feed of the course plan are only available to its owner
+    for field in &["history { kind }", "changesSince(revision: 0) { kind }"] {
+        let body = serde_json::to_string(&GraphQLRequest {query: &format!("{{ coursePlan {{ id {} }} }}", field)}).unwrap();
+        let mut response = app.client.post(format!("/shared/{}/graphql", token))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        let body: Value = serde_json::from_str(&response.body_string().unwrap_or_default()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN", "{} through a share link: {}", field, body);
+    }
+
+    let revoked = app.ok(&alice, &format!("mutation {{ revokeShareLink(shareLinkId: {}) {{ revoked }} }}", link_id));
+    assert_eq!(revoked["revokeShareLink"]["revoked"], true);
+    assert_eq!(app.client.get(format!("/shared/{}", token)).dispatch().status(), Status::NotFound);
+}
+
+#[test]
+#[ignore]
+fn logout_everywhere() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let alice_phone = app.session(&alice.user);
+    let bob = app.user("Bob");
+
+    let data = app.ok(&alice, "mutation { logoutEverywhere }");
+    assert_eq!(data["logoutEverywhere"], 2);
+
+    assert_eq!(app.graphql(&alice, "{ me { id } }").0, Status::Forbidden);
+    assert_eq!(app.graphql(&alice_phone, "{ me { id } }").0, Status::Forbidden);
+    // The sessions of other users are not affected
+    app.ok(&bob, "{ me { id } }");
+}
+
+#[test]
+#[ignore]
+fn admin_only_fields() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let admin = app.admin("Admin");
+
+    assert_eq!(app.error_code(&alice, "{ admin { users { id } } }"), "FORBIDDEN");
+    let code = app.error_code(&alice, &format!("mutation {{ admin {{ disableUser(userId: {}) {{ id }} }} }}", admin.user.id));
+    assert_eq!(code, "FORBIDDEN");
+
+    let code = app.error_code(&admin, &format!("mutation {{ admin {{ disableUser(userId: {}) {{ id }} }} }}", admin.user.id));
+    assert_eq!(code, "VALIDATION");
+
+    let disabled = app.ok(&admin, &format!("mutation {{ admin {{ disableUser(userId: {}) {{ disabledAt }} }} }}", alice.user.id));
+    assert!(disabled["admin"]["disableUser"]["disabledAt"].is_string());
+    // Disabling a user revokes all of their sessions
+    let (status, _) = app.graphql(&alice, "{ me { id } }");
+    assert_eq!(status, Status::Forbidden);
+
+    let enabled = app.ok(&admin, &format!("mutation {{ admin {{ enableUser(userId: {}) {{ disabledAt }} }} }}", alice.user.id));
+    assert!(enabled["admin"]["enableUser"]["disabledAt"].is_null());
+
+    let code = app.error_code(&admin, "mutation { admin { enableUser(userId: -1) { id } } }");
+    assert_eq!(code, "NOT_FOUND");
+}
+
+#[test]
+#[ignore]
+fn users_cannot_access_the_data_of_other_users() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+    let bob = app.user("Bob");
+    let bob_plan = app.ok(&bob, "mutation { createCoursePlan(params: {}) { id } }");
+    let bob_term = app.ok(&bob, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, id(&bob_plan["createCoursePlan"])));
+    let bob_term_id = id(&bob_term["createTerm"]);
+
+    let plan = app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+    let plan_id = id(&plan["createCoursePlan"]);
+    let term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1A") {{ id }} }}"#, plan_id));
+    let term_id = id(&term["createTerm"]);
+    let course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "CS 137") {{ id }} }}"#, term_id));
+    let course_id = id(&course["createTermCourse"]);
+    let deleted_term = app.ok(&alice, &format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "1B") {{ id }} }}"#, plan_id));
+    let deleted_term_id = id(&deleted_term["createTerm"]);
+    app.ok(&alice, &format!("mutation {{ deleteTerm(termId: {}) {{ id }} }}", deleted_term_id));
+    let deleted_course = app.ok(&alice, &format!(r#"mutation {{ createTermCourse(termId: {}, name: "MATH 135") {{ id }} }}"#, term_id));
+    let deleted_course_id = id(&deleted_course["createTermCourse"]);
+    app.ok(&alice, &format!("mutation {{ deleteTermCourse(termCourseId: {}) {{ id }} }}", deleted_course_id));
+    let history = app.ok(&alice, "{ coursePlan(default: true) { history { id } } }");
+    let revision_id = id(&history["coursePlan"]["history"][0]);
+    let link = app.ok(&alice, &format!("mutation {{ createShareLink(coursePlanId: {}) {{ id }} }}", plan_id));
+    let link_id = id(&link["createShareLink"]);
+
+    let before = plan_terms(&app, &alice);
+    let attempts = vec![
+        format!(r#"mutation {{ createTerm(coursePlanId: {}, name: "2A") {{ id }} }}"#, plan_id),
+        format!("mutation {{ deleteTerm(termId: {}) {{ id }} }}", term_id),
+        format!(r#"mutation {{ renameTerm(termId: {}, name: "2A") {{ id }} }}"#, term_id),
+        format!("mutation {{ moveTerm(termId: {}, position: 0) {{ id }} }}", term_id),
+        format!(r#"mutation {{ createTermCourse(termId: {}, name: "ECE 105") {{ id }} }}"#, term_id),
+        format!("mutation {{ deleteTermCourse(termCourseId: {}) {{ id }} }}", course_id),
+        format!("mutation {{ moveTermCourse(termCourseId: {}, termId: {}) {{ id }} }}", course_id, term_id),
+        // Alice's course cannot be taken into Bob's own term either
+        format!("mutation {{ moveTermCourse(termCourseId: {}, termId: {}) {{ id }} }}", course_id, bob_term_id),
+        format!("mutation {{ restoreTerm(termId: {}) {{ id }} }}", deleted_term_id),
+        format!("mutation {{ restoreTermCourse(termCourseId: {}) {{ id }} }}", deleted_course_id),
+        format!(r#"mutation {{ importIntoCoursePlan(coursePlanId: {}, data: "term,course\n2A,ECE 105", mode: REPLACE) {{ addedTerms }} }}"#, plan_id),
+        format!(r#"mutation {{ remapCoursePlan(coursePlanId: {}, targetTemplate: "uw-software-engineering_2018-2019_stream-8") {{ keptTerms }} }}"#, plan_id),
+        format!("mutation {{ undo(coursePlanId: {}) {{ id }} }}", plan_id),
+        format!("mutation {{ restoreRevision(revisionId: {}) {{ id }} }}", revision_id),
+        format!("mutation {{ createShareLink(coursePlanId: {}) {{ id }} }}", plan_id),
+        format!("mutation {{ revokeShareLink(shareLinkId: {}) {{ id }} }}", link_id),
+        format!("mutation {{ deleteCoursePlan(coursePlanId: {}) }}", plan_id),
+    ];
+    for attempt in &attempts {
+        assert_eq!(app.error_code(&bob, attempt), "NOT_FOUND", "{}", attempt);
+    }
+
+    // Nothing that Bob tried changed Alice's course plan or her share link
+    assert_eq!(plan_terms(&app, &alice), before);
+    let links = app.ok(&alice, "{ coursePlan(default: true) { shareLinks { revoked } } }");
+    assert_eq!(links["coursePlan"]["shareLinks"][0]["revoked"], false);
+    // Bob's own course plan still only has his empty term
+    assert_eq!(plan_terms(&app, &bob), vec![("1A".to_string(), Vec::new())]);
+}
+
+#[test]
+#[ignore]
+fn health_and_metrics() {
+    let app = TestApp::start();
+    let alice = app.user("Alice");
+
+    assert_eq!(app.client.get("/healthz").dispatch().status(), Status::Ok);
+    assert_eq!(app.client.get("/readyz").dispatch().status(), Status::Ok);
+
+    app.ok(&alice, "{ me { id } }");
+    app.error_code(&alice, "{ coursePlan(default: false) { id } }");
+
+    let metrics = app.client.get("/metrics").dispatch().body_string().unwrap_or_default();
+    assert!(metrics.contains(r#"http_requests_total{method="POST",route="/graphql",status="200"}"#), "{}", metrics);
+    assert!(metrics.contains(r#"graphql_operation_duration_seconds_count{operation="anonymous"}"#), "{}", metrics);
+    assert!(metrics.contains(r#"graphql_errors_total{code="VALIDATION"} 1"#), "{}", metrics);
+    assert!(metrics.contains("db_pool_max_connections 1"), "{}", metrics);
+}
+
+#[test]
+#[ignore]
+fn request_ids() {
+    let app = TestApp::start();
+
+    let response = app.client.get("/healthz").dispatch();
+    let generated = response.headers().get_one("X-Request-Id").map(String::from);
+    assert_eq!(generated.as_ref().map(String::len), Some(16));
+
+    let response = app.client.get("/healthz").header(Header::new("X-Request-Id", "proxy-1234")).dispatch();
+    assert_eq!(response.headers().get_one("X-Request-Id"), Some("proxy-1234"));
+
+    // IDs that could garble the logs are replaced
+    let response = app.client.get("/healthz").header(Header::new("X-Request-Id", "bad id\"")).dispatch();
+    let replaced = response.headers().get_one("X-Request-Id").unwrap_or_default();
+    assert_ne!(replaced, "bad id\"");
+    assert_eq!(replaced.len(), 16);
+}
+
+#[test]
+#[ignore]
+fn rate_limits() {
+    let app = TestApp::start_with(|config| config.limits.requests_per_minute = 2);
+    let alice = app.user("Alice");
+    let bob = app.user("Bob");
+
+    app.ok(&alice, "{ me { id } }");
+    app.ok(&alice, "{ me { id } }");
+    let body = serde_json::to_string(&GraphQLRequest {query: "{ me { id } }"}).unwrap();
+    let mut response = app.client.post("/graphql")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("Bearer {}", alice.token)))
+        .body(body)
+        .dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let retry_after: u64 = response.headers().get_one("Retry-After").and_then(|secs| secs.parse().ok())
+        .expect("missing Retry-After header");
+    assert!(retry_after >= 1 && retry_after <= 30, "{}", retry_after);
+    let body: Value = serde_json::from_str(&response.body_string().unwrap_or_default()).unwrap();
+    assert_eq!(body["retryAfter"], retry_after);
+
+    // Each user has their own limit
+    app.ok(&bob, "{ me { id } }");
+}
+
+#[test]
+#[ignore]
+fn query_cost_limits() {
+    let app = TestApp::start_with(|config| config.limits.max_query_depth = 3);
+    let alice = app.user("Alice");
+    app.ok(&alice, "mutation { createCoursePlan(params: {}) { id } }");
+
+    app.ok(&alice, "{ coursePlan(default: true) { terms { name } } }");
+    let (status, response) = app.graphql(&alice, "{ coursePlan(default: true) { terms { courses { name } } } }");
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(response["errors"][0]["extensions"]["code"], "QUERY_TOO_COMPLEX");
+    assert_eq!(response["data"], Value::Null);
+
+    let (status, response) = app.graphql(&alice, "{ ...Missing }");
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(response["errors"][0]["extensions"]["code"], "VALIDATION");
+
+    // Nesting this deep would overflow the stack if the query were parsed all the way through
+    let (status, response) = app.graphql(&alice, &"{a".repeat(100_000));
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(response["errors"][0]["extensions"]["code"], "QUERY_TOO_COMPLEX");
+
+    let (status, _) = app.graphql(&alice, &format!("{{ me {{ id }} }} #{}", "x".repeat(2 * 1024 * 1024)));
+    assert_eq!(status, Status::PayloadTooLarge);
+}
@@ -0,0 +1,219 @@
+//! Applying a list of edits to a course plan all at once.
+//!
+//! A single action in the UI (e.g. dragging a course into a new term) can take several edits. The
+//! edits are applied in order in a single transaction, so if any of them fails none of them are
+//! applied. Every edit is made through the `plan` module so it shows up in the history of the
+//! course plan like any other change. All of the edits are part of one revision of the course plan,
+//! so a single undo reverts all of them.
+//!
+//! An edit can refer to a term or course that an earlier edit in the same list created by the
+//! index of that edit, since the client does not know the ID of the new record yet.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use diesel::Connection;
+use diesel::result::Error as QueryError;
+
+use api::db::DbConnection;
+use models::{users::User, course_plans::CoursePlan, terms, term_courses};
+use plan;
+
+/// A term or course referred to by an operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ref {
+    /// A term or course that already exists, by its ID
+    Existing(i32),
+    /// The term or course created by the operation at the given index
+    Created(usize),
+}
+
+/// A single edit to a course plan. Positions are indexes among the terms of the course plan.
+#[derive(Debug, Clone)]
+pub enum Operation {
+    /// Adds a term, at the end of the course plan unless a position is given
+    CreateTerm {name: String, position: Option<usize>},
+    RenameTerm {term: Ref, name: String},
+    MoveTerm {term: Ref, position: usize},
+    DeleteTerm {term: Ref},
+    CreateCourse {term: Ref, name: String},
+    RenameCourse {course: Ref, name: String},
+    MoveCourse {course: Ref, term: Ref},
+    DeleteCourse {course: Ref},
+}
+
+/// What an operation changed
+#[derive(Debug, Clone)]
+pub enum Applied {
+    /// The term that was created, renamed or moved
+    Term(terms::Term),
+    /// The term that was deleted along with its courses
+    DeletedTerm(terms::Term, Vec<term_courses::TermCourse>),
+    /// The course that was created, renamed, moved or deleted
+    Course(term_courses::TermCourse),
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    /// The operation at the given index refers to a term or course that is not in the course plan
+    NotFound {index: usize, message: String},
+    /// The operation at the given index cannot be applied
+    Invalid {index: usize, message: String},
+    Query(QueryError),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatchError::NotFound {index, message} |
+            BatchError::Invalid {index, message} => write!(f, "Operation {}: {}", index, message),
+            BatchError::Query(err) => write!(f, "Database error: {}", err),
+        }
+    }
+}
+
+impl From<QueryError> for BatchError {
+    fn from(err: QueryError) -> Self {
+        BatchError::Query(err)
+    }
+}
+
+/// The terms and courses of the course plan as the operations change them
+struct Batch<'a> {
+    conn: &'a DbConnection,
+    user: &'a User,
+    course_plan: &'a CoursePlan,
+    terms: Vec<terms::Term>,
+    courses: Vec<term_courses::TermCourse>,
+    /// The IDs of the terms and courses created by each operation, by the index of the operation
+    created_terms: HashMap<usize, i32>,
+    created_courses: HashMap<usize, i32>,
+}
+
+/// Applies each of the operations to the course plan in order. All of the operations happen in a
+/// single transaction, so either all of them are applied or none of them are. Returns what each
+/// operation changed in the same order as the operations.
+pub fn apply(
+    conn: &DbConnection,
+    user: &User,
+    course_plan: &CoursePlan,
+    operations: Vec<Operation>,
+) -> Result<Vec<Applied>, BatchError> {
+    conn.transaction(|| {
+        let terms = terms::all(conn, course_plan)?;
+        let courses = term_courses::all_for_terms(conn, &terms)?.into_iter().flatten().collect();
+        let mut batch = Batch {
+            conn,
+            user,
+            course_plan,
+            terms,
+            courses,
+            created_terms: HashMap::new(),
+            created_courses: HashMap::new(),
+        };
+
+        let mut applied = Vec::new();
+        for (index, operation) in operations.into_iter().enumerate() {
+            applied.push(batch.apply(index, operation)?);
+        }
+        Ok(applied)
+    })
+}
+
+impl<'a> Batch<'a> {
+    fn apply(&mut self, index: usize, operation: Operation) -> Result<Applied, BatchError> {
+        let conn = self.conn;
+        let user = self.user;
+        Ok(match operation {
+            Operation::CreateTerm {name, position} => {
+                let mut term = plan::create_term(conn, user, self.course_plan, name)?;
+                if let Some(position) = position {
+                    plan::move_term(conn, user, &term, position)?;
+                    term = terms::get(conn, term.id)?;
+                }
+                self.created_terms.insert(index, term.id);
+                self.terms.push(term.clone());
+                Applied::Term(term)
+            },
+            Operation::RenameTerm {term, name} => {
+                let term = self.term(index, term)?;
+                let renamed = plan::rename_term(conn, user, &self.terms[term], name)?;
+                self.terms[term] = renamed.clone();
+                Applied::Term(renamed)
+            },
+            Operation::MoveTerm {term, position} => {
+                let term = self.term(index, term)?;
+                plan::move_term(conn, user, &self.terms[term], position)?;
+                Applied::Term(terms::get(conn, self.terms[term].id)?)
+            },
+            Operation::DeleteTerm {term} => {
+                let term = self.term(index, term)?;
+                let term = self.terms.remove(term);
+                let courses = plan::delete_term(conn, user, &term)?;
+                self.courses.retain(|course| course.term_id != term.id);
+                Applied::DeletedTerm(term, courses)
+            },
+            Operation::CreateCourse {term, name} => {
+                let term = self.term(index, term)?;
+                let course = plan::create_term_course(conn, user, &self.terms[term], name)?;
+                self.created_courses.insert(index, course.id);
+                self.courses.push(course.clone());
+                Applied::Course(course)
+            },
+            Operation::RenameCourse {course, name} => {
+                let course = self.course(index, course)?;
+                let renamed = plan::rename_term_course(conn, user, &self.courses[course], name)?;
+                self.courses[course] = renamed.clone();
+                Applied::Course(renamed)
+            },
+            Operation::MoveCourse {course, term} => {
+                let course = self.course(index, course)?;
+                let term = self.term(index, term)?;
+                if self.courses[course].term_id != self.terms[term].id {
+                    let moved = plan::move_term_course(conn, user, &self.courses[course], &self.terms[term])?;
+                    self.courses[course] = moved;
+                }
+                Applied::Course(self.courses[course].clone())
+            },
+            Operation::DeleteCourse {course} => {
+                let course = self.course(index, course)?;
+                let course = self.courses.remove(course);
+                plan::delete_term_course(conn, user, &course)?;
+                Applied::Course(course)
+            },
+        })
+    }
+
+    /// Finds the index of the given term among the terms of the course plan
+    fn term(&self, index: usize, term: Ref) -> Result<usize, BatchError> {
+        let term_id = self.resolve(index, term, &self.created_terms, "term")?;
+        self.terms.iter().position(|term| term.id == term_id).ok_or_else(|| BatchError::NotFound {
+            index,
+            message: format!("Could not find term with ID {} in the course plan", term_id),
+        })
+    }
+
+    /// Finds the index of the given course among the courses of the course plan
+    fn course(&self, index: usize, course: Ref) -> Result<usize, BatchError> {
+        let course_id = self.resolve(index, course, &self.created_courses, "course")?;
+        self.courses.iter().position(|course| course.id == course_id).ok_or_else(|| BatchError::NotFound {
+            index,
+            message: format!("Could not find course with ID {} in the course plan", course_id),
+        })
+    }
+
+    /// Looks up the ID of a term or course. Only operations before this one have created anything
+    /// yet, so a later operation is never found.
+    fn resolve(&self, index: usize, reference: Ref, created: &HashMap<usize, i32>, what: &str) -> Result<i32, BatchError> {
+        match reference {
+            Ref::Existing(id) => Ok(id),
+            Ref::Created(earlier) => match created.get(&earlier) {
+                Some(&id) => Ok(id),
+                None => Err(BatchError::Invalid {
+                    index,
+                    message: format!("Operation {} is not an earlier operation that creates a {}", earlier, what),
+                }),
+            },
+        }
+    }
+}
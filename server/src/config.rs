@@ -0,0 +1,510 @@
+//! Typed configuration of the server.
+//!
+//! The configuration is read from a TOML file (Config.toml, or the file named by the CONFIG_FILE
+//! environment variable) and then any value can be overridden by an environment variable (which
+//! may also come from the .env file). Secrets like the secret key should only ever be set through
+//! the environment. The whole configuration is validated at startup so that a mistake is reported
+//! clearly instead of causing an error later on.
+//!
+//! | TOML key                           | Environment variable        | Default     |
+//! |------------------------------------|-----------------------------|-------------|
+//! | database.url                       | DATABASE_URL                | (required)  |
+//! | database.pool_size                 | DATABASE_POOL_SIZE          | 10          |
+//! | auth.secret_key                    | SECRET_KEY                  | (required)  |
+//! | auth.access_token_lifetime_mins    | ACCESS_TOKEN_LIFETIME_MINS  | 15          |
+//! | auth.refresh_token_lifetime_days   | REFRESH_TOKEN_LIFETIME_DAYS | 30          |
+//! | auth.google.client_id              | GOOGLE_CLIENT_ID            | (required)  |
+//! | auth.google.jwks_file              | GOOGLE_JWKS_FILE            | (none)      |
+//! | auth.github.client_id              | GITHUB_CLIENT_ID            | (none)      |
+//! | auth.github.client_secret          | GITHUB_CLIENT_SECRET        | (none)      |
+//! | cors.allowed_origins               | CORS_ALLOWED_ORIGINS        | (none)      |
+//! | templates.dir                      | TEMPLATE_DIR                | "templates" |
+//! | plans.deleted_retention_days       | DELETED_RETENTION_DAYS      | 30          |
+//! | logging.format ("text" or "json")  | LOG_FORMAT                  | "text"      |
+//! | limits.requests_per_minute         | RATE_LIMIT_PER_MINUTE       | 120         |
+//! | limits.auth_requests_per_minute    | AUTH_RATE_LIMIT_PER_MINUTE  | 10          |
+//! | limits.max_query_depth             | MAX_QUERY_DEPTH             | 15          |
+//! | limits.max_query_complexity        | MAX_QUERY_COMPLEXITY        | 300         |
+//!
+//! CORS_ALLOWED_ORIGINS is a comma-separated list of origins. Access tokens can last at most a day,
+//! and refresh tokens and deleted terms and courses at most ten years.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::Duration;
+use rocket_cors::AllowedOrigins;
+use toml;
+
+use logging::LogFormat;
+
+/// The file read when CONFIG_FILE is not set
+const DEFAULT_CONFIG_FILE: &str = "Config.toml";
+
+/// The longest allowed lifetimes and retention period. Durations much longer than these cannot be
+/// added to the current time without overflowing.
+const MAX_ACCESS_TOKEN_LIFETIME_MINS: i64 = 24 * 60;
+const MAX_REFRESH_TOKEN_LIFETIME_DAYS: i64 = 3650;
+const MAX_DELETED_RETENTION_DAYS: i64 = 3650;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read
+    Io(PathBuf, io::Error),
+    /// The configuration file is not valid TOML or has values of the wrong type
+    Toml(PathBuf, toml::de::Error),
+    /// A required value was not set in either the file or the environment
+    Missing {key: &'static str, var: &'static str},
+    /// A value was set but is not valid
+    Invalid {key: &'static str, var: &'static str, reason: String},
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "Unable to read configuration file {}: {}", path.display(), err),
+            ConfigError::Toml(path, err) => write!(f, "Invalid configuration file {}: {}", path.display(), err),
+            ConfigError::Missing {key, var} => write!(f, "Missing configuration: set {} in the configuration file or {} in the environment", key, var),
+            ConfigError::Invalid {key, var, reason} => write!(f, "Invalid configuration for {} ({}): {}", key, var, reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub cors: CorsConfig,
+    pub templates: TemplatesConfig,
+    pub plans: PlansConfig,
+    pub logging: LoggingConfig,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// The maximum number of connections in the connection pool
+    pub pool_size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// The key used to sign access tokens
+    pub secret_key: String,
+    pub access_token_lifetime: Duration,
+    /// How long a session lasts without being refreshed
+    pub refresh_token_lifetime: Duration,
+    pub google: GoogleConfig,
+    /// Logging in with GitHub is disabled if this is not configured
+    pub github: Option<GitHubConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GoogleConfig {
+    /// The OAuth client ID of the application. Google ID tokens must have been issued for it.
+    pub client_id: String,
+    /// Verify Google ID tokens against the key set (JWKS) in this file instead of the keys
+    /// published by Google (for testing only)
+    pub jwks_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitHubConfig {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// The origins allowed to make requests to the server from a browser
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TemplatesConfig {
+    /// The directory containing the program templates
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlansConfig {
+    /// How long deleted terms and courses can be restored before they are permanently removed
+    pub deleted_retention: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// The number of GraphQL requests each user (or client IP if not logged in) can make per
+    /// minute, including short bursts of up to this many requests
+    pub requests_per_minute: u32,
+    /// The number of login and refresh requests each client IP can make per minute
+    pub auth_requests_per_minute: u32,
+    /// The deepest that fields can be nested in a GraphQL query
+    pub max_query_depth: usize,
+    /// The most fields that a GraphQL query can select, counting each use of a fragment
+    pub max_query_complexity: usize,
+}
+
+/// The configuration file as written, before environment overrides and validation. Every value
+/// is optional here because it may be set in the environment instead.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    database: RawDatabase,
+    auth: RawAuth,
+    cors: RawCors,
+    templates: RawTemplates,
+    plans: RawPlans,
+    logging: RawLogging,
+    limits: RawLimits,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawDatabase {
+    url: Option<String>,
+    pool_size: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawAuth {
+    secret_key: Option<String>,
+    access_token_lifetime_mins: Option<i64>,
+    refresh_token_lifetime_days: Option<i64>,
+    google: RawGoogle,
+    github: RawGitHub,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawGoogle {
+    client_id: Option<String>,
+    jwks_file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawGitHub {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawCors {
+    allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawTemplates {
+    dir: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawPlans {
+    deleted_retention_days: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLogging {
+    format: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLimits {
+    requests_per_minute: Option<u32>,
+    auth_requests_per_minute: Option<u32>,
+    max_query_depth: Option<usize>,
+    max_query_complexity: Option<usize>,
+}
+
+impl Config {
+    /// Loads the configuration file (if it exists) and the environment
+    pub fn load() -> Result<Self, ConfigError> {
+        // A missing file is only an error if it was asked for explicitly
+        let (path, required) = match env::var("CONFIG_FILE") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref err) if !required && err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(ConfigError::Io(path, err)),
+        };
+
+        let mut raw: RawConfig = toml::from_str(&text)
+            .map_err(|err| ConfigError::Toml(path, err))?;
+
+        env_override(&mut raw.database.url, "database.url", "DATABASE_URL")?;
+        env_override(&mut raw.database.pool_size, "database.pool_size", "DATABASE_POOL_SIZE")?;
+        env_override(&mut raw.auth.secret_key, "auth.secret_key", "SECRET_KEY")?;
+        env_override(&mut raw.auth.access_token_lifetime_mins, "auth.access_token_lifetime_mins", "ACCESS_TOKEN_LIFETIME_MINS")?;
+        env_override(&mut raw.auth.refresh_token_lifetime_days, "auth.refresh_token_lifetime_days", "REFRESH_TOKEN_LIFETIME_DAYS")?;
+        env_override(&mut raw.auth.google.client_id, "auth.google.client_id", "GOOGLE_CLIENT_ID")?;
+        env_override(&mut raw.auth.google.jwks_file, "auth.google.jwks_file", "GOOGLE_JWKS_FILE")?;
+        env_override(&mut raw.auth.github.client_id, "auth.github.client_id", "GITHUB_CLIENT_ID")?;
+        env_override(&mut raw.auth.github.client_secret, "auth.github.client_secret", "GITHUB_CLIENT_SECRET")?;
+        env_override(&mut raw.templates.dir, "templates.dir", "TEMPLATE_DIR")?;
+        env_override(&mut raw.plans.deleted_retention_days, "plans.deleted_retention_days", "DELETED_RETENTION_DAYS")?;
+        env_override(&mut raw.logging.format, "logging.format", "LOG_FORMAT")?;
+        env_override(&mut raw.limits.requests_per_minute, "limits.requests_per_minute", "RATE_LIMIT_PER_MINUTE")?;
+        env_override(&mut raw.limits.auth_requests_per_minute, "limits.auth_requests_per_minute", "AUTH_RATE_LIMIT_PER_MINUTE")?;
+        env_override(&mut raw.limits.max_query_depth, "limits.max_query_depth", "MAX_QUERY_DEPTH")?;
+        env_override(&mut raw.limits.max_query_complexity, "limits.max_query_complexity", "MAX_QUERY_COMPLEXITY")?;
+        if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+            raw.cors.allowed_origins = Some(origins.split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect());
+        }
+
+        raw.validate()
+    }
+}
+
+/// Replaces a value from the configuration file with the value of an environment variable, if set
+fn env_override<T>(value: &mut Option<T>, key: &'static str, var: &'static str) -> Result<(), ConfigError>
+    where T: FromStr,
+          T::Err: fmt::Display,
+{
+    if let Ok(text) = env::var(var) {
+        let parsed = text.parse().map_err(|err: T::Err| invalid(key, var, err.to_string()))?;
+        *value = Some(parsed);
+    }
+    Ok(())
+}
+
+fn required<T>(value: Option<T>, key: &'static str, var: &'static str) -> Result<T, ConfigError> {
+    value.ok_or(ConfigError::Missing {key, var})
+}
+
+fn invalid<S: Into<String>>(key: &'static str, var: &'static str, reason: S) -> ConfigError {
+    ConfigError::Invalid {key, var, reason: reason.into()}
+}
+
+/// Returns an error unless the value is a positive number
+fn positive<T: PartialOrd + Default>(value: T, key: &'static str, var: &'static str) -> Result<T, ConfigError> {
+    if value <= T::default() {
+        return Err(invalid(key, var, "must be greater than zero"));
+    }
+    Ok(value)
+}
+
+/// Returns an error unless the value is a positive number no greater than max
+fn positive_at_most<T>(value: T, max: T, key: &'static str, var: &'static str) -> Result<T, ConfigError>
+    where T: PartialOrd + Default + fmt::Display,
+{
+    let value = positive(value, key, var)?;
+    if value > max {
+        return Err(invalid(key, var, format!("must be at most {}", max)));
+    }
+    Ok(value)
+}
+
+impl RawConfig {
+    fn validate(self) -> Result<Config, ConfigError> {
+        let RawConfig {database, auth, cors, templates, plans, logging, limits} = self;
+
+        let url = required(database.url, "database.url", "DATABASE_URL")?;
+        if url.trim().is_empty() {
+            return Err(invalid("database.url", "DATABASE_URL", "must not be empty"));
+        }
+        let pool_size = positive(database.pool_size.unwrap_or(10), "database.pool_size", "DATABASE_POOL_SIZE")?;
+
+        let secret_key = required(auth.secret_key, "auth.secret_key", "SECRET_KEY")?;
+        if secret_key.trim().is_empty() {
+            return Err(invalid("auth.secret_key", "SECRET_KEY", "must not be empty"));
+        }
+        let access_mins = positive_at_most(auth.access_token_lifetime_mins.unwrap_or(15), MAX_ACCESS_TOKEN_LIFETIME_MINS,
+            "auth.access_token_lifetime_mins", "ACCESS_TOKEN_LIFETIME_MINS")?;
+        let refresh_days = positive_at_most(auth.refresh_token_lifetime_days.unwrap_or(30), MAX_REFRESH_TOKEN_LIFETIME_DAYS,
+            "auth.refresh_token_lifetime_days", "REFRESH_TOKEN_LIFETIME_DAYS")?;
+        let access_token_lifetime = Duration::minutes(access_mins);
+        let refresh_token_lifetime = Duration::days(refresh_days);
+        if access_token_lifetime >= refresh_token_lifetime {
+            return Err(invalid("auth.access_token_lifetime_mins", "ACCESS_TOKEN_LIFETIME_MINS",
+                "access tokens must expire before refresh tokens"));
+        }
+
+        let client_id = required(auth.google.client_id, "auth.google.client_id", "GOOGLE_CLIENT_ID")?;
+        let jwks_file = auth.google.jwks_file.map(PathBuf::from);
+        if let Some(ref path) = jwks_file {
+            if !path.is_file() {
+                return Err(invalid("auth.google.jwks_file", "GOOGLE_JWKS_FILE",
+                    format!("{} is not a file", path.display())));
+            }
+        }
+        let google = GoogleConfig {client_id, jwks_file};
+
+        let github = match (auth.github.client_id, auth.github.client_secret) {
+            (Some(client_id), Some(client_secret)) => Some(GitHubConfig {client_id, client_secret}),
+            (None, None) => None,
+            (Some(_), None) => return Err(ConfigError::Missing {key: "auth.github.client_secret", var: "GITHUB_CLIENT_SECRET"}),
+            (None, Some(_)) => return Err(ConfigError::Missing {key: "auth.github.client_id", var: "GITHUB_CLIENT_ID"}),
+        };
+
+        let allowed_origins = cors.allowed_origins.unwrap_or_default();
+        for origin in &allowed_origins {
+            // Parsed the same way as when the CORS fairing is set up so that the server never
+            // starts with an origin that it cannot use
+            let (_, failed) = AllowedOrigins::some(&[origin.as_str()]);
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && !origin.ends_with('/')
+                && failed.is_empty();
+            if !valid {
+                return Err(invalid("cors.allowed_origins", "CORS_ALLOWED_ORIGINS",
+                    format!("'{}' is not an origin (e.g. https://uwcourseplan.com)", origin)));
+            }
+        }
+
+        let dir = PathBuf::from(templates.dir.unwrap_or_else(|| "templates".to_string()));
+        if !dir.is_dir() {
+            return Err(invalid("templates.dir", "TEMPLATE_DIR", format!("{} is not a directory", dir.display())));
+        }
+
+        let retention_days = positive_at_most(plans.deleted_retention_days.unwrap_or(30), MAX_DELETED_RETENTION_DAYS,
+            "plans.deleted_retention_days", "DELETED_RETENTION_DAYS")?;
+
+        let format = logging.format.as_ref().map_or("text", String::as_str).parse()
+            .map_err(|err: String| invalid("logging.format", "LOG_FORMAT", err))?;
+
+        let limits = LimitsConfig {
+            requests_per_minute: positive(limits.requests_per_minute.unwrap_or(120),
+                "limits.requests_per_minute", "RATE_LIMIT_PER_MINUTE")?,
+            auth_requests_per_minute: positive(limits.auth_requests_per_minute.unwrap_or(10),
+                "limits.auth_requests_per_minute", "AUTH_RATE_LIMIT_PER_MINUTE")?,
+            max_query_depth: positive(limits.max_query_depth.unwrap_or(15),
+                "limits.max_query_depth", "MAX_QUERY_DEPTH")?,
+            max_query_complexity: positive(limits.max_query_complexity.unwrap_or(300),
+                "limits.max_query_complexity", "MAX_QUERY_COMPLEXITY")?,
+        };
+
+        Ok(Config {
+            database: DatabaseConfig {url, pool_size},
+            auth: AuthConfig {
+                secret_key,
+                access_token_lifetime,
+                refresh_token_lifetime,
+                google,
+                github,
+            },
+            cors: CorsConfig {allowed_origins},
+            templates: TemplatesConfig {dir},
+            plans: PlansConfig {deleted_retention: Duration::days(retention_days)},
+            logging: LoggingConfig {format},
+            limits,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, RawConfig};
+
+    /// A configuration with only the required values set
+    fn raw() -> RawConfig {
+        let mut raw = RawConfig::default();
+        raw.database.url = Some("postgres://localhost/uwcourseplan".to_string());
+        raw.auth.secret_key = Some("secret".to_string());
+        raw.auth.google.client_id = Some("client".to_string());
+        raw
+    }
+
+    /// The key of the value that was invalid
+    fn invalid_key(raw: RawConfig) -> &'static str {
+        match raw.validate() {
+            Err(ConfigError::Invalid {key, ..}) => key,
+            result => panic!("expected the configuration to be invalid, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = raw().validate().expect("configuration is not valid");
+        assert_eq!(config.auth.access_token_lifetime.num_minutes(), 15);
+        assert_eq!(config.auth.refresh_token_lifetime.num_days(), 30);
+        assert_eq!(config.plans.deleted_retention.num_days(), 30);
+        assert_eq!(config.limits.max_query_depth, 15);
+    }
+
+    #[test]
+    fn missing_values() {
+        let mut config = raw();
+        config.database.url = None;
+        match config.validate() {
+            Err(ConfigError::Missing {var: "DATABASE_URL", ..}) => {},
+            result => panic!("expected DATABASE_URL to be missing, got {:?}", result),
+        }
+
+        let mut config = raw();
+        config.auth.github.client_id = Some("client".to_string());
+        match config.validate() {
+            Err(ConfigError::Missing {var: "GITHUB_CLIENT_SECRET", ..}) => {},
+            result => panic!("expected GITHUB_CLIENT_SECRET to be missing, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn values_must_be_positive() {
+        let mut config = raw();
+        config.auth.access_token_lifetime_mins = Some(0);
+        assert_eq!(invalid_key(config), "auth.access_token_lifetime_mins");
+
+        let mut config = raw();
+        config.plans.deleted_retention_days = Some(-1);
+        assert_eq!(invalid_key(config), "plans.deleted_retention_days");
+
+        let mut config = raw();
+        config.limits.max_query_depth = Some(0);
+        assert_eq!(invalid_key(config), "limits.max_query_depth");
+    }
+
+    #[test]
+    fn durations_have_upper_bounds() {
+        let mut config = raw();
+        config.auth.access_token_lifetime_mins = Some(24 * 60);
+        config.auth.refresh_token_lifetime_days = Some(3650);
+        config.plans.deleted_retention_days = Some(3650);
+        assert!(config.validate().is_ok());
+
+        let mut config = raw();
+        config.auth.access_token_lifetime_mins = Some(24 * 60 + 1);
+        assert_eq!(invalid_key(config), "auth.access_token_lifetime_mins");
+
+        // Large enough to overflow if it were turned into a duration
+        let mut config = raw();
+        config.auth.access_token_lifetime_mins = Some(i64::max_value());
+        assert_eq!(invalid_key(config), "auth.access_token_lifetime_mins");
+
+        let mut config = raw();
+        config.auth.refresh_token_lifetime_days = Some(3651);
+        assert_eq!(invalid_key(config), "auth.refresh_token_lifetime_days");
+
+        let mut config = raw();
+        config.plans.deleted_retention_days = Some(i64::max_value());
+        assert_eq!(invalid_key(config), "plans.deleted_retention_days");
+    }
+
+    #[test]
+    fn access_tokens_expire_before_refresh_tokens() {
+        let mut config = raw();
+        config.auth.access_token_lifetime_mins = Some(24 * 60);
+        config.auth.refresh_token_lifetime_days = Some(1);
+        assert_eq!(invalid_key(config), "auth.access_token_lifetime_mins");
+    }
+}
@@ -0,0 +1,318 @@
+//! Comparing two course plans (or templates) term by term.
+//!
+//! Terms are matched by their level and season (e.g. "1A F18" matches "1A F19" but not "1A W19")
+//! so that a course plan can be compared against a template for a different year. Terms without
+//! a date are matched by their whole name. Courses are matched by their course code, ignoring case
+//! and spaces, so "CS 137" and "cs137" are the same course.
+//!
+//! A course that is in a different term on each side is reported as moved in the term it was
+//! moved to. A term that is only on one side has all of its courses reported as added or removed.
+
+use std::fmt::{self, Write};
+
+use diesel::result::{QueryResult, Error as QueryError};
+
+use api::db::DbConnection;
+use models::{users::User, course_plans};
+use template::{CoursePlanTemplate, TemplateError, Templates};
+use term_name::{TermName, Season};
+use export::{self, escape_markdown};
+
+/// A course plan or template to compare
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Side {
+    CoursePlan(i32),
+    Template(String),
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::CoursePlan(id) => write!(f, "course plan {}", id),
+            Side::Template(id) => write!(f, "template {}", id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DiffError {
+    /// The course plan does not exist or does not belong to the user
+    Query(QueryError),
+    Template(TemplateError),
+}
+
+impl From<QueryError> for DiffError {
+    fn from(err: QueryError) -> Self {
+        DiffError::Query(err)
+    }
+}
+
+impl From<TemplateError> for DiffError {
+    fn from(err: TemplateError) -> Self {
+        DiffError::Template(err)
+    }
+}
+
+/// Loads the terms and courses of one side of a comparison. Only the course plans of the given
+/// user can be loaded.
+pub fn load(conn: &DbConnection, user: &User, templates: &Templates, side: &Side) -> Result<CoursePlanTemplate, DiffError> {
+    match side {
+        Side::CoursePlan(id) => {
+            let course_plan = course_plans::get(conn, *id, user)?;
+            Ok(export::template(conn, &course_plan)?)
+        },
+        Side::Template(id) => Ok(CoursePlanTemplate::from_template(templates, id)?),
+    }
+}
+
+/// The template that a course plan of the given user was created from, if it was created from one
+pub fn source_template(conn: &DbConnection, user: &User, course_plan_id: i32) -> QueryResult<Option<Side>> {
+    let course_plan = course_plans::get(conn, course_plan_id, user)?;
+    Ok(course_plan.template_id.map(Side::Template))
+}
+
+/// A course that is in a different term on each side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovedCourse {
+    /// The name of the course on the right side
+    pub name: String,
+    /// The name of the term that the course is in on the left side
+    pub from: String,
+}
+
+/// The differences between the courses of a term on each side
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TermDiff {
+    /// The name of the term on the left side, if it is there
+    pub left: Option<String>,
+    /// The name of the term on the right side, if it is there
+    pub right: Option<String>,
+    /// The courses that are only in this term on the right side
+    pub added: Vec<String>,
+    /// The courses that are only in this term on the left side
+    pub removed: Vec<String>,
+    /// The courses that are in this term on the right side but in another term on the left side
+    pub moved: Vec<MovedCourse>,
+}
+
+impl TermDiff {
+    /// The name of the term, preferring the right side
+    pub fn name(&self) -> &str {
+        self.right.as_ref().or(self.left.as_ref()).map_or("", String::as_str)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.left.is_some() && self.right.is_some() &&
+            self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// The differences between two course plans. Only the terms that differ are included, in the
+/// order of the right side with terms that are only on the left side after the term they follow
+/// on the left side.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanDiff {
+    pub terms: Vec<TermDiff>,
+}
+
+/// The part of a term name that terms are matched by
+fn term_key(name: &str) -> (String, Option<Season>) {
+    let name = TermName::parse(name);
+    let label = name.label.split_whitespace().collect::<Vec<_>>().join(" ").to_uppercase();
+    (label, name.date.map(|date| date.season))
+}
+
+/// The part of a course name that courses are matched by
+pub fn course_key(name: &str) -> String {
+    name.split_whitespace().collect::<String>().to_uppercase()
+}
+
+/// A term on one or both sides
+struct Slot {
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// A course on one side that has not been matched with the other side yet
+struct Course<'a> {
+    key: String,
+    name: &'a str,
+    slot: usize,
+    matched: bool,
+}
+
+fn courses<'a>(plan: &'a CoursePlanTemplate, slots: &[Slot], side: fn(&Slot) -> Option<usize>) -> Vec<Course<'a>> {
+    let mut courses = Vec::new();
+    for (slot, term) in slots.iter().enumerate() {
+        if let Some(term) = side(term) {
+            for course in &plan.terms[term].courses {
+                courses.push(Course {key: course_key(&course.name), name: &course.name, slot, matched: false});
+            }
+        }
+    }
+    courses
+}
+
+/// Finds the differences between the left and right course plans
+pub fn diff(left: &CoursePlanTemplate, right: &CoursePlanTemplate) -> PlanDiff {
+    // Terms with the same key are matched in the order that they appear on each side
+    let left_keys: Vec<_> = left.terms.iter().map(|term| term_key(&term.name)).collect();
+    let mut left_matched = vec![false; left.terms.len()];
+    let mut slots = Vec::new();
+    for (j, term) in right.terms.iter().enumerate() {
+        let key = term_key(&term.name);
+        let matching = (0..left.terms.len()).find(|&i| !left_matched[i] && left_keys[i] == key);
+        if let Some(i) = matching {
+            left_matched[i] = true;
+        }
+        slots.push(Slot {left: matching, right: Some(j)});
+    }
+    let mut previous = None;
+    for i in 0..left.terms.len() {
+        if !left_matched[i] {
+            let position = previous.map_or(0, |slot| slot + 1);
+            slots.insert(position, Slot {left: Some(i), right: None});
+        }
+        previous = slots.iter().position(|slot| slot.left == Some(i));
+    }
+
+    let mut terms: Vec<_> = slots.iter().map(|slot| TermDiff {
+        left: slot.left.map(|i| left.terms[i].name.clone()),
+        right: slot.right.map(|i| right.terms[i].name.clone()),
+        ..TermDiff::default()
+    }).collect();
+    let mut left_courses = courses(left, &slots, |slot| slot.left);
+    let mut right_courses = courses(right, &slots, |slot| slot.right);
+
+    // Courses in the same term on both sides are unchanged. Anything left over that is on both
+    // sides was moved.
+    for same_term in &[true, false] {
+        for course in right_courses.iter_mut().filter(|course| !course.matched) {
+            let matching = left_courses.iter_mut()
+                .find(|other| !other.matched && other.key == course.key && (other.slot == course.slot) == *same_term);
+            if let Some(other) = matching {
+                other.matched = true;
+                course.matched = true;
+                if !*same_term {
+                    let from = terms[other.slot].left.clone().unwrap_or_default();
+                    terms[course.slot].moved.push(MovedCourse {name: course.name.to_string(), from});
+                }
+            }
+        }
+    }
+    for course in right_courses.iter().filter(|course| !course.matched) {
+        terms[course.slot].added.push(course.name.to_string());
+    }
+    for course in left_courses.iter().filter(|course| !course.matched) {
+        terms[course.slot].removed.push(course.name.to_string());
+    }
+
+    PlanDiff {terms: terms.into_iter().filter(|term| !term.is_empty()).collect()}
+}
+
+/// Describes the differences as Markdown, with the given titles for each side
+pub fn to_markdown(diff: &PlanDiff, left_title: &str, right_title: &str) -> Vec<u8> {
+    let mut out = format!(
+        "# Course Plan Diff\n\nChanges from {} to {}\n",
+        escape_markdown(left_title),
+        escape_markdown(right_title),
+    );
+    if diff.terms.is_empty() {
+        out.push_str("\n_No differences_\n");
+    }
+    for term in &diff.terms {
+        // Writing to a String cannot fail
+        let _ = match (&term.left, &term.right) {
+            (Some(left), Some(right)) if left != right => {
+                write!(out, "\n## {} _(was {})_\n\n", escape_markdown(right), escape_markdown(left))
+            },
+            (Some(_), None) => write!(out, "\n## {} _(removed)_\n\n", escape_markdown(term.name())),
+            (None, Some(_)) => write!(out, "\n## {} _(added)_\n\n", escape_markdown(term.name())),
+            _ => write!(out, "\n## {}\n\n", escape_markdown(term.name())),
+        };
+
+        for course in &term.added {
+            let _ = writeln!(out, "- Added {}", escape_markdown(course));
+        }
+        for course in &term.removed {
+            let _ = writeln!(out, "- Removed {}", escape_markdown(course));
+        }
+        for course in &term.moved {
+            let _ = writeln!(out, "- Moved {} from {}", escape_markdown(&course.name), escape_markdown(&course.from));
+        }
+    }
+
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use template::names;
+
+    #[test]
+    fn identical_plans() {
+        let left = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137", "MATH 135"]), ("Co-op 1", &[])]);
+        let right = CoursePlanTemplate::from_names(&[("1a F19", &["cs137", "MATH 135"]), ("Co-op 1", &[])]);
+        assert_eq!(diff(&left, &right), PlanDiff::default());
+    }
+
+    #[test]
+    fn added_removed_and_moved_courses() {
+        let left = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137", "MATH 135"]), ("1B W19", &["CS 138"])]);
+        let right = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137", "CS 138"]), ("1B W19", &["MATH 136"])]);
+        assert_eq!(diff(&left, &right).terms, vec![
+            TermDiff {
+                left: Some("1A F18".to_string()),
+                right: Some("1A F18".to_string()),
+                added: vec![],
+                removed: names(&["MATH 135"]),
+                moved: vec![MovedCourse {name: "CS 138".to_string(), from: "1B W19".to_string()}],
+            },
+            TermDiff {
+                left: Some("1B W19".to_string()),
+                right: Some("1B W19".to_string()),
+                added: names(&["MATH 136"]),
+                removed: vec![],
+                moved: vec![],
+            },
+        ]);
+    }
+
+    #[test]
+    fn terms_on_one_side() {
+        let left = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137"]), ("1B W19", &["CS 138"]), ("2A S19", &[])]);
+        let right = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137"]), ("2A S19", &[]), ("2B F19", &["CS 241"])]);
+        let terms = diff(&left, &right).terms;
+        assert_eq!(terms.len(), 2);
+        assert_eq!((terms[0].left.as_ref().map(String::as_str), terms[0].right.as_ref()), (Some("1B W19"), None));
+        assert_eq!(terms[0].removed, names(&["CS 138"]));
+        assert_eq!((terms[1].left.as_ref(), terms[1].right.as_ref().map(String::as_str)), (None, Some("2B F19")));
+        assert_eq!(terms[1].added, names(&["CS 241"]));
+    }
+
+    #[test]
+    fn seasons_must_match() {
+        let left = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137"])]);
+        let right = CoursePlanTemplate::from_names(&[("1A W19", &["CS 137"])]);
+        let terms = diff(&left, &right).terms;
+        assert_eq!(terms.len(), 2);
+        assert_eq!(terms[1].moved, vec![MovedCourse {name: "CS 137".to_string(), from: "1A F18".to_string()}]);
+    }
+
+    #[test]
+    fn markdown() {
+        let left = CoursePlanTemplate::from_names(&[("1A F18", &["CS 137", "MATH 135"])]);
+        let right = CoursePlanTemplate::from_names(&[("1A F19", &["CS 137", "MATH 145", "*ELECTIVE_1*"])]);
+        let markdown = String::from_utf8(to_markdown(&diff(&left, &right), "left", "right")).unwrap();
+        assert_eq!(markdown, "# Course Plan Diff\n\nChanges from left to right\n\n## 1A F19 _(was 1A F18)_\n\n- Added MATH 145\n- Added \\*ELECTIVE\\_1\\*\n- Removed MATH 135\n");
+    }
+
+    #[test]
+    fn markdown_escaping() {
+        assert_eq!(escape_markdown("CS 137"), "CS 137");
+        assert_eq!(escape_markdown("# [CS 137](x)"), "\\# \\[CS 137\\](x)");
+        assert_eq!(escape_markdown("- Co-op 1"), "\\- Co-op 1");
+        assert_eq!(escape_markdown("1. CS 137"), "1\\. CS 137");
+    }
+}
@@ -0,0 +1,142 @@
+//! The errors that can be returned from a GraphQL field.
+//!
+//! Every error is returned to the client with a machine-readable `code` extension alongside the
+//! usual message and path of the field that failed:
+//!
+//! ```json
+//! {"message": "Could not find term with ID 3", "path": ["deleteTerm"], "extensions": {"code": "NOT_FOUND"}}
+//! ```
+//!
+//! Internal errors (e.g. a failed database query) are logged and replaced with a generic message
+//! so that no details about the server leak to the client.
+
+use diesel::result::{Error as QueryError, DatabaseErrorKind};
+use juniper::{FieldError, FieldResult, Value};
+use ring::error::Unspecified;
+use serde_json::error::Error as SerdeError;
+
+use batch::BatchError;
+use diff::DiffError;
+use import::ImportError;
+use logging;
+use plan::StaleRevision;
+use template::TemplateError;
+
+/// Error is intentionally not Display so that it is never converted into a FieldError through
+/// its message alone (which would leave out the code)
+#[derive(Debug)]
+pub enum Error {
+    /// The requested data does not exist or does not belong to the current user
+    NotFound(String),
+    /// The current user is not allowed to do this
+    Forbidden(String),
+    /// The input provided by the client is invalid
+    Validation(String),
+    /// The request conflicts with the current state of the data
+    Conflict(String),
+    /// Something went wrong on the server. The details are logged, never returned.
+    Internal(String),
+}
+
+impl Error {
+    /// The machine-readable code returned in the extensions of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "NOT_FOUND",
+            Error::Forbidden(_) => "FORBIDDEN",
+            Error::Validation(_) => "VALIDATION",
+            Error::Conflict(_) => "CONFLICT",
+            Error::Internal(_) => "INTERNAL",
+        }
+    }
+}
+
+impl From<Error> for FieldError {
+    fn from(err: Error) -> Self {
+        let code = err.code();
+        let message = match err {
+            Error::NotFound(message) |
+            Error::Forbidden(message) |
+            Error::Validation(message) |
+            Error::Conflict(message) => message,
+            Error::Internal(details) => {
+                logging::error("Internal error in GraphQL field").field("details", details).log();
+                "An internal error occurred".to_string()
+            },
+        };
+
+        let extensions = vec![("code", Value::string(code))].into_iter().collect();
+        FieldError::new(message, Value::object(extensions))
+    }
+}
+
+impl From<StaleRevision> for Error {
+    fn from(StaleRevision {expected, current}: StaleRevision) -> Self {
+        Error::Conflict(format!("The course plan has changed: it is at revision {} but the change was made to revision {}", current, expected))
+    }
+}
+
+impl From<QueryError> for Error {
+    fn from(err: QueryError) -> Self {
+        match err {
+            QueryError::NotFound => Error::NotFound("The requested data could not be found".to_string()),
+            QueryError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                Error::Conflict("The data conflicts with data that already exists".to_string())
+            },
+            err => Error::Internal(format!("Database error: {}", err)),
+        }
+    }
+}
+
+impl From<ImportError> for Error {
+    fn from(err: ImportError) -> Self {
+        Error::Validation(err.to_string())
+    }
+}
+
+impl From<BatchError> for Error {
+    fn from(err: BatchError) -> Self {
+        match err {
+            BatchError::NotFound {..} => Error::NotFound(err.to_string()),
+            BatchError::Invalid {..} => Error::Validation(err.to_string()),
+            BatchError::Query(err) => err.into(),
+        }
+    }
+}
+
+impl From<DiffError> for Error {
+    fn from(err: DiffError) -> Self {
+        match err {
+            DiffError::Query(err) => err.into(),
+            DiffError::Template(err) => err.into(),
+        }
+    }
+}
+
+impl From<TemplateError> for Error {
+    fn from(err: TemplateError) -> Self {
+        match err {
+            TemplateError::Unknown(_) => Error::Validation(err.to_string()),
+            err => Error::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<SerdeError> for Error {
+    fn from(err: SerdeError) -> Self {
+        Error::Validation(format!("Invalid JSON: {}", err))
+    }
+}
+
+impl From<Unspecified> for Error {
+    fn from(_: Unspecified) -> Self {
+        Error::Internal("Unable to generate random token".to_string())
+    }
+}
+
+/// Runs the body of a field, converting any error it returns into a GraphQL error with a code.
+/// This makes `?` go through the conversions above instead of using the message of the
+/// underlying error directly.
+pub fn resolve<T, F: FnOnce() -> Result<T, Error>>(body: F) -> FieldResult<T> {
+    body().map_err(Into::into)
+}
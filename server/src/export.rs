@@ -0,0 +1,104 @@
+//! Conversion of course plans into formats that can be used outside of the application.
+//!
+//! Every format is produced from a `CoursePlanTemplate` so that the JSON format can be imported
+//! back into a course plan exactly as it was exported.
+
+use std::fmt::Write;
+
+use diesel::result::QueryResult;
+use chrono::NaiveDate;
+use serde_json;
+use serde_json::error::Error as SerdeError;
+use csv;
+
+use api::db::DbConnection;
+use models::{course_plans::CoursePlan, terms, term_courses};
+use template::{self, CoursePlanTemplate};
+use term_name::{TermName, TermStatus};
+
+/// A single row of the CSV format. Terms without any courses are represented by a row with an
+/// empty course so that they are not lost.
+#[derive(Debug, Serialize)]
+struct CsvRow<'a> {
+    term: &'a str,
+    course: &'a str,
+    status: &'a str,
+}
+
+/// Loads the terms and courses of a course plan into the template format
+pub fn template(conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<CoursePlanTemplate> {
+    let mut template_terms = Vec::new();
+    for term in terms::all(conn, course_plan)? {
+        let courses = term_courses::all(conn, &term)?;
+        template_terms.push(template::Term {
+            name: term.name,
+            courses: courses.into_iter()
+                .map(|course| template::TermCourse {name: course.name})
+                .collect(),
+        });
+    }
+
+    Ok(CoursePlanTemplate {terms: template_terms})
+}
+
+/// Exports the course plan as template JSON
+pub fn to_json(plan: &CoursePlanTemplate) -> Result<Vec<u8>, SerdeError> {
+    serde_json::to_vec_pretty(plan)
+}
+
+/// Exports the course plan as CSV with a row for each course
+pub fn to_csv(plan: &CoursePlanTemplate, today: NaiveDate) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for term in &plan.terms {
+        let status = TermName::parse(&term.name).status(today).as_str();
+        if term.courses.is_empty() {
+            writer.serialize(CsvRow {term: &term.name, course: "", status})?;
+        }
+        for course in &term.courses {
+            writer.serialize(CsvRow {term: &term.name, course: &course.name, status})?;
+        }
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+/// Escapes the characters of the given text that Markdown would otherwise treat as formatting,
+/// including anything at the start that would make it a list item
+pub fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    // A list marker ("-", "+" or a number followed by "." or ")") is only special at the start
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let marker = match text.chars().nth(digits) {
+        Some('-') | Some('+') if digits == 0 => Some(0),
+        Some('.') | Some(')') if digits > 0 => Some(digits),
+        _ => None,
+    };
+    for (i, c) in text.chars().enumerate() {
+        if "\\`*_[]#<>|~!".contains(c) || marker == Some(i) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Exports the course plan as a Markdown timeline suitable for printing
+pub fn to_markdown(plan: &CoursePlanTemplate, today: NaiveDate) -> Vec<u8> {
+    let mut out = String::from("# Course Plan\n");
+    for term in &plan.terms {
+        // Writing to a String cannot fail
+        let _ = match TermName::parse(&term.name).status(today) {
+            TermStatus::Unknown => write!(out, "\n## {}\n\n", escape_markdown(&term.name)),
+            status => write!(out, "\n## {} _({})_\n\n", escape_markdown(&term.name), status.as_str()),
+        };
+
+        if term.courses.is_empty() {
+            out.push_str("_No courses_\n");
+        }
+        for course in &term.courses {
+            let _ = writeln!(out, "- {}", escape_markdown(&course.name));
+        }
+    }
+
+    out.into_bytes()
+}
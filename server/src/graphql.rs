@@ -3,14 +3,25 @@
 //! NOTE: Names of fields should be camelCase, not snake_case to match JavaScript conventions
 #![allow(non_snake_case)]
 
-use juniper::{self, FieldResult};
-use chrono::{DateTime, Utc};
-use diesel::result::Error as QueryError;
+use std::sync::Arc;
+
+use juniper::{self, FieldResult, EmptyMutation};
+use chrono::{DateTime, Utc, Duration};
+use diesel::result::{Error as QueryError, OptionalExtension};
 use diesel::Connection;
 
 use api::db;
-use models::{users, course_plans, terms, term_courses};
-use template::CoursePlanTemplate;
+use error::{Error, resolve};
+use loader::Loader;
+use models::{users, course_plans, terms, term_courses, plan_events, plan_changes, share_links, sessions};
+use template::{CoursePlanTemplate, Templates};
+use term_name::TermDate;
+use plan;
+use random;
+use import;
+use batch;
+use diff;
+use remap;
 
 pub type Schema = juniper::RootNode<'static, Query, Mutation>;
 
@@ -18,11 +29,51 @@ pub fn schema() -> Schema {
     Schema::new(Query, Mutation)
 }
 
+/// The restricted, read-only schema available to anyone with a share link
+pub type SharedSchema = juniper::RootNode<'static, SharedQuery, EmptyMutation<Context>>;
+
+pub fn shared_schema() -> SharedSchema {
+    SharedSchema::new(SharedQuery, EmptyMutation::new())
+}
+
 /// Allows GraphQL objects to access shared data
 pub struct Context {
     /// The database connection
     pub conn: db::Connection,
+    /// The currently logged in user, or the owner of the course plan if the request was made
+    /// through a share link
     pub user: users::User,
+    /// The share link used to make the request, if any. Requests made through a share link may
+    /// only read the shared course plan.
+    pub share_link: Option<share_links::ShareLink>,
+    /// The JWT ID of the session used to make the request, if the request was made by a logged in
+    /// user
+    pub session_jti: Option<String>,
+    /// The program templates that course plans can be created from
+    pub templates: Arc<Templates>,
+    /// The terms and courses loaded so far during this request
+    pub loader: Loader,
+    /// The ID of the request, included in everything logged about it
+    pub request_id: String,
+}
+
+impl Context {
+    /// Returns an error if the request was not made by the owner of the data being accessed
+    fn require_owner(&self) -> Result<(), Error> {
+        match self.share_link {
+            Some(_) => Err(Error::Forbidden("This field is not available through a share link".to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns an error if the request was not made by an admin
+    fn require_admin(&self) -> Result<(), Error> {
+        self.require_owner()?;
+        if !self.user.is_admin {
+            Err(Error::Forbidden("Only admins can access this field".to_string()))?
+        }
+        Ok(())
+    }
 }
 
 // Implement the marker trait to make our context usable by juniper
@@ -41,10 +92,115 @@ graphql_object!(Query: Context |&self| {
     }
 
     field coursePlan(&executor, default: bool) -> FieldResult<CoursePlan> as "Query for a specific course plan" {
-        assert!(default, "Use coursePlan(default: true) to query course plans");
-        let ctx = executor.context();
-        let course_plan = course_plans::get_default(&ctx.conn, &ctx.user)?;
-        Ok(CoursePlan {course_plan})
+        resolve(|| {
+            if !default {
+                Err(Error::Validation("Use coursePlan(default: true) to query course plans".to_string()))?
+            }
+            let ctx = executor.context();
+            let course_plan = course_plans::get_default(&ctx.conn, &ctx.user)?;
+            Ok(CoursePlan {course_plan})
+        })
+    }
+
+    field planDiff(&executor, left: Option<PlanDiffSide>, right: PlanDiffSide) -> FieldResult<PlanDiff> as "List what changed from the left course plan or template to the right one, term by term. Terms are matched by their level and season (ignoring the year) and courses by their course code. If left is not given, the right course plan is compared against the template it was created from." {
+        resolve(|| {
+            let ctx = executor.context();
+            let right = right.into_side()?;
+            let left = match (left, &right) {
+                (Some(left), _) => left.into_side()?,
+                (None, &diff::Side::CoursePlan(id)) => match diff::source_template(&ctx.conn, &ctx.user, id)? {
+                    Some(template) => template,
+                    None => Err(Error::Validation(format!("Course plan {} was not created from a template, so left must be given", id)))?,
+                },
+                (None, &diff::Side::Template(_)) => Err(Error::Validation("left must be given when right is a template".to_string()))?,
+            };
+
+            let left = diff::load(&ctx.conn, &ctx.user, &ctx.templates, &left)?;
+            let right = diff::load(&ctx.conn, &ctx.user, &ctx.templates, &right)?;
+            Ok(diff::diff(&left, &right).into())
+        })
+    }
+
+    field admin(&executor) -> FieldResult<AdminQuery> as "Queries only available to admins" {
+        executor.context().require_admin()?;
+        Ok(AdminQuery)
+    }
+});
+
+/// The maximum number of users returned by a single admin users query
+const MAX_USERS_PER_PAGE: i32 = 500;
+
+/// Queries only available to admins
+pub struct AdminQuery;
+
+graphql_object!(AdminQuery: Context |&self| {
+    description: "Queries only available to admins"
+
+    field users(&executor, offset = 0: i32, limit = 50: i32) -> FieldResult<Vec<User>> as "List of all users ordered by ID" {
+        resolve(|| {
+            let ctx = executor.context();
+            if offset < 0 || limit < 0 || limit > MAX_USERS_PER_PAGE {
+                Err(Error::Validation(format!("The offset must not be negative and the limit must be between 0 and {}", MAX_USERS_PER_PAGE)))?
+            }
+            let all_users = users::all(&ctx.conn, offset as i64, limit as i64)?;
+            Ok(all_users.into_iter().map(Into::into).collect())
+        })
+    }
+
+    field user(&executor, id: i32) -> FieldResult<User> as "Query for any user" {
+        resolve(|| {
+            let ctx = executor.context();
+            match users::get(&ctx.conn, id).optional()? {
+                Some(user) => Ok(user.into()),
+                None => Err(Error::NotFound(format!("Could not find user with ID {}", id)))?,
+            }
+        })
+    }
+
+    field coursePlan(&executor, id: i32) -> FieldResult<CoursePlan> as "Query for any course plan, regardless of which user it belongs to" {
+        resolve(|| {
+            let ctx = executor.context();
+            match course_plans::find(&ctx.conn, id).optional()? {
+                Some(course_plan) => Ok(CoursePlan {course_plan}),
+                None => Err(Error::NotFound(format!("Could not find course plan with ID {}", id)))?,
+            }
+        })
+    }
+
+    field templateUsage(&executor) -> FieldResult<Vec<TemplateUsage>> as "The number of course plans created from each program template, most used first" {
+        resolve(|| {
+            let ctx = executor.context();
+            let usage = course_plans::template_usage(&ctx.conn)?;
+            Ok(usage.into_iter().map(|(template_id, count)| TemplateUsage {
+                templateId: template_id,
+                coursePlans: count as i32,
+            }).collect())
+        })
+    }
+});
+
+#[derive(Debug, GraphQLObject)]
+/// The number of course plans created from a program template
+pub struct TemplateUsage {
+    /// The program template identifier (e.g. uw-software-engineering_2018-2019_stream-8)
+    pub templateId: String,
+    /// The number of course plans created from the template
+    pub coursePlans: i32,
+}
+
+/// The root query available through a share link
+pub struct SharedQuery;
+
+graphql_object!(SharedQuery: Context |&self| {
+    description: "The root query of the read-only schema available through a share link"
+
+    field coursePlan(&executor) -> FieldResult<CoursePlan> as "The course plan that was shared" {
+        resolve(|| {
+            let ctx = executor.context();
+            let share_link = ctx.share_link.as_ref().ok_or_else(|| Error::Forbidden("No share link provided".to_string()))?;
+            let course_plan = course_plans::get(&ctx.conn, share_link.course_plan_id, &ctx.user)?;
+            Ok(CoursePlan {course_plan})
+        })
     }
 });
 
@@ -60,19 +216,69 @@ graphql_object!(CoursePlan: Context |&self| {
         self.course_plan.id
     }
 
+    field templateId() -> Option<String> as "The identifier of the program template that the course plan is based on, if any" {
+        self.course_plan.template_id.clone()
+    }
+
+    field revision() -> i32 as "Incremented by one with every change made to the course plan. Pass it as the expectedRevision of a mutation to make sure that the change is not based on an out of date copy of the course plan." {
+        self.course_plan.revision
+    }
+
     field terms(&executor) -> FieldResult<Vec<Term>> as "List of all the terms in this course plan" {
-        let ctx = executor.context();
-        let terms = terms::all(&ctx.conn, &self.course_plan)?;
-        let mut gql_terms = Vec::new();
-        for t in terms {
-            gql_terms.push(Term {term: t});
-        }
-        Ok(gql_terms)
+        resolve(|| {
+            let ctx = executor.context();
+            let terms = ctx.loader.terms(&ctx.conn, &self.course_plan)?;
+            let mut gql_terms = Vec::new();
+            for (term, courses) in terms {
+                gql_terms.push(Term {term, courses: Some(courses)});
+            }
+            Ok(gql_terms)
+        })
+    }
+
+    field changesSince(&executor, revision: i32) -> FieldResult<Vec<PlanChange>> as "List of the changes made to the terms and courses of this course plan after the given revision, in the order they were made. Applying them in order to a copy of the course plan at that revision brings it up to date." {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.require_owner()?;
+            if revision < 0 || revision > self.course_plan.revision {
+                Err(Error::Validation(format!("The revision must be between 0 and the current revision ({})", self.course_plan.revision)))?
+            }
+            let changes = plan_changes::since(&ctx.conn, &self.course_plan, revision)?;
+            let mut gql_changes = Vec::new();
+            for change in changes {
+                gql_changes.push(PlanChange::from_change(change)?);
+            }
+            Ok(gql_changes)
+        })
+    }
+
+    field history(&executor) -> FieldResult<Vec<PlanEvent>> as "List of all the changes made to this course plan, most recent first" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.require_owner()?;
+            let events = plan_events::all(&ctx.conn, &self.course_plan)?;
+            let mut gql_events = Vec::new();
+            for event in events {
+                gql_events.push(PlanEvent::from_event(event)?);
+            }
+            Ok(gql_events)
+        })
+    }
+
+    field shareLinks(&executor) -> FieldResult<Vec<ShareLink>> as "List of all the share links created for this course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.require_owner()?;
+            let links = share_links::all(&ctx.conn, &self.course_plan)?;
+            Ok(links.into_iter().map(Into::into).collect())
+        })
     }
 });
 
 pub struct Term {
     term: terms::Term,
+    /// The courses of the term, if they were loaded along with it
+    courses: Option<Vec<term_courses::TermCourse>>,
 }
 
 graphql_object!(Term: Context |&self| {
@@ -87,13 +293,18 @@ graphql_object!(Term: Context |&self| {
     }
 
     field courses(&executor) -> FieldResult<Vec<TermCourse>> as "List of courses associated with this term" {
-        let ctx = executor.context();
-        let term_courses = term_courses::all(&ctx.conn, &self.term)?;
-        let mut gql_term_courses = Vec::new();
-        for t in term_courses {
-            gql_term_courses.push(t.into());
-        }
-        Ok(gql_term_courses)
+        resolve(|| {
+            let ctx = executor.context();
+            let term_courses = match self.courses {
+                Some(ref courses) => courses.clone(),
+                None => ctx.loader.courses(&ctx.conn, &self.term)?,
+            };
+            let mut gql_term_courses = Vec::new();
+            for t in term_courses {
+                gql_term_courses.push(t.into());
+            }
+            Ok(gql_term_courses)
+        })
     }
 });
 
@@ -143,30 +354,258 @@ impl From<term_courses::TermCourse> for TermCourse {
     }
 }
 
+#[derive(Debug, Clone, Copy, GraphQLEnum)]
+/// The kind of change that was made to a course plan
+pub enum PlanEventKind {
+    /// A term was added to the course plan
+    CreateTerm,
+    /// A term and all of its courses were removed from the course plan
+    DeleteTerm,
+    /// A course was added to a term
+    CreateTermCourse,
+    /// A course was removed from a term
+    DeleteTermCourse,
+    /// A deleted term was restored to the course plan
+    RestoreTerm,
+    /// A deleted course was restored to its term
+    RestoreTermCourse,
+    /// A term was renamed
+    RenameTerm,
+    /// A term was moved to a different position in the course plan
+    MoveTerm,
+    /// A course was moved to a different term
+    MoveTermCourse,
+    /// A course was renamed
+    RenameTermCourse,
+}
+
+impl From<plan_events::EventKind> for PlanEventKind {
+    fn from(kind: plan_events::EventKind) -> Self {
+        use models::plan_events::EventKind::*;
+        match kind {
+            CreateTerm => PlanEventKind::CreateTerm,
+            DeleteTerm => PlanEventKind::DeleteTerm,
+            CreateTermCourse => PlanEventKind::CreateTermCourse,
+            DeleteTermCourse => PlanEventKind::DeleteTermCourse,
+            RestoreTerm => PlanEventKind::RestoreTerm,
+            RestoreTermCourse => PlanEventKind::RestoreTermCourse,
+            RenameTerm => PlanEventKind::RenameTerm,
+            MoveTerm => PlanEventKind::MoveTerm,
+            MoveTermCourse => PlanEventKind::MoveTermCourse,
+            RenameTermCourse => PlanEventKind::RenameTermCourse,
+        }
+    }
+}
+
 #[derive(Debug, GraphQLObject)]
-/// An application user
-pub struct User {
-    /// A unique identifier for the user
+/// A single change made to a course plan. The identifier of an event can be used as a revision to
+/// restore the course plan to.
+pub struct PlanEvent {
+    /// A unique identifier for the event
     pub id: i32,
-    /// The full provided name of the user
-    pub name: String,
-    /// The email of the user
-    pub email: String,
-    /// The date that the user was created
+    /// The kind of change that was made
+    pub kind: PlanEventKind,
+    /// The identifier of the user that made the change
+    pub userId: i32,
+    /// The date that the change was made
     pub createdAt: DateTime<Utc>,
+    /// JSON snapshot of the changed data before the change (if any)
+    pub before: Option<String>,
+    /// JSON snapshot of the changed data after the change (if any)
+    pub after: Option<String>,
+    /// True if this change has been undone
+    pub undone: bool,
+    /// The revision of the course plan that this change is part of. Undo reverts every change of
+    /// the most recent revision together.
+    pub revision: Option<i32>,
 }
 
-impl From<users::User> for User {
-    fn from(users::User {id, name, email, created_at, ..}: users::User) -> Self {
-        User {
+impl PlanEvent {
+    fn from_event(event: plan_events::PlanEvent) -> Result<Self, QueryError> {
+        Ok(PlanEvent {
+            id: event.id,
+            kind: event.kind()?.into(),
+            userId: event.user_id,
+            createdAt: event.created_at,
+            undone: event.undone_at.is_some(),
+            before: event.before,
+            after: event.after,
+            revision: event.revision,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, GraphQLEnum)]
+/// What happened to a term or course of a course plan
+pub enum PlanChangeKind {
+    /// A term was added at the given position (along with a CourseAdded change for each of its
+    /// courses)
+    TermAdded,
+    /// A term was given a new name
+    TermRenamed,
+    /// A term was moved to the given position
+    TermMoved,
+    /// A term and all of its courses were removed
+    TermDeleted,
+    /// A course was added to a term
+    CourseAdded,
+    /// A course was given a new name
+    CourseRenamed,
+    /// A course was moved to the given term
+    CourseMoved,
+    /// A course was removed from its term
+    CourseDeleted,
+}
+
+impl From<plan_changes::ChangeKind> for PlanChangeKind {
+    fn from(kind: plan_changes::ChangeKind) -> Self {
+        use models::plan_changes::ChangeKind::*;
+        match kind {
+            TermAdded => PlanChangeKind::TermAdded,
+            TermRenamed => PlanChangeKind::TermRenamed,
+            TermMoved => PlanChangeKind::TermMoved,
+            TermDeleted => PlanChangeKind::TermDeleted,
+            CourseAdded => PlanChangeKind::CourseAdded,
+            CourseRenamed => PlanChangeKind::CourseRenamed,
+            CourseMoved => PlanChangeKind::CourseMoved,
+            CourseDeleted => PlanChangeKind::CourseDeleted,
+        }
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+/// A single change to the terms and courses of a course plan
+pub struct PlanChange {
+    /// The revision of the course plan that the change was made in. One revision can include many
+    /// changes.
+    pub revision: i32,
+    /// What happened
+    pub kind: PlanChangeKind,
+    /// The term that changed, or the term of the course that changed (the new term if it moved)
+    pub termId: i32,
+    /// The course that changed, if the change is to a course
+    pub termCourseId: Option<i32>,
+    /// The name of the term or course that was added, or the new name of a renamed term or course
+    pub name: Option<String>,
+    /// The index of a term that was added or moved among the terms of the course plan
+    pub position: Option<i32>,
+}
+
+impl PlanChange {
+    fn from_change(change: plan_changes::PlanChange) -> Result<Self, QueryError> {
+        Ok(PlanChange {
+            revision: change.revision,
+            kind: change.kind()?.into(),
+            termId: change.term_id,
+            termCourseId: change.term_course_id,
+            name: change.name,
+            position: change.position,
+        })
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+/// A link that gives anyone who has it read-only access to a course plan
+pub struct ShareLink {
+    /// A unique identifier for the share link
+    pub id: i32,
+    /// The identifier of the course plan that is shared
+    pub coursePlanId: i32,
+    /// The unguessable token used to access the course plan
+    pub token: String,
+    /// The date that the share link was created
+    pub createdAt: DateTime<Utc>,
+    /// The date after which the share link can no longer be used (if any)
+    pub expiresAt: Option<DateTime<Utc>>,
+    /// True if the share link has been revoked
+    pub revoked: bool,
+}
+
+impl From<share_links::ShareLink> for ShareLink {
+    fn from(share_links::ShareLink {id, course_plan_id, token, created_at, expires_at, revoked_at}: share_links::ShareLink) -> Self {
+        ShareLink {
             id,
-            name,
-            email,
+            coursePlanId: course_plan_id,
+            token,
             createdAt: created_at,
+            expiresAt: expires_at,
+            revoked: revoked_at.is_some(),
         }
     }
 }
 
+/// An application user
+pub struct User {
+    user: users::User,
+}
+
+impl From<users::User> for User {
+    fn from(user: users::User) -> Self {
+        User {user}
+    }
+}
+
+graphql_object!(User: Context |&self| {
+    description: "An application user"
+
+    field id() -> i32 as "A unique identifier for the user" {
+        self.user.id
+    }
+
+    field name() -> &str as "The full provided name of the user" {
+        &self.user.name
+    }
+
+    field email() -> &str as "The email of the user" {
+        &self.user.email
+    }
+
+    field createdAt() -> DateTime<Utc> as "The date that the user was created" {
+        self.user.created_at
+    }
+
+    field isAdmin() -> bool as "True if the user is an admin" {
+        self.user.is_admin
+    }
+
+    field disabledAt() -> Option<DateTime<Utc>> as "The date that the account of the user was disabled (if it is disabled)" {
+        self.user.disabled_at
+    }
+
+    field sessions(&executor) -> FieldResult<Vec<Session>> as "List of the active login sessions of the user, most recently used first" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.require_owner()?;
+            let active = sessions::all_active(&ctx.conn, &self.user)?;
+            Ok(active.into_iter().map(|session| Session {
+                current: ctx.session_jti.as_ref() == Some(&session.jti),
+                id: session.id,
+                createdAt: session.created_at,
+                lastUsedAt: session.last_used_at,
+                userAgent: session.user_agent,
+                expiresAt: session.expires_at,
+            }).collect())
+        })
+    }
+});
+
+#[derive(Debug, GraphQLObject)]
+/// A login session of a user on a particular device
+pub struct Session {
+    /// A unique identifier for the session
+    pub id: i32,
+    /// The date that the user logged in
+    pub createdAt: DateTime<Utc>,
+    /// The date that the session was last used to make a request (updated about once a minute)
+    pub lastUsedAt: DateTime<Utc>,
+    /// The User-Agent header sent when the user logged in (if any)
+    pub userAgent: Option<String>,
+    /// The date that the session expires unless it is refreshed before then
+    pub expiresAt: DateTime<Utc>,
+    /// True if this is the session used to make the current request
+    pub current: bool,
+}
+
 /// The input to the createCoursePlan mutation
 #[derive(GraphQLInputObject)]
 struct CreateCoursePlanInput {
@@ -174,8 +613,289 @@ struct CreateCoursePlanInput {
     program: Option<String>,
     /// Data extracted from a user's transcript (JSON string)
     transcript: Option<String>,
+    /// The first term of the program (e.g. F26). The terms of the program template are dated
+    /// from this term instead of the intake year of the template.
+    startTerm: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, GraphQLEnum)]
+/// How imported data is combined with the terms already in a course plan
+pub enum ImportMode {
+    /// Add every imported term after the existing terms
+    Append,
+    /// Delete all of the existing terms and then add every imported term
+    Replace,
+    /// Add imported courses to the existing term with the same name, only adding the terms that
+    /// do not exist yet
+    Merge,
+}
+
+impl From<ImportMode> for import::ImportMode {
+    fn from(mode: ImportMode) -> Self {
+        match mode {
+            ImportMode::Append => import::ImportMode::Append,
+            ImportMode::Replace => import::ImportMode::Replace,
+            ImportMode::Merge => import::ImportMode::Merge,
+        }
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+/// A description of what happened to each imported term and course. Courses are described as
+/// "<course> (<term>)".
+pub struct ImportSummary {
+    /// The terms that were added to the course plan
+    pub addedTerms: Vec<String>,
+    /// The courses that were added to the course plan
+    pub addedCourses: Vec<String>,
+    /// The courses that were not added because they were already in the same term
+    pub skippedCourses: Vec<String>,
+    /// The courses that were not added because they are already planned in a different term
+    pub conflicts: Vec<String>,
+}
+
+impl From<import::ImportSummary> for ImportSummary {
+    fn from(import::ImportSummary {added_terms, added_courses, skipped_courses, conflicts}: import::ImportSummary) -> Self {
+        ImportSummary {
+            addedTerms: added_terms,
+            addedCourses: added_courses,
+            skippedCourses: skipped_courses,
+            conflicts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+/// An edit that can be made by applyPlanChanges
+pub enum PlanOperationKind {
+    /// Add a term named `name`, at `position` if given or at the end of the course plan otherwise
+    CreateTerm,
+    /// Change the name of a term to `name`
+    UpdateTerm,
+    /// Move a term to `position` among the terms of the course plan
+    MoveTerm,
+    /// Remove a term and all of its courses
+    DeleteTerm,
+    /// Add a course named `name` to a term
+    CreateCourse,
+    /// Change the name of a course to `name`
+    UpdateCourse,
+    /// Move a course to a term
+    MoveCourse,
+    /// Remove a course from its term
+    DeleteCourse,
+}
+
+/// A single edit made by applyPlanChanges. Which of the other fields are needed depends on the
+/// kind of edit. A term or course is given either by its ID or, if it was created by an earlier
+/// operation in the same list, by the index of that operation.
+#[derive(GraphQLInputObject)]
+struct PlanOperation {
+    /// The kind of edit to make
+    kind: PlanOperationKind,
+    /// The term to change, or the term to add or move a course to
+    termId: Option<i32>,
+    /// The index of an earlier CREATE_TERM operation, instead of termId
+    termOperation: Option<i32>,
+    /// The course to change
+    termCourseId: Option<i32>,
+    /// The index of an earlier CREATE_COURSE operation, instead of termCourseId
+    termCourseOperation: Option<i32>,
+    /// The name of a new term or course, or the new name of a term or course
+    name: Option<String>,
+    /// The index (starting from 0) among the terms of the course plan to put a term at
+    position: Option<i32>,
+}
+
+impl PlanOperation {
+    /// Checks that the operation has every value that its kind needs
+    fn into_operation(self, index: usize) -> Result<batch::Operation, Error> {
+        let PlanOperation {kind, termId, termOperation, termCourseId, termCourseOperation, name, position} = self;
+        let invalid = |message: &str| Error::Validation(format!("Operation {}: {}", index, message));
+        let reference = |what: &str, id: Option<i32>, operation: Option<i32>| match (id, operation) {
+            (Some(id), None) => Ok(batch::Ref::Existing(id)),
+            (None, Some(operation)) if operation >= 0 => Ok(batch::Ref::Created(operation as usize)),
+            _ => Err(invalid(&format!("Exactly one of {}Id and {}Operation (which cannot be negative) is required", what, what))),
+        };
+        let position = match position {
+            Some(position) if position < 0 => Err(invalid("The position of a term cannot be negative"))?,
+            position => position.map(|position| position as usize),
+        };
+        let name = || name.ok_or_else(|| invalid("A name is required"));
+        let term = || reference("term", termId, termOperation);
+        let course = || reference("termCourse", termCourseId, termCourseOperation);
+
+        Ok(match kind {
+            PlanOperationKind::CreateTerm => batch::Operation::CreateTerm {name: name()?, position},
+            PlanOperationKind::UpdateTerm => batch::Operation::RenameTerm {term: term()?, name: name()?},
+            PlanOperationKind::MoveTerm => batch::Operation::MoveTerm {
+                term: term()?,
+                position: position.ok_or_else(|| invalid("A position is required"))?,
+            },
+            PlanOperationKind::DeleteTerm => batch::Operation::DeleteTerm {term: term()?},
+            PlanOperationKind::CreateCourse => batch::Operation::CreateCourse {term: term()?, name: name()?},
+            PlanOperationKind::UpdateCourse => batch::Operation::RenameCourse {course: course()?, name: name()?},
+            PlanOperationKind::MoveCourse => batch::Operation::MoveCourse {course: course()?, term: term()?},
+            PlanOperationKind::DeleteCourse => batch::Operation::DeleteCourse {course: course()?},
+        })
+    }
+}
+
+/// What a single operation of applyPlanChanges changed
+pub struct PlanOperationResult {
+    kind: PlanOperationKind,
+    applied: batch::Applied,
+}
+
+graphql_object!(PlanOperationResult: Context |&self| {
+    description: "The result of a single edit made by applyPlanChanges"
+
+    field kind() -> PlanOperationKind as "The kind of edit that was made" {
+        self.kind
+    }
+
+    field term() -> Option<Term> as "The term that was created, updated, moved or deleted (a deleted term includes the courses that were deleted with it)" {
+        match self.applied {
+            batch::Applied::Term(ref term) => Some(Term {term: term.clone(), courses: None}),
+            batch::Applied::DeletedTerm(ref term, ref courses) => Some(Term {term: term.clone(), courses: Some(courses.clone())}),
+            batch::Applied::Course(_) => None,
+        }
+    }
+
+    field termCourse() -> Option<TermCourse> as "The course that was created, updated, moved or deleted" {
+        match self.applied {
+            batch::Applied::Course(ref course) => Some(course.clone().into()),
+            batch::Applied::Term(_) | batch::Applied::DeletedTerm(..) => None,
+        }
+    }
+});
+
+/// The course plan after applyPlanChanges along with what each operation changed
+pub struct AppliedPlanChanges {
+    course_plan: course_plans::CoursePlan,
+    results: Vec<PlanOperationResult>,
+}
+
+graphql_object!(AppliedPlanChanges: Context |&self| {
+    description: "The result of applying a list of edits to a course plan"
+
+    field coursePlan() -> CoursePlan as "The course plan after every edit was made" {
+        CoursePlan {course_plan: self.course_plan.clone()}
+    }
+
+    field results() -> &[PlanOperationResult] as "What each edit changed, in the same order as the edits" {
+        &self.results
+    }
+});
+
+#[derive(Debug, GraphQLObject)]
+/// A description of what happened to each term and course when a course plan was switched to a
+/// different template. Courses are described as "<course> (<term>)".
+pub struct RemapSummary {
+    /// The terms that were kept because they are completed or in progress
+    pub keptTerms: Vec<String>,
+    /// The planned terms that were replaced
+    pub removedTerms: Vec<String>,
+    /// The terms that were added from the target template
+    pub addedTerms: Vec<String>,
+    /// The planned courses that the target template also has, in their new terms
+    pub carriedOverCourses: Vec<String>,
+    /// The planned courses that are not in the target template, in their old terms
+    pub droppedCourses: Vec<String>,
+    /// The courses of the target template that were not planned because they were already taken
+    pub skippedCourses: Vec<String>,
+}
+
+impl From<remap::RemapSummary> for RemapSummary {
+    fn from(summary: remap::RemapSummary) -> Self {
+        let remap::RemapSummary {kept_terms, removed_terms, added_terms, carried_over_courses, dropped_courses, skipped_courses} = summary;
+        RemapSummary {
+            keptTerms: kept_terms,
+            removedTerms: removed_terms,
+            addedTerms: added_terms,
+            carriedOverCourses: carried_over_courses,
+            droppedCourses: dropped_courses,
+            skippedCourses: skipped_courses,
+        }
+    }
+}
+
+/// One side of a planDiff comparison. Exactly one of the fields must be given.
+#[derive(GraphQLInputObject)]
+struct PlanDiffSide {
+    /// A course plan of the currently logged in user
+    coursePlanId: Option<i32>,
+    /// A program template identifier (e.g. uw-software-engineering_2018-2019_stream-8)
+    templateId: Option<String>,
 }
 
+impl PlanDiffSide {
+    fn into_side(self) -> Result<diff::Side, Error> {
+        match (self.coursePlanId, self.templateId) {
+            (Some(id), None) => Ok(diff::Side::CoursePlan(id)),
+            (None, Some(id)) => Ok(diff::Side::Template(id)),
+            _ => Err(Error::Validation("Exactly one of coursePlanId and templateId must be given".to_string())),
+        }
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+/// The differences between two course plans, only including the terms that differ
+pub struct PlanDiff {
+    /// The terms that differ, in the order of the right side
+    pub terms: Vec<TermDiff>,
+}
+
+impl From<diff::PlanDiff> for PlanDiff {
+    fn from(diff::PlanDiff {terms}: diff::PlanDiff) -> Self {
+        PlanDiff {terms: terms.into_iter().map(Into::into).collect()}
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+/// The differences between the courses of a term on each side
+pub struct TermDiff {
+    /// The name of the term on the left side, or null if the term was added
+    pub leftName: Option<String>,
+    /// The name of the term on the right side, or null if the term was removed
+    pub rightName: Option<String>,
+    /// The courses that are only in this term on the right side
+    pub addedCourses: Vec<String>,
+    /// The courses that are only in this term on the left side
+    pub removedCourses: Vec<String>,
+    /// The courses that were moved into this term from another term
+    pub movedCourses: Vec<MovedCourse>,
+}
+
+impl From<diff::TermDiff> for TermDiff {
+    fn from(diff::TermDiff {left, right, added, removed, moved}: diff::TermDiff) -> Self {
+        TermDiff {
+            leftName: left,
+            rightName: right,
+            addedCourses: added,
+            removedCourses: removed,
+            movedCourses: moved.into_iter()
+                .map(|diff::MovedCourse {name, from}| MovedCourse {name, fromTerm: from})
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, GraphQLObject)]
+/// A course that is in a different term on each side
+pub struct MovedCourse {
+    /// Name of the course on the right side
+    pub name: String,
+    /// The name of the term that the course is in on the left side
+    pub fromTerm: String,
+}
+
+/// The maximum number of operations in a single applyPlanChanges mutation
+const MAX_PLAN_OPERATIONS: usize = 200;
+
+/// The longest that a share link can last before it expires (about ten years)
+const MAX_SHARE_LINK_DAYS: i32 = 3650;
+
 /// All of the supported mutations
 pub struct Mutation;
 
@@ -183,92 +903,405 @@ graphql_object!(Mutation: Context |&self| {
     description: "The available mutations of the schema"
 
     field createCoursePlan(&executor, params: CreateCoursePlanInput) -> FieldResult<CoursePlan> as "Create a new course plan for the currently logged in user" {
-        // Enforce that only a single course plan per user is allowed right now
-        let ctx = executor.context();
-        let course_plan = course_plans::get_default(&ctx.conn, &ctx.user);
-        match course_plan {
-            Ok(_) => Err("Course plan already exists for user (limit of 1 per user for now)")?,
-            Err(QueryError::NotFound) => {
-                // No course plan found, good! That means we can create one.
-            },
-            Err(err) => Err(err)?,
-        }
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
 
-        // Create a new course plan
-        let template = match (&params.program, &params.transcript) {
-            //TODO: Match up the person's transcript with their program to get a full course plan
-            (Some(program), Some(transcript)) if !program.is_empty() && !transcript.is_empty() => {
-                Err("Program template and transcript matching is currently unsupported")?
-            },
-            (Some(program), None) if !program.is_empty() => {
-                CoursePlanTemplate::from_template(program)?
-            },
-            (None, Some(transcript)) if !transcript.is_empty() => transcript.parse()?,
-            // Return the blank course plan as is
-            _ => {
-                let course_plan = course_plans::create(&ctx.conn, &ctx.user)?;
-                return Ok(CoursePlan {course_plan});
-            },
-        };
+            let template_id = match params.program {
+                Some(ref program) if !program.is_empty() => Some(program.clone()),
+                _ => None,
+            };
+            let template = match (&params.program, &params.transcript) {
+                //TODO: Match up the person's transcript with their program to get a full course plan
+                (Some(program), Some(transcript)) if !program.is_empty() && !transcript.is_empty() => {
+                    Err(Error::Validation("Program template and transcript matching is currently unsupported".to_string()))?
+                },
+                (Some(program), None) if !program.is_empty() => {
+                    Some(CoursePlanTemplate::from_template(&ctx.templates, program)?)
+                },
+                (None, Some(transcript)) if !transcript.is_empty() => Some(transcript.parse()?),
+                // Create a blank course plan
+                _ => None,
+            };
+            let template = match (template, &params.startTerm) {
+                (Some(template), Some(start)) if template_id.is_some() => {
+                    let start = TermDate::parse(start.trim()).ok_or_else(|| Error::Validation(format!(
+                        "Invalid start term {}: expected a season (F, W or S) followed by a two digit year (e.g. F26)", start,
+                    )))?;
+                    Some(template.starting_at(start))
+                },
+                (_, Some(_)) => Err(Error::Validation("A start term can only be given along with a program".to_string()))?,
+                (template, None) => template,
+            };
+
+            // Any error in this transaction will cause all of the changes to be rolled back
+            let course_plan = ctx.conn.transaction::<_, Error, _>(|| {
+                // Enforce that only a single course plan per user is allowed right now
+                match course_plans::get_default(&ctx.conn, &ctx.user) {
+                    Ok(_) => Err(Error::Conflict("Course plan already exists for user (limit of 1 per user for now)".to_string()))?,
+                    Err(QueryError::NotFound) => {
+                        // No course plan found, good! That means we can create one.
+                    },
+                    Err(err) => Err(err)?,
+                }
 
-        // Any error in this transaction will cause all of the changes to be rolled back
-        let course_plan = ctx.conn.transaction::<_, QueryError, _>(|| {
-            let course_plan = course_plans::create(&ctx.conn, &ctx.user)?;
-            for term in template.terms {
-                let dbterm = terms::create(&ctx.conn, &course_plan, term.name)?;
-                for course in term.courses {
-                    term_courses::create(&ctx.conn, &dbterm, course.name)?;
+                let course_plan = course_plans::create(&ctx.conn, &ctx.user, template_id)?;
+                for term in template.map(|template| template.terms).unwrap_or_default() {
+                    let dbterm = terms::create(&ctx.conn, &course_plan, term.name)?;
+                    for course in term.courses {
+                        term_courses::create(&ctx.conn, &dbterm, course.name)?;
+                    }
                 }
+
+                Ok(course_plan)
+            })?;
+
+            Ok(CoursePlan {course_plan})
+        })
+    }
+
+    field deleteCoursePlan(&executor, coursePlanId: i32, expectedRevision: Option<i32>) -> FieldResult<i32> as "Permanently delete a course plan along with all of its terms, courses, history and share links. Returns the ID of the deleted course plan." {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            let course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user).optional()?;
+            match course_plan {
+                Some(course_plan) => {
+                    plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                        Ok(course_plans::delete(&ctx.conn, course_plan.id)?)
+                    })?;
+                    Ok(course_plan.id)
+                },
+                None => Err(Error::NotFound(format!("Could not find course plan with ID {} for the currently logged in user", coursePlanId)))?,
             }
+        })
+    }
 
-            Ok(course_plan)
-        })?;
+    field importIntoCoursePlan(&executor, coursePlanId: i32, data: String, mode: ImportMode, expectedRevision: Option<i32>) -> FieldResult<ImportSummary> as "Import template JSON or CSV (with term and course columns) into an existing course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            let course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
+            let data = import::parse(&data)?;
+            let (summary, _) = plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                Ok(import::import(&ctx.conn, &ctx.user, &course_plan, data, mode.into())?)
+            })?;
+            Ok(summary.into())
+        })
+    }
 
-        Ok(CoursePlan {course_plan})
+    field applyPlanChanges(&executor, coursePlanId: i32, operations: Vec<PlanOperation>, expectedRevision: Option<i32>) -> FieldResult<AppliedPlanChanges> as "Apply a list of edits to the terms and courses of a course plan in order. Either every edit is made or, if any of them fails, none of them are. The whole list counts as a single revision. At most 200 operations can be applied at once." {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if operations.len() > MAX_PLAN_OPERATIONS {
+                Err(Error::Validation(format!("At most {} operations can be applied at once", MAX_PLAN_OPERATIONS)))?
+            }
+            let mut course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
+            let mut kinds = Vec::new();
+            let mut batch_operations = Vec::new();
+            for (index, operation) in operations.into_iter().enumerate() {
+                kinds.push(operation.kind);
+                batch_operations.push(operation.into_operation(index)?);
+            }
+            let (applied, revision) = plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                Ok(batch::apply(&ctx.conn, &ctx.user, &course_plan, batch_operations)?)
+            })?;
+            course_plan.revision = revision;
+            let results = kinds.into_iter().zip(applied)
+                .map(|(kind, applied)| PlanOperationResult {kind, applied})
+                .collect();
+            Ok(AppliedPlanChanges {course_plan, results})
+        })
     }
 
-    field createTerm(&executor, coursePlanId: i32, name: String) -> FieldResult<Term> as "Create a new term for a specified course plan" {
-        let ctx = executor.context();
-        let course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
-        let term = terms::create(&ctx.conn, &course_plan, name)?;
-        Ok(Term {term})
+    field remapCoursePlan(&executor, coursePlanId: i32, targetTemplate: String, expectedRevision: Option<i32>) -> FieldResult<RemapSummary> as "Switch a course plan to a different program or stream template. Terms that are completed or in progress are kept, every planned term is replaced by the remaining terms of the target template and planned courses that are in the target template are carried over." {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            let course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
+            let target = CoursePlanTemplate::from_template(&ctx.templates, &targetTemplate)?;
+            let today = Utc::today().naive_utc();
+            let (summary, _) = plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                Ok(remap::remap(&ctx.conn, &ctx.user, &course_plan, &targetTemplate, &target, today)?)
+            })?;
+            Ok(summary.into())
+        })
     }
 
-    field deleteTerm(&executor, termId: i32) -> FieldResult<DeletedTerm> as "Remove a specified term and all its associated courses from a course plan" {
-        let ctx = executor.context();
-        if terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
-            let term = terms::get(&ctx.conn, termId)?;
-            let deleted_term = DeletedTerm {
-                term: term.clone(),
-                courses: term_courses::all(&ctx.conn, &term)?
+    field createTerm(&executor, coursePlanId: i32, name: String, expectedRevision: Option<i32>) -> FieldResult<Term> as "Create a new term for a specified course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            let course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
+            let (term, _) = plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                Ok(plan::create_term(&ctx.conn, &ctx.user, &course_plan, name)?)
+            })?;
+            Ok(Term {term, courses: None})
+        })
+    }
+
+    field deleteTerm(&executor, termId: i32, expectedRevision: Option<i32>) -> FieldResult<DeletedTerm> as "Remove a specified term and all its associated courses from a course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
+                let term = terms::get(&ctx.conn, termId)?;
+                let (courses, _) = plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::delete_term(&ctx.conn, &ctx.user, &term)?)
+                })?;
+                return Ok(DeletedTerm {term, courses});
+            }
+
+            Err(Error::NotFound(format!("Could not find term with ID {} for the currently logged in user", termId)))?
+        })
+    }
+
+    field renameTerm(&executor, termId: i32, name: String, expectedRevision: Option<i32>) -> FieldResult<Term> as "Change the name of a term" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
+                let term = terms::get(&ctx.conn, termId)?;
+                let (term, _) = plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::rename_term(&ctx.conn, &ctx.user, &term, name)?)
+                })?;
+                return Ok(Term {term, courses: None});
+            }
+
+            Err(Error::NotFound(format!("Could not find term with ID {} for the currently logged in user", termId)))?
+        })
+    }
+
+    field moveTerm(&executor, termId: i32, position: i32, expectedRevision: Option<i32>) -> FieldResult<CoursePlan> as "Move a term to the given index (starting from 0) among the terms of its course plan. An index past the last term moves the term to the end." {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if position < 0 {
+                Err(Error::Validation("The position of a term cannot be negative".to_string()))?
+            }
+            if terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
+                let term = terms::get(&ctx.conn, termId)?;
+                let (_, revision) = plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::move_term(&ctx.conn, &ctx.user, &term, position as usize)?)
+                })?;
+                let mut course_plan = course_plans::get(&ctx.conn, term.course_plan_id, &ctx.user)?;
+                course_plan.revision = revision;
+                return Ok(CoursePlan {course_plan});
+            }
+
+            Err(Error::NotFound(format!("Could not find term with ID {} for the currently logged in user", termId)))?
+        })
+    }
+
+    field createTermCourse(&executor, termId:i32, name: String, expectedRevision: Option<i32>) -> FieldResult<TermCourse> as "Create a new course for a specified term" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
+                let term = terms::get(&ctx.conn, termId)?;
+                let (term_course, _) = plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::create_term_course(&ctx.conn, &ctx.user, &term, name)?)
+                })?;
+                return Ok(term_course.into())
+            }
+
+            Err(Error::NotFound(format!("Could not find term with ID {} for the currently logged in user", termId)))?
+        })
+    }
+
+    field deleteTermCourse(&executor, termCourseId: i32, expectedRevision: Option<i32>) -> FieldResult<TermCourse> as "Remove a specified course from a term in the logged in user's course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if term_courses::belongs_to_user(&ctx.conn, termCourseId, &ctx.user)? {
+                let deleted_course = term_courses::get(&ctx.conn, termCourseId)?;
+                let term = terms::get(&ctx.conn, deleted_course.term_id)?;
+                plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::delete_term_course(&ctx.conn, &ctx.user, &deleted_course)?)
+                })?;
+                return Ok(deleted_course.into());
+            }
+
+            Err(Error::NotFound(format!("Could not find course with ID {} for the currently logged in user", termCourseId)))?
+        })
+    }
+
+    field moveTermCourse(&executor, termCourseId: i32, termId: i32, expectedRevision: Option<i32>) -> FieldResult<TermCourse> as "Move a course to a different term of the same course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if !term_courses::belongs_to_user(&ctx.conn, termCourseId, &ctx.user)? {
+                Err(Error::NotFound(format!("Could not find course with ID {} for the currently logged in user", termCourseId)))?
+            }
+            if !terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
+                Err(Error::NotFound(format!("Could not find term with ID {} for the currently logged in user", termId)))?
+            }
+            let course = term_courses::get(&ctx.conn, termCourseId)?;
+            let from = terms::get(&ctx.conn, course.term_id)?;
+            let to = terms::get(&ctx.conn, termId)?;
+            if from.course_plan_id != to.course_plan_id {
+                Err(Error::Validation("Courses can only be moved to a term of the same course plan".to_string()))?
+            }
+            if from.id == to.id {
+                plan::check_revision::<Error>(&ctx.conn, to.course_plan_id, expectedRevision)?;
+                return Ok(course.into());
+            }
+            let (moved, _) = plan::revise::<_, Error, _>(&ctx.conn, to.course_plan_id, expectedRevision, || {
+                Ok(plan::move_term_course(&ctx.conn, &ctx.user, &course, &to)?)
+            })?;
+            Ok(moved.into())
+        })
+    }
+
+    field restoreTerm(&executor, termId: i32, expectedRevision: Option<i32>) -> FieldResult<Term> as "Restore a term that was deleted from a course plan, along with the courses it had when it was deleted" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if terms::deleted_belongs_to_user(&ctx.conn, termId, &ctx.user)? {
+                let term = terms::get(&ctx.conn, termId)?;
+                plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::restore_term(&ctx.conn, &ctx.user, &term)?)
+                })?;
+                return Ok(Term {term: terms::get(&ctx.conn, termId)?, courses: None});
+            }
+
+            Err(Error::NotFound(format!("Could not find deleted term with ID {} for the currently logged in user", termId)))?
+        })
+    }
+
+    field restoreTermCourse(&executor, termCourseId: i32, expectedRevision: Option<i32>) -> FieldResult<TermCourse> as "Restore a course that was deleted from a term that has not been deleted" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            if term_courses::deleted_belongs_to_user(&ctx.conn, termCourseId, &ctx.user)? {
+                let course = term_courses::get(&ctx.conn, termCourseId)?;
+                let term = terms::get(&ctx.conn, course.term_id)?;
+                plan::revise::<_, Error, _>(&ctx.conn, term.course_plan_id, expectedRevision, || {
+                    Ok(plan::restore_term_course(&ctx.conn, &ctx.user, &course)?)
+                })?;
+                return Ok(course.into());
+            }
+
+            Err(Error::NotFound(format!("Could not find deleted course with ID {} for the currently logged in user", termCourseId)))?
+        })
+    }
+
+    field createShareLink(&executor, coursePlanId: i32, expiresInDays: Option<i32>) -> FieldResult<ShareLink> as "Create a link that gives anyone who has it read-only access to a course plan. expiresInDays must be between 1 and 3650 if it is given." {
+        resolve(|| {
+            let ctx = executor.context();
+            let course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
+            let expires_at = match expiresInDays {
+                Some(days) if days <= 0 => Err(Error::Validation("Share links must expire at least one day after they are created".to_string()))?,
+                Some(days) if days > MAX_SHARE_LINK_DAYS => Err(Error::Validation(format!("Share links must expire at most {} days after they are created", MAX_SHARE_LINK_DAYS)))?,
+                Some(days) => Some(Utc::now() + Duration::days(days as i64)),
+                None => None,
             };
-            term_courses::delete_all(&ctx.conn, termId)?;
-            let count = terms::delete(&ctx.conn, termId)?;
-            return Ok(deleted_term);
-        }
+            let token = random::token()?;
+            let share_link = share_links::create(&ctx.conn, &course_plan, token, expires_at)?;
+            Ok(share_link.into())
+        })
+    }
 
-        Err(format!("Could not find term with ID {} for the currently logged in user", termId))?
+    field revokeShareLink(&executor, shareLinkId: i32) -> FieldResult<ShareLink> as "Revoke a share link so it can no longer be used to access its course plan" {
+        resolve(|| {
+            let ctx = executor.context();
+            if share_links::belongs_to_user(&ctx.conn, shareLinkId, &ctx.user)? {
+                let share_link = share_links::revoke(&ctx.conn, shareLinkId)?;
+                return Ok(share_link.into());
+            }
+
+            Err(Error::NotFound(format!("Could not find share link with ID {} for the currently logged in user", shareLinkId)))?
+        })
     }
 
-    field createTermCourse(&executor, termId:i32, name: String) -> FieldResult<TermCourse> as "Create a new course for a specified term" {
-        let ctx = executor.context();
-        if terms::belongs_to_user(&ctx.conn, termId, &ctx.user)? {
-            let term = terms::get(&ctx.conn, termId)?;
-            let term_course = term_courses::create(&ctx.conn, &term, name)?;
-            return Ok(term_course.into())
-        }
+    field undo(&executor, coursePlanId: i32, expectedRevision: Option<i32>) -> FieldResult<CoursePlan> as "Revert the most recent change to a course plan that has not already been undone. Every edit made by a single mutation (e.g. applyPlanChanges or remapCoursePlan) is undone together." {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            let mut course_plan = course_plans::get(&ctx.conn, coursePlanId, &ctx.user)?;
+            let (_, revision) = plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                let undone = plan::undo(&ctx.conn, &course_plan)?;
+                if undone.is_empty() {
+                    Err(Error::Conflict("There are no changes to undo in this course plan".to_string()))?
+                }
+                Ok(undone)
+            })?;
+            course_plan.revision = revision;
+            Ok(CoursePlan {course_plan})
+        })
+    }
 
-        Err(format!("Could not find term with ID {} for the currently logged in user", termId))?
+    field restoreRevision(&executor, revisionId: i32, expectedRevision: Option<i32>) -> FieldResult<CoursePlan> as "Revert every change made to a course plan after the specified revision (plan event)" {
+        resolve(|| {
+            let ctx = executor.context();
+            ctx.loader.clear();
+            let revision = plan_events::get(&ctx.conn, revisionId).optional()?;
+            let course_plan = match revision {
+                Some(ref revision) => course_plans::get(&ctx.conn, revision.course_plan_id, &ctx.user).optional()?,
+                None => None,
+            };
+            match (revision, course_plan) {
+                (Some(revision), Some(mut course_plan)) => {
+                    if revision.undone_at.is_some() {
+                        Err(Error::Conflict(format!("Revision with ID {} has been undone and cannot be restored", revisionId)))?
+                    }
+                    let (_, new_revision) = plan::revise::<_, Error, _>(&ctx.conn, course_plan.id, expectedRevision, || {
+                        Ok(plan::restore_revision(&ctx.conn, &revision)?)
+                    })?;
+                    course_plan.revision = new_revision;
+                    Ok(CoursePlan {course_plan})
+                },
+                _ => Err(Error::NotFound(format!("Could not find revision with ID {} for the currently logged in user", revisionId)))?,
+            }
+        })
     }
 
-    field deleteTermCourse(&executor, termCourseId: i32) -> FieldResult<TermCourse> as "Remove a specified course from a term in the logged in user's course plan" {
-        let ctx = executor.context();
-        if term_courses::belongs_to_user(&ctx.conn, termCourseId, &ctx.user)? {
-            let deleted_course = term_courses::get(&ctx.conn, termCourseId)?;
-            let count = term_courses::delete(&ctx.conn, termCourseId)?;
-            return Ok(deleted_course.into());
-        }
+    field admin(&executor) -> FieldResult<AdminMutation> as "Mutations only available to admins" {
+        executor.context().require_admin()?;
+        Ok(AdminMutation)
+    }
 
-        Err(format!("Could not find course with ID {} for the currently logged in user", termCourseId))?
+    field logoutEverywhere(&executor) -> FieldResult<i32> as "Revoke every session of the currently logged in user, including the current one. Returns the number of sessions revoked." {
+        resolve(|| {
+            let ctx = executor.context();
+            let revoked = sessions::revoke_all(&ctx.conn, &ctx.user)?;
+            Ok(revoked as i32)
+        })
+    }
+});
+
+/// Mutations only available to admins
+pub struct AdminMutation;
+
+graphql_object!(AdminMutation: Context |&self| {
+    description: "Mutations only available to admins"
+
+    field disableUser(&executor, userId: i32) -> FieldResult<User> as "Disable the account of a user, logging them out everywhere and preventing them from logging in" {
+        resolve(|| {
+            let ctx = executor.context();
+            if userId == ctx.user.id {
+                Err(Error::Validation("You cannot disable your own account".to_string()))?
+            }
+            let user = ctx.conn.transaction::<_, QueryError, _>(|| {
+                let user = users::set_disabled(&ctx.conn, userId, true)?;
+                sessions::revoke_all(&ctx.conn, &user)?;
+                Ok(user)
+            }).optional()?;
+            match user {
+                Some(user) => Ok(user.into()),
+                None => Err(Error::NotFound(format!("Could not find user with ID {}", userId)))?,
+            }
+        })
+    }
+
+    field enableUser(&executor, userId: i32) -> FieldResult<User> as "Re-enable the account of a user so that they can log in again" {
+        resolve(|| {
+            let ctx = executor.context();
+            match users::set_disabled(&ctx.conn, userId, false).optional()? {
+                Some(user) => Ok(user.into()),
+                None => Err(Error::NotFound(format!("Could not find user with ID {}", userId)))?,
+            }
+        })
     }
 });
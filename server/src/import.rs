@@ -0,0 +1,162 @@
+//! Importing terms and courses into an existing course plan.
+//!
+//! Data can be imported in any format that `export` produces and that can be read back: template
+//! JSON or CSV with `term` and `course` columns. Every change is made through the `plan` module so
+//! an import shows up in the history of the course plan like any other change.
+
+use std::fmt;
+
+use diesel::Connection;
+use diesel::result::QueryResult;
+use serde_json::error::Error as SerdeError;
+use csv;
+
+use api::db::DbConnection;
+use models::{users::User, course_plans::CoursePlan, terms, term_courses};
+use template::{self, CoursePlanTemplate};
+use plan;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Json(SerdeError),
+    Csv(csv::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Json(err) => write!(f, "Unable to read template JSON: {}", err),
+            ImportError::Csv(err) => write!(f, "Unable to read CSV: {}", err),
+        }
+    }
+}
+
+/// How imported data is combined with the terms already in the course plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add every imported term after the existing terms
+    Append,
+    /// Delete all of the existing terms and then add every imported term
+    Replace,
+    /// Add imported courses to the existing term with the same name, only adding the terms that
+    /// do not exist yet
+    Merge,
+}
+
+/// A description of what happened to each imported term and course. Courses are described as
+/// "<course> (<term>)".
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// The terms that were added to the course plan
+    pub added_terms: Vec<String>,
+    /// The courses that were added to the course plan
+    pub added_courses: Vec<String>,
+    /// The courses that were not added because they were already in the same term
+    pub skipped_courses: Vec<String>,
+    /// The courses that were not added because they are already planned in a different term
+    pub conflicts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    term: String,
+    #[serde(default)]
+    course: String,
+}
+
+/// Parses data in either the template JSON format or the CSV format
+pub fn parse(data: &str) -> Result<CoursePlanTemplate, ImportError> {
+    if data.trim_left().starts_with('{') {
+        return data.parse().map_err(ImportError::Json);
+    }
+
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let mut plan = CoursePlanTemplate {terms: Vec::new()};
+    for row in reader.deserialize() {
+        let CsvRow {term, course} = row.map_err(ImportError::Csv)?;
+        let term_name = term.trim();
+        // Rows for the same term are grouped together even if they are not next to each other
+        let index = match plan.terms.iter().position(|t| t.name == term_name) {
+            Some(index) => index,
+            None => {
+                plan.terms.push(template::Term {name: term_name.to_string(), courses: Vec::new()});
+                plan.terms.len() - 1
+            },
+        };
+
+        // Terms without any courses are exported as a single row with an empty course
+        let course = course.trim();
+        if !course.is_empty() {
+            plan.terms[index].courses.push(template::TermCourse {name: course.to_string()});
+        }
+    }
+
+    Ok(plan)
+}
+
+/// A term of the course plan being imported into along with the names of its courses
+struct ExistingTerm {
+    term: terms::Term,
+    courses: Vec<String>,
+}
+
+/// Imports the given data into a course plan. All changes happen in a single transaction, so
+/// either all of the data is imported or none of it is.
+pub fn import(
+    conn: &DbConnection,
+    user: &User,
+    course_plan: &CoursePlan,
+    data: CoursePlanTemplate,
+    mode: ImportMode,
+) -> QueryResult<ImportSummary> {
+    conn.transaction(|| {
+        let mut summary = ImportSummary::default();
+        let mut existing = Vec::new();
+        for term in terms::all(conn, course_plan)? {
+            let courses = term_courses::all(conn, &term)?.into_iter().map(|c| c.name).collect();
+            existing.push(ExistingTerm {term, courses});
+        }
+
+        if mode == ImportMode::Replace {
+            for ExistingTerm {term, ..} in existing.drain(..) {
+                plan::delete_term(conn, user, &term)?;
+            }
+        }
+
+        for imported in data.terms {
+            let matching = match mode {
+                ImportMode::Merge => existing.iter().position(|e| e.term.name == imported.name),
+                ImportMode::Append | ImportMode::Replace => None,
+            };
+            let index = match matching {
+                Some(index) => index,
+                None => {
+                    let term = plan::create_term(conn, user, course_plan, imported.name.clone())?;
+                    summary.added_terms.push(term.name.clone());
+                    existing.push(ExistingTerm {term, courses: Vec::new()});
+                    existing.len() - 1
+                },
+            };
+
+            for course in imported.courses {
+                let description = format!("{} ({})", course.name, imported.name);
+                if mode == ImportMode::Merge {
+                    if existing[index].courses.contains(&course.name) {
+                        summary.skipped_courses.push(description);
+                        continue;
+                    }
+                    if existing.iter().any(|e| e.courses.contains(&course.name)) {
+                        summary.conflicts.push(description);
+                        continue;
+                    }
+                }
+
+                plan::create_term_course(conn, user, &existing[index].term, course.name.clone())?;
+                existing[index].courses.push(course.name);
+                summary.added_courses.push(description);
+            }
+        }
+
+        Ok(summary)
+    })
+}
@@ -0,0 +1,165 @@
+//! A per-request cache of the terms and courses loaded while resolving a GraphQL query.
+//!
+//! Resolving `coursePlan { terms { courses } }` one term at a time would run a query for the
+//! courses of every term. Instead, the terms of a course plan are loaded along with the courses of
+//! all of those terms in two queries, and the results are kept for the rest of the request.
+//!
+//! Mutations must call `clear` before changing terms or courses so that the fields resolved after
+//! the change do not see stale data.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use diesel::result::QueryResult;
+
+use api::db::DbConnection;
+use models::{course_plans::CoursePlan, terms::{self, Term}, term_courses::{self, TermCourse}};
+
+#[derive(Debug, Default)]
+pub struct Loader {
+    /// The terms of each course plan (by course plan ID) along with their courses
+    terms: Mutex<HashMap<i32, Vec<(Term, Vec<TermCourse>)>>>,
+    /// The courses of each term (by term ID)
+    courses: Mutex<HashMap<i32, Vec<TermCourse>>>,
+}
+
+impl Loader {
+    /// Loads the terms of a course plan and the courses of each of those terms
+    pub fn terms(&self, conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<Vec<(Term, Vec<TermCourse>)>> {
+        if let Some(cached) = lock(&self.terms).get(&course_plan.id) {
+            return Ok(cached.clone());
+        }
+
+        let plan_terms = terms::all(conn, course_plan)?;
+        let courses = term_courses::all_for_terms(conn, &plan_terms)?;
+        let loaded: Vec<_> = plan_terms.into_iter().zip(courses).collect();
+
+        {
+            let mut cached_courses = lock(&self.courses);
+            for (term, courses) in &loaded {
+                cached_courses.insert(term.id, courses.clone());
+            }
+        }
+        lock(&self.terms).insert(course_plan.id, loaded.clone());
+        Ok(loaded)
+    }
+
+    /// Loads the courses of a term, unless they were already loaded along with the term
+    pub fn courses(&self, conn: &DbConnection, term: &Term) -> QueryResult<Vec<TermCourse>> {
+        if let Some(cached) = lock(&self.courses).get(&term.id) {
+            return Ok(cached.clone());
+        }
+
+        let courses = term_courses::all(conn, term)?;
+        lock(&self.courses).insert(term.id, courses.clone());
+        Ok(courses)
+    }
+
+    /// Forgets everything that has been loaded
+    pub fn clear(&self) {
+        lock(&self.terms).clear();
+        lock(&self.courses).clear();
+    }
+}
+
+/// The cache is only ever used by one request at a time, so a poisoned lock can only mean that a
+/// previous resolver panicked. The cached data itself is still consistent.
+fn lock<T>(mutex: &Mutex<T>) -> ::std::sync::MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// Only PostgreSQL keeps the statistics used to count queries
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use std::env;
+    use std::sync::Arc;
+
+    use diesel::prelude::*;
+    use diesel::sql_query;
+    use diesel::sql_types::{BigInt, Text};
+    use juniper::{self, Variables, Value};
+
+    use api::db::{self, DbConnection};
+    use graphql::{self, Context};
+    use models::{users, course_plans, terms, term_courses};
+    use template::Templates;
+    use super::Loader;
+
+    #[derive(QueryableByName)]
+    struct Scans {
+        #[sql_type = "BigInt"]
+        scans: i64,
+    }
+
+    /// The number of times the given table has been read in the current transaction
+    fn scans(conn: &DbConnection, table: &str) -> i64 {
+        sql_query("SELECT (seq_scan + COALESCE(idx_scan, 0))::BIGINT AS scans \
+                   FROM pg_stat_xact_user_tables WHERE relname = $1")
+            .bind::<Text, _>(table)
+            .get_result::<Scans>(conn)
+            .map(|row| row.scans)
+            .unwrap_or(0)
+    }
+
+    /// Requires TEST_DATABASE_URL to point to a database with all of the migrations applied
+    #[test]
+    #[ignore]
+    fn course_plan_terms_and_courses_are_batch_loaded() {
+        let database_url = env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL must be set to run the query count test");
+
+        let pool = db::connect_test(&database_url);
+        let conn = pool.get().expect("unable to connect to the test database");
+
+        let user = users::create(&conn, "Loader Test", "loader-test@example.com").unwrap();
+        let course_plan = course_plans::create(&conn, &user, None).unwrap();
+        for i in 0..14 {
+            let term = terms::create(&conn, &course_plan, format!("Term {}", i)).unwrap();
+            for j in 0..3 {
+                term_courses::create(&conn, &term, format!("COURSE {}{}", i, j)).unwrap();
+            }
+        }
+
+        let ctx = Context {
+            conn: db::Connection::new(conn),
+            user,
+            share_link: None,
+            session_jti: None,
+            templates: Arc::new(Templates::new("templates")),
+            loader: Loader::default(),
+            request_id: "loader-test".to_string(),
+        };
+        let terms_before = scans(&ctx.conn, "terms");
+        let courses_before = scans(&ctx.conn, "term_courses");
+
+        let query = "{ coursePlan(default: true) { terms { name courses { name } } } }";
+        let (result, errors) = juniper::execute(query, None, &graphql::schema(), &Variables::new(), &ctx)
+            .expect("query failed");
+        assert!(errors.is_empty(), "query returned errors: {:?}", errors);
+
+        // One query for the terms and one for all of their courses, no matter how many terms
+        assert_eq!(scans(&ctx.conn, "terms") - terms_before, 1);
+        assert_eq!(scans(&ctx.conn, "term_courses") - courses_before, 1);
+
+        let terms = result.as_object_value()
+            .and_then(|data| data.get("coursePlan"))
+            .and_then(Value::as_object_value)
+            .and_then(|plan| plan.get("terms"))
+            .and_then(Value::as_list_value)
+            .expect("course plan has no terms");
+        assert_eq!(terms.len(), 14);
+        for term in terms {
+            let courses = term.as_object_value()
+                .and_then(|term| term.get("courses"))
+                .and_then(Value::as_list_value)
+                .expect("term has no courses");
+            assert_eq!(courses.len(), 3);
+        }
+
+        // Resolving the same terms again in the same request does not query anything
+        juniper::execute(query, None, &graphql::schema(), &Variables::new(), &ctx)
+            .expect("query failed");
+        assert_eq!(scans(&ctx.conn, "terms") - terms_before, 1);
+        assert_eq!(scans(&ctx.conn, "term_courses") - courses_before, 1);
+    }
+}
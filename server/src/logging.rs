@@ -0,0 +1,123 @@
+//! Structured logging.
+//!
+//! Every log line is a single event: a message along with named fields. Events are written to
+//! stderr either as text for reading in a terminal or as one JSON object per line for log
+//! collectors (see the logging.format configuration):
+//!
+//! ```text
+//! 2018-10-04T17:02:11.204Z INFO request request_id="8f1c2a4b9d3e6f70" method="POST" status=200
+//! {"level":"INFO","message":"request","method":"POST","request_id":"8f1c2a4b9d3e6f70","status":200,"time":"2018-10-04T17:02:11.204Z"}
+//! ```
+//!
+//! Usage: `logging::error("Unable to purge deleted records").field("error", err.to_string()).log()`
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{Utc, SecondsFormat};
+use serde_json::{Map, Value};
+
+/// How log events are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable text with the fields as key=value pairs
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}' (expected 'text' or 'json')", text)),
+        }
+    }
+}
+
+/// True if events should be written as JSON. Text is used until `init` is called.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Sets the format of every event logged from now on
+pub fn init(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::SeqCst);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Info => write!(f, "INFO"),
+            Level::Warn => write!(f, "WARN"),
+            Level::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// An event that is written when `log` is called
+#[must_use = "events are only written when log() is called"]
+#[derive(Debug)]
+pub struct Event {
+    level: Level,
+    message: String,
+    fields: Vec<(&'static str, Value)>,
+}
+
+pub fn info<S: Into<String>>(message: S) -> Event {
+    Event::new(Level::Info, message)
+}
+
+pub fn warn<S: Into<String>>(message: S) -> Event {
+    Event::new(Level::Warn, message)
+}
+
+pub fn error<S: Into<String>>(message: S) -> Event {
+    Event::new(Level::Error, message)
+}
+
+impl Event {
+    fn new<S: Into<String>>(level: Level, message: S) -> Self {
+        Event {level, message: message.into(), fields: Vec::new()}
+    }
+
+    /// Adds a field to the event. Text events have their fields in the order they were added.
+    pub fn field<V: Into<Value>>(mut self, name: &'static str, value: V) -> Self {
+        self.fields.push((name, value.into()));
+        self
+    }
+
+    /// Writes the event to stderr
+    pub fn log(self) {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let line = if JSON.load(Ordering::SeqCst) {
+            let mut object = Map::new();
+            object.insert("time".to_string(), Value::String(time));
+            object.insert("level".to_string(), Value::String(self.level.to_string()));
+            object.insert("message".to_string(), Value::String(self.message));
+            for (name, value) in self.fields {
+                object.insert(name.to_string(), value);
+            }
+            Value::Object(object).to_string()
+        } else {
+            let mut line = format!("{} {} {}", time, self.level, self.message);
+            for (name, value) in self.fields {
+                line.push_str(&format!(" {}={}", name, value));
+            }
+            line
+        };
+
+        // Written all at once so that events from different threads are never interleaved
+        let _ = io::stderr().write_all(format!("{}\n", line).as_bytes());
+    }
+}
@@ -8,6 +8,9 @@ extern crate rocket;
 extern crate rocket_cors;
 extern crate rocket_contrib;
 extern crate jsonwebtoken as jwt;
+extern crate ring;
+extern crate base64;
+extern crate reqwest;
 
 #[macro_use]
 extern crate serde_derive;
@@ -19,37 +22,75 @@ extern crate diesel;
 
 #[macro_use]
 extern crate juniper;
-extern crate juniper_rocket;
 
 extern crate dotenv;
 extern crate chrono;
+extern crate csv;
+extern crate toml;
 
+mod config;
+mod logging;
 mod schema;
+mod sql_types;
 mod models;
 mod graphql;
+mod error;
+mod loader;
 mod api;
 mod template;
+mod plan;
+mod accounts;
+mod random;
+mod term_name;
+mod export;
+mod import;
+mod diff;
+mod remap;
+mod batch;
+mod query_cost;
 
-use std::env;
+use std::process;
 
 use dotenv::dotenv;
 
+use config::Config;
+
 fn main() {
     // Load the environment from the .env configuration
     dotenv().ok();
 
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+    logging::init(config.logging.format);
 
-    // Ensure that the secret key is set
-    let secret_key = env::var("SECRET_KEY")
-        .expect("SECRET_KEY must be set");
+    // Mint an access token for a test user instead of starting the server (development only)
+    // Usage: uwcourseplan mint-token <email>
+    #[cfg(any(debug_assertions, feature = "dev-login"))]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if args.get(1).map(String::as_str) == Some("mint-token") {
+            let email = match args.get(2) {
+                Some(email) => email,
+                None => {
+                    eprintln!("Usage: uwcourseplan mint-token <email>");
+                    process::exit(1);
+                },
+            };
+            match api::dev::mint_token(&config, email) {
+                Ok(token) => println!("{}", token),
+                Err(err) => {
+                    eprintln!("Unable to mint token: {}", err);
+                    process::exit(1);
+                },
+            }
+            return;
+        }
+    }
 
-    api::run_server(&database_url, secret_key, &[
-        //TODO: Get these URLs from a configuration instead of hardcoding them
-        "http://localhost:1234",
-        "http://local.uwcourseplan.com:1234",
-        "http://local.uwcourseplan.com:8000",
-        "http://uwcourseplan.com",
-    ]);
+    api::run_server(config);
 }
@@ -3,9 +3,9 @@
 
 use diesel;
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
 use chrono::{DateTime, Utc};
 
+use api::db::{self, DbConnection};
 use schema::*;
 use super::users::User;
 
@@ -15,6 +15,10 @@ pub struct CoursePlan {
     pub id: i32,
     pub user_id: i32,
     pub created_at: DateTime<Utc>,
+    /// The identifier of the program template that the course plan was created from (if any)
+    pub template_id: Option<String>,
+    /// Incremented by every change made to the course plan
+    pub revision: i32,
 }
 
 #[derive(Debug, Insertable, Associations)]
@@ -22,16 +26,17 @@ pub struct CoursePlan {
 #[table_name="course_plans"]
 struct NewCoursePlan {
     pub user_id: i32,
+    pub template_id: Option<String>,
 }
 
 /// Retrieve the default course plan for a given user
-pub fn get_default(conn: &PgConnection, user: &User) -> QueryResult<CoursePlan> {
+pub fn get_default(conn: &DbConnection, user: &User) -> QueryResult<CoursePlan> {
     CoursePlan::belonging_to(user)
         .first::<CoursePlan>(conn)
 }
 
 /// Retrieve a course plan for a given user based on the course plan identifier
-pub fn get(conn: &PgConnection, course_plan_id: i32, user: &User) -> QueryResult<CoursePlan> {
+pub fn get(conn: &DbConnection, course_plan_id: i32, user: &User) -> QueryResult<CoursePlan> {
     use schema::course_plans::dsl::{course_plans, user_id, id};
 
     course_plans.filter(user_id.eq(user.id))
@@ -39,13 +44,87 @@ pub fn get(conn: &PgConnection, course_plan_id: i32, user: &User) -> QueryResult
         .first::<CoursePlan>(conn)
 }
 
+/// Retrieve a course plan based on the course plan identifier, regardless of which user it belongs
+/// to. Only use this when access to the course plan has already been checked some other way.
+pub fn find(conn: &DbConnection, course_plan_id: i32) -> QueryResult<CoursePlan> {
+    use schema::course_plans::dsl::course_plans;
+
+    course_plans.find(course_plan_id)
+        .first::<CoursePlan>(conn)
+}
+
 /// Inserts a new course plan in the database and returns the complete record
-pub fn create(conn: &PgConnection, user: &User) -> QueryResult<CoursePlan> {
+pub fn create(conn: &DbConnection, user: &User, template_id: Option<String>) -> QueryResult<CoursePlan> {
     let new_course_plan = NewCoursePlan {
         user_id: user.id,
+        template_id,
     };
 
-    diesel::insert_into(course_plans::table)
-        .values(&new_course_plan)
-        .get_result(conn)
+    db::insert(conn, course_plans::table, &new_course_plan)
+}
+
+/// Increments the revision of a course plan and returns the new revision. If an expected revision
+/// is given, the course plan is only updated if it is still at that revision. Returns None if the
+/// course plan was not updated.
+pub fn increment_revision(conn: &DbConnection, course_plan_id: i32, expected_revision: Option<i32>) -> QueryResult<Option<i32>> {
+    use schema::course_plans::dsl::{course_plans, id, revision};
+
+    conn.transaction(|| {
+        let course_plan = course_plans.filter(id.eq(course_plan_id));
+        let updated = match expected_revision {
+            Some(expected) => diesel::update(course_plan.filter(revision.eq(expected)))
+                .set(revision.eq(revision + 1))
+                .execute(conn)?,
+            None => diesel::update(course_plan)
+                .set(revision.eq(revision + 1))
+                .execute(conn)?,
+        };
+        if updated == 0 {
+            return Ok(None);
+        }
+
+        // No other change can be made to the course plan until the transaction ends, so this is
+        // the revision that was just set
+        course_plans.find(course_plan_id)
+            .select(revision)
+            .first::<i32>(conn)
+            .map(Some)
+    })
+}
+
+/// Changes the program template that a course plan is based on
+pub fn set_template(conn: &DbConnection, course_plan_id: i32, new_template_id: &str) -> QueryResult<usize> {
+    use schema::course_plans::dsl::{course_plans, template_id};
+
+    diesel::update(course_plans.find(course_plan_id))
+        .set(template_id.eq(new_template_id))
+        .execute(conn)
+}
+
+/// Permanently deletes a course plan along with its terms, courses, history and share links
+pub fn delete(conn: &DbConnection, course_plan_id: i32) -> QueryResult<usize> {
+    use schema::course_plans::dsl::course_plans;
+
+    // Everything that belongs to the course plan is removed by the database (ON DELETE CASCADE)
+    diesel::delete(course_plans.find(course_plan_id))
+        .execute(conn)
+}
+
+/// Counts the course plans created from each program template, most used first
+pub fn template_usage(conn: &DbConnection) -> QueryResult<Vec<(String, i64)>> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::BigInt;
+    use schema::course_plans::dsl::{course_plans, template_id};
+
+    // diesel does not allow selecting an aggregate function (count_star) along with the grouped
+    // column, so the count has to be written out
+    let usage = course_plans.filter(template_id.is_not_null())
+        .group_by(template_id)
+        .select((template_id, sql::<BigInt>("COUNT(*)")))
+        .order(sql::<BigInt>("COUNT(*)").desc())
+        .load::<(Option<String>, i64)>(conn)?;
+
+    Ok(usage.into_iter()
+        .filter_map(|(template, count)| template.map(|template| (template, count)))
+        .collect())
 }
@@ -0,0 +1,78 @@
+// https://github.com/diesel-rs/diesel/issues/1785
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use diesel;
+use diesel::prelude::*;
+use chrono::{DateTime, Utc};
+
+use api::db::{self, DbConnection};
+use schema::*;
+use super::users::User;
+
+/// An account with an authentication provider (e.g. Google or GitHub) that can be used to log in
+/// as a user. A user can have any number of identities, but at most one per provider.
+#[derive(Debug, Clone, Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name="identities"]
+pub struct Identity {
+    pub id: i32,
+    pub user_id: i32,
+    /// The name of the provider (e.g. "google")
+    pub provider: String,
+    /// The unique ID of the account with the provider
+    pub subject: String,
+    /// The email of the account with the provider the last time it was used to log in
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable, Associations)]
+#[belongs_to(User)]
+#[table_name="identities"]
+struct NewIdentity<'a> {
+    pub user_id: i32,
+    pub provider: &'a str,
+    pub subject: &'a str,
+    pub email: &'a str,
+}
+
+/// Retrieve all of the identities of a user
+pub fn all(conn: &DbConnection, user: &User) -> QueryResult<Vec<Identity>> {
+    Identity::belonging_to(user)
+        .order(identities::id)
+        .load::<Identity>(conn)
+}
+
+/// Retrieve the identity with the given provider and subject
+pub fn find(conn: &DbConnection, identity_provider: &str, identity_subject: &str) -> QueryResult<Option<Identity>> {
+    use schema::identities::dsl::*;
+
+    // This can only return up to one identity because of the unique constraint on these columns
+    identities.filter(provider.eq(identity_provider))
+        .filter(subject.eq(identity_subject))
+        .first::<Identity>(conn)
+        .optional()
+}
+
+/// Links a new identity to the given user and returns that record
+pub fn create(conn: &DbConnection, user: &User, provider: &str, subject: &str, email: &str) -> QueryResult<Identity> {
+    let new_identity = NewIdentity {
+        user_id: user.id,
+        provider,
+        subject,
+        email,
+    };
+
+    db::insert(conn, identities::table, &new_identity)
+}
+
+/// Records the latest email of an identity
+pub fn update_email(conn: &DbConnection, identity_id: i32, new_email: &str) -> QueryResult<Identity> {
+    use schema::identities::dsl::{identities, email};
+
+    diesel::update(identities.find(identity_id))
+        .set(email.eq(new_email))
+        .execute(conn)?;
+    identities.find(identity_id)
+        .first::<Identity>(conn)
+}
@@ -1,4 +1,9 @@
 pub mod users;
+pub mod identities;
 pub mod course_plans;
 pub mod terms;
 pub mod term_courses;
+pub mod plan_events;
+pub mod share_links;
+pub mod sessions;
+pub mod plan_changes;
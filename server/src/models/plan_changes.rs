@@ -0,0 +1,249 @@
+// https://github.com/diesel-rs/diesel/issues/1785
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use diesel;
+use diesel::prelude::*;
+use diesel::result::Error as QueryError;
+use chrono::{DateTime, Utc};
+
+use api::db::DbConnection;
+use schema::*;
+use super::course_plans::CoursePlan;
+use super::terms::Term;
+use super::term_courses::TermCourse;
+
+/// The kinds of changes that can be made to the terms and courses of a course plan. Unlike plan
+/// events, these describe what happened to the data rather than what the user did (e.g. undoing
+/// the creation of a term is a TermDeleted change).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    TermAdded,
+    TermRenamed,
+    TermMoved,
+    TermDeleted,
+    CourseAdded,
+    CourseRenamed,
+    CourseMoved,
+    CourseDeleted,
+}
+
+impl ChangeKind {
+    /// The name of the change kind as it is stored in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::TermAdded => "term_added",
+            ChangeKind::TermRenamed => "term_renamed",
+            ChangeKind::TermMoved => "term_moved",
+            ChangeKind::TermDeleted => "term_deleted",
+            ChangeKind::CourseAdded => "course_added",
+            ChangeKind::CourseRenamed => "course_renamed",
+            ChangeKind::CourseMoved => "course_moved",
+            ChangeKind::CourseDeleted => "course_deleted",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownChangeKind(String);
+
+impl fmt::Display for UnknownChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown plan change kind: {}", self.0)
+    }
+}
+
+impl Error for UnknownChangeKind {
+    fn description(&self) -> &str {
+        "unknown plan change kind"
+    }
+}
+
+impl FromStr for ChangeKind {
+    type Err = UnknownChangeKind;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match text {
+            "term_added" => ChangeKind::TermAdded,
+            "term_renamed" => ChangeKind::TermRenamed,
+            "term_moved" => ChangeKind::TermMoved,
+            "term_deleted" => ChangeKind::TermDeleted,
+            "course_added" => ChangeKind::CourseAdded,
+            "course_renamed" => ChangeKind::CourseRenamed,
+            "course_moved" => ChangeKind::CourseMoved,
+            "course_deleted" => ChangeKind::CourseDeleted,
+            _ => return Err(UnknownChangeKind(text.to_string())),
+        })
+    }
+}
+
+/// A change to be recorded. Which of the optional values are set depends on the kind of change.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// The term that changed, or the term of the course that changed
+    pub term_id: i32,
+    /// The course that changed, if the change is to a course
+    pub term_course_id: Option<i32>,
+    /// The name of the term or course that was added or the new name of a renamed term or course
+    pub name: Option<String>,
+    /// The index of a term that was added or moved among the terms that are not deleted
+    pub position: Option<i32>,
+}
+
+impl Change {
+    pub fn term_added(term: &Term, position: i32) -> Self {
+        Change {
+            kind: ChangeKind::TermAdded,
+            term_id: term.id,
+            term_course_id: None,
+            name: Some(term.name.clone()),
+            position: Some(position),
+        }
+    }
+
+    pub fn term_renamed(term: &Term) -> Self {
+        Change {
+            kind: ChangeKind::TermRenamed,
+            term_id: term.id,
+            term_course_id: None,
+            name: Some(term.name.clone()),
+            position: None,
+        }
+    }
+
+    pub fn term_moved(term: &Term, position: i32) -> Self {
+        Change {
+            kind: ChangeKind::TermMoved,
+            term_id: term.id,
+            term_course_id: None,
+            name: None,
+            position: Some(position),
+        }
+    }
+
+    pub fn term_deleted(term_id: i32) -> Self {
+        Change {
+            kind: ChangeKind::TermDeleted,
+            term_id,
+            term_course_id: None,
+            name: None,
+            position: None,
+        }
+    }
+
+    pub fn course_added(course: &TermCourse) -> Self {
+        Change {
+            kind: ChangeKind::CourseAdded,
+            term_id: course.term_id,
+            term_course_id: Some(course.id),
+            name: Some(course.name.clone()),
+            position: None,
+        }
+    }
+
+    pub fn course_renamed(course: &TermCourse) -> Self {
+        Change {
+            kind: ChangeKind::CourseRenamed,
+            term_id: course.term_id,
+            term_course_id: Some(course.id),
+            name: Some(course.name.clone()),
+            position: None,
+        }
+    }
+
+    /// The course is now in the given term
+    pub fn course_moved(course: &TermCourse) -> Self {
+        Change {
+            kind: ChangeKind::CourseMoved,
+            term_id: course.term_id,
+            term_course_id: Some(course.id),
+            name: None,
+            position: None,
+        }
+    }
+
+    pub fn course_deleted(term_course_id: i32, term_id: i32) -> Self {
+        Change {
+            kind: ChangeKind::CourseDeleted,
+            term_id,
+            term_course_id: Some(term_course_id),
+            name: None,
+            position: None,
+        }
+    }
+}
+
+/// A record of a single change made to the terms and courses of a course plan
+#[derive(Debug, Clone, Identifiable, Queryable, Associations)]
+#[belongs_to(CoursePlan)]
+pub struct PlanChange {
+    pub id: i32,
+    pub course_plan_id: i32,
+    /// The revision of the course plan that the change was made in
+    pub revision: i32,
+    pub kind: String,
+    pub term_id: i32,
+    pub term_course_id: Option<i32>,
+    pub name: Option<String>,
+    pub position: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PlanChange {
+    /// Parses the kind of this change
+    pub fn kind(&self) -> QueryResult<ChangeKind> {
+        self.kind.parse().map_err(|err| QueryError::DeserializationError(Box::new(err)))
+    }
+}
+
+#[derive(Debug, Insertable, Associations)]
+#[belongs_to(CoursePlan)]
+#[table_name="plan_changes"]
+struct NewPlanChange {
+    pub course_plan_id: i32,
+    pub revision: i32,
+    pub kind: String,
+    pub term_id: i32,
+    pub term_course_id: Option<i32>,
+    pub name: Option<String>,
+    pub position: Option<i32>,
+}
+
+/// Retrieve the changes made to a course plan after the given revision in the order they were made
+pub fn since(conn: &DbConnection, course_plan: &CoursePlan, after_revision: i32) -> QueryResult<Vec<PlanChange>> {
+    use schema::plan_changes::dsl::*;
+
+    plan_changes.filter(course_plan_id.eq(course_plan.id))
+        .filter(revision.gt(after_revision))
+        .order(id)
+        .load::<PlanChange>(conn)
+}
+
+/// Records a change to the given course plan as part of its current revision. This must be called
+/// in the same transaction as the change itself.
+pub fn record(conn: &DbConnection, plan_id: i32, change: Change) -> QueryResult<()> {
+    use schema::course_plans::dsl::{course_plans, revision};
+
+    let current_revision = course_plans.find(plan_id)
+        .select(revision)
+        .first::<i32>(conn)?;
+    let Change {kind, term_id, term_course_id, name, position} = change;
+    let new_change = NewPlanChange {
+        course_plan_id: plan_id,
+        revision: current_revision,
+        kind: kind.as_str().to_string(),
+        term_id,
+        term_course_id,
+        name,
+        position,
+    };
+
+    diesel::insert_into(plan_changes::table)
+        .values(&new_change)
+        .execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,345 @@
+// https://github.com/diesel-rs/diesel/issues/1785
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use diesel;
+use diesel::prelude::*;
+use diesel::result::Error as QueryError;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use api::db::{self, DbConnection};
+use schema::*;
+use super::course_plans::CoursePlan;
+use super::users::User;
+use super::terms::Term;
+use super::term_courses::TermCourse;
+
+/// The kinds of changes that can be made to a course plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    CreateTerm,
+    DeleteTerm,
+    CreateTermCourse,
+    DeleteTermCourse,
+    RestoreTerm,
+    RestoreTermCourse,
+    RenameTerm,
+    MoveTerm,
+    MoveTermCourse,
+    RenameTermCourse,
+}
+
+impl EventKind {
+    /// The name of the event kind as it is stored in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::CreateTerm => "create_term",
+            EventKind::DeleteTerm => "delete_term",
+            EventKind::CreateTermCourse => "create_term_course",
+            EventKind::DeleteTermCourse => "delete_term_course",
+            EventKind::RestoreTerm => "restore_term",
+            EventKind::RestoreTermCourse => "restore_term_course",
+            EventKind::RenameTerm => "rename_term",
+            EventKind::MoveTerm => "move_term",
+            EventKind::MoveTermCourse => "move_term_course",
+            EventKind::RenameTermCourse => "rename_term_course",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownEventKind(String);
+
+impl fmt::Display for UnknownEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown plan event kind: {}", self.0)
+    }
+}
+
+impl Error for UnknownEventKind {
+    fn description(&self) -> &str {
+        "unknown plan event kind"
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = UnknownEventKind;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match text {
+            "create_term" => EventKind::CreateTerm,
+            "delete_term" => EventKind::DeleteTerm,
+            "create_term_course" => EventKind::CreateTermCourse,
+            "delete_term_course" => EventKind::DeleteTermCourse,
+            "restore_term" => EventKind::RestoreTerm,
+            "restore_term_course" => EventKind::RestoreTermCourse,
+            "rename_term" => EventKind::RenameTerm,
+            "move_term" => EventKind::MoveTerm,
+            "move_term_course" => EventKind::MoveTermCourse,
+            "rename_term_course" => EventKind::RenameTermCourse,
+            _ => return Err(UnknownEventKind(text.to_string())),
+        })
+    }
+}
+
+/// The data changed by an event, stored as JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Snapshot {
+    Term(TermSnapshot),
+    Course(CourseSnapshot),
+}
+
+impl Snapshot {
+    /// Whether the snapshot includes any of the given terms or courses, or a course in any of the
+    /// given terms
+    fn refers_to(&self, term_ids: &[i32], course_ids: &[i32]) -> bool {
+        match self {
+            Snapshot::Term(term) => term_ids.contains(&term.id)
+                || term.courses.iter().any(|course| course.refers_to(term_ids, course_ids)),
+            Snapshot::Course(course) => course.refers_to(term_ids, course_ids),
+        }
+    }
+}
+
+/// The state of a term (and its courses) before or after an event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermSnapshot {
+    pub id: i32,
+    pub name: String,
+    pub courses: Vec<CourseSnapshot>,
+    /// The index of the term among the terms of its course plan, only recorded when a term is moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<i32>,
+}
+
+impl TermSnapshot {
+    pub fn new(term: &Term, courses: &[TermCourse]) -> Self {
+        TermSnapshot {
+            id: term.id,
+            name: term.name.clone(),
+            courses: courses.iter().map(CourseSnapshot::from).collect(),
+            position: None,
+        }
+    }
+
+    /// A snapshot of a term that includes its position
+    pub fn at(term: &Term, courses: &[TermCourse], position: i32) -> Self {
+        TermSnapshot {position: Some(position), ..TermSnapshot::new(term, courses)}
+    }
+}
+
+/// The state of a single term course before or after an event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CourseSnapshot {
+    pub id: i32,
+    pub term_id: i32,
+    pub name: String,
+}
+
+impl CourseSnapshot {
+    fn refers_to(&self, term_ids: &[i32], course_ids: &[i32]) -> bool {
+        course_ids.contains(&self.id) || term_ids.contains(&self.term_id)
+    }
+}
+
+impl<'a> From<&'a TermCourse> for CourseSnapshot {
+    fn from(course: &'a TermCourse) -> Self {
+        CourseSnapshot {
+            id: course.id,
+            term_id: course.term_id,
+            name: course.name.clone(),
+        }
+    }
+}
+
+/// A record of a single change made to a course plan
+#[derive(Debug, Clone, Identifiable, Queryable, Associations)]
+#[belongs_to(CoursePlan)]
+#[belongs_to(User)]
+pub struct PlanEvent {
+    pub id: i32,
+    pub course_plan_id: i32,
+    pub user_id: i32,
+    pub kind: String,
+    /// JSON snapshot of the changed data before the event (if any)
+    pub before: Option<String>,
+    /// JSON snapshot of the changed data after the event (if any)
+    pub after: Option<String>,
+    /// The date that this event was undone, NULL if it is still in effect
+    pub undone_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    /// The revision of the course plan that this event is part of. Every event of a revision is
+    /// undone together. NULL for events recorded before revisions were stored with events.
+    pub revision: Option<i32>,
+}
+
+impl PlanEvent {
+    /// Parses the kind of this event
+    pub fn kind(&self) -> QueryResult<EventKind> {
+        self.kind.parse().map_err(|err| QueryError::DeserializationError(Box::new(err)))
+    }
+
+    /// Parses the snapshot of the data from before this event
+    pub fn before_snapshot<T: DeserializeOwned>(&self) -> QueryResult<T> {
+        parse_snapshot(&self.before)
+    }
+
+    /// Parses the snapshot of the data from after this event
+    pub fn after_snapshot<T: DeserializeOwned>(&self) -> QueryResult<T> {
+        parse_snapshot(&self.after)
+    }
+}
+
+fn parse_snapshot<T: DeserializeOwned>(snapshot: &Option<String>) -> QueryResult<T> {
+    let snapshot = snapshot.as_ref().ok_or(QueryError::NotFound)?;
+    serde_json::from_str(snapshot).map_err(|err| QueryError::DeserializationError(Box::new(err)))
+}
+
+#[derive(Debug, Insertable, Associations)]
+#[belongs_to(CoursePlan)]
+#[belongs_to(User)]
+#[table_name="plan_events"]
+struct NewPlanEvent {
+    pub course_plan_id: i32,
+    pub user_id: i32,
+    pub kind: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub revision: Option<i32>,
+}
+
+/// Retrieve the history of a course plan, most recent event first
+pub fn all(conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<Vec<PlanEvent>> {
+    use schema::plan_events::dsl::*;
+
+    plan_events.filter(course_plan_id.eq(course_plan.id))
+        .order(id.desc())
+        .load::<PlanEvent>(conn)
+}
+
+/// Retrieve a plan event based on its identifier
+pub fn get(conn: &DbConnection, event_id: i32) -> QueryResult<PlanEvent> {
+    use schema::plan_events::dsl::plan_events;
+
+    plan_events.find(event_id)
+        .first::<PlanEvent>(conn)
+}
+
+/// Retrieve the most recent event in the course plan that has not been undone
+pub fn last_active(conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<Option<PlanEvent>> {
+    use schema::plan_events::dsl::*;
+
+    plan_events.filter(course_plan_id.eq(course_plan.id))
+        .filter(undone_at.is_null())
+        .order(id.desc())
+        .first::<PlanEvent>(conn)
+        .optional()
+}
+
+/// Retrieve all of the events that have not been undone and that happened after the given event,
+/// most recent event first. Events that are part of the same revision as the given event are not
+/// included.
+pub fn active_since(conn: &DbConnection, event: &PlanEvent) -> QueryResult<Vec<PlanEvent>> {
+    use schema::plan_events::dsl::*;
+
+    let events = plan_events.filter(course_plan_id.eq(event.course_plan_id))
+        .filter(id.gt(event.id))
+        .filter(undone_at.is_null())
+        .order(id.desc())
+        .load::<PlanEvent>(conn)?;
+    Ok(events.into_iter()
+        .filter(|later| event.revision.is_none() || later.revision != event.revision)
+        .collect())
+}
+
+/// Retrieve all of the events of the given revision of a course plan that have not been undone,
+/// most recent event first
+pub fn active_in_revision(conn: &DbConnection, plan_id: i32, plan_revision: i32) -> QueryResult<Vec<PlanEvent>> {
+    use schema::plan_events::dsl::*;
+
+    plan_events.filter(course_plan_id.eq(plan_id))
+        .filter(revision.eq(plan_revision))
+        .filter(undone_at.is_null())
+        .order(id.desc())
+        .load::<PlanEvent>(conn)
+}
+
+/// Records an event in the history of the given course plan as part of its current revision. This
+/// must be called in the same transaction as the change itself.
+pub fn record(
+    conn: &DbConnection,
+    course_plan_id: i32,
+    user: &User,
+    kind: EventKind,
+    before: Option<Snapshot>,
+    after: Option<Snapshot>,
+) -> QueryResult<PlanEvent> {
+    let current_revision = course_plans::table.find(course_plan_id)
+        .select(course_plans::revision)
+        .first::<i32>(conn)?;
+    let new_event = NewPlanEvent {
+        course_plan_id,
+        user_id: user.id,
+        kind: kind.as_str().to_string(),
+        before: to_json(before)?,
+        after: to_json(after)?,
+        revision: Some(current_revision),
+    };
+
+    db::insert(conn, plan_events::table, &new_event)
+}
+
+fn to_json(snapshot: Option<Snapshot>) -> QueryResult<Option<String>> {
+    match snapshot {
+        Some(snapshot) => serde_json::to_string(&snapshot)
+            .map(Some)
+            .map_err(|err| QueryError::SerializationError(Box::new(err))),
+        None => Ok(None),
+    }
+}
+
+/// Permanently removes the events of a course plan that refer to any of the given terms or courses.
+/// Used when those terms and courses are purged, since undoing any of those events would need
+/// records that no longer exist.
+pub fn purge_referring_to(
+    conn: &DbConnection,
+    course_plan_id: i32,
+    term_ids: &[i32],
+    course_ids: &[i32],
+) -> QueryResult<usize> {
+    let events = plan_events::table
+        .filter(plan_events::course_plan_id.eq(course_plan_id))
+        .load::<PlanEvent>(conn)?;
+
+    let refers_to = |snapshot: &Option<String>| -> QueryResult<bool> {
+        match snapshot {
+            Some(_) => Ok(parse_snapshot::<Snapshot>(snapshot)?.refers_to(term_ids, course_ids)),
+            None => Ok(false),
+        }
+    };
+    let mut purged = Vec::new();
+    for event in events {
+        if refers_to(&event.before)? || refers_to(&event.after)? {
+            purged.push(event.id);
+        }
+    }
+
+    diesel::delete(plan_events::table.filter(plan_events::id.eq_any(purged)))
+        .execute(conn)
+}
+
+/// Marks the given event as undone
+pub fn mark_undone(conn: &DbConnection, event_id: i32) -> QueryResult<PlanEvent> {
+    use schema::plan_events::dsl::{plan_events, undone_at};
+
+    diesel::update(plan_events.find(event_id))
+        .set(undone_at.eq(Some(Utc::now())))
+        .execute(conn)?;
+    get(conn, event_id)
+}
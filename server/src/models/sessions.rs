@@ -0,0 +1,170 @@
+// https://github.com/diesel-rs/diesel/issues/1785
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use diesel;
+use diesel::prelude::*;
+use chrono::{DateTime, Utc, Duration};
+
+use api::db::{self, DbConnection};
+use schema::*;
+use sql_types::UtcDateTime;
+use super::users::User;
+
+/// How often the last used date of a session is updated. Updating it on every request would mean
+/// a database write for every request.
+const LAST_USED_PRECISION_SECS: i64 = 60;
+
+/// A login session of a user on a particular device. Access tokens are short-lived and reference
+/// their session by its jti, so revoking a session takes effect for every token issued for it.
+#[derive(Debug, Clone, Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    /// The JWT ID included in every access token issued for this session
+    pub jti: String,
+    /// Hash of the current refresh token. The refresh token itself is never stored.
+    pub refresh_token_hash: String,
+    /// Hash of the refresh token that was replaced by the current one. Seeing it again means the
+    /// refresh token was stolen and reused.
+    pub previous_refresh_token_hash: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    /// The date after which the refresh token can no longer be used
+    pub expires_at: DateTime<Utc>,
+    /// The date that this session was revoked (e.g. by logging out), NULL if it is still active
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable, Associations)]
+#[belongs_to(User)]
+#[table_name="sessions"]
+struct NewSession {
+    pub user_id: i32,
+    pub jti: String,
+    pub refresh_token_hash: String,
+    pub user_agent: Option<String>,
+    pub expires_at: UtcDateTime,
+}
+
+/// Retrieve all of the sessions of a user that have not expired or been revoked, most recently
+/// used first
+pub fn all_active(conn: &DbConnection, user: &User) -> QueryResult<Vec<Session>> {
+    use schema::sessions::dsl::*;
+
+    sessions.filter(user_id.eq(user.id))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.gt(UtcDateTime(Utc::now())))
+        .order(last_used_at.desc())
+        .load::<Session>(conn)
+}
+
+/// Retrieve the session with the given JWT ID as long as it has not expired or been revoked
+pub fn get_active(conn: &DbConnection, session_jti: &str) -> QueryResult<Option<Session>> {
+    use schema::sessions::dsl::*;
+
+    sessions.filter(jti.eq(session_jti))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.gt(UtcDateTime(Utc::now())))
+        .first::<Session>(conn)
+        .optional()
+}
+
+/// Retrieve the session whose current refresh token has the given hash
+pub fn get_by_refresh_token(conn: &DbConnection, token_hash: &str) -> QueryResult<Option<Session>> {
+    use schema::sessions::dsl::*;
+
+    sessions.filter(refresh_token_hash.eq(token_hash))
+        .first::<Session>(conn)
+        .optional()
+}
+
+/// Retrieve the session whose previous refresh token has the given hash
+pub fn get_by_previous_refresh_token(conn: &DbConnection, token_hash: &str) -> QueryResult<Option<Session>> {
+    use schema::sessions::dsl::*;
+
+    sessions.filter(previous_refresh_token_hash.eq(token_hash))
+        .first::<Session>(conn)
+        .optional()
+}
+
+/// Inserts a new session in the database and returns that record
+pub fn create(
+    conn: &DbConnection,
+    user: &User,
+    jti: String,
+    refresh_token_hash: String,
+    user_agent: Option<String>,
+    expires_at: DateTime<Utc>,
+) -> QueryResult<Session> {
+    let new_session = NewSession {
+        user_id: user.id,
+        jti,
+        refresh_token_hash,
+        user_agent,
+        expires_at: UtcDateTime(expires_at),
+    };
+
+    db::insert(conn, sessions::table, &new_session)
+}
+
+/// Records that the session was just used, at most once every LAST_USED_PRECISION_SECS
+pub fn touch(conn: &DbConnection, session: &Session) -> QueryResult<()> {
+    use schema::sessions::dsl::{sessions, last_used_at};
+
+    let now = Utc::now();
+    if now - session.last_used_at >= Duration::seconds(LAST_USED_PRECISION_SECS) {
+        diesel::update(sessions.find(session.id))
+            .set(last_used_at.eq(UtcDateTime(now)))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Replaces the refresh token of a session and extends its expiry. Returns None if the refresh
+/// token was already replaced (e.g. by a concurrent request).
+pub fn rotate(
+    conn: &DbConnection,
+    session: &Session,
+    new_refresh_token_hash: String,
+    new_expires_at: DateTime<Utc>,
+) -> QueryResult<Option<Session>> {
+    use schema::sessions::dsl::*;
+
+    conn.transaction(|| {
+        let updated = diesel::update(sessions.find(session.id).filter(refresh_token_hash.eq(&session.refresh_token_hash)))
+            .set((
+                previous_refresh_token_hash.eq(Some(session.refresh_token_hash.clone())),
+                refresh_token_hash.eq(new_refresh_token_hash),
+                expires_at.eq(UtcDateTime(new_expires_at)),
+                last_used_at.eq(UtcDateTime(Utc::now())),
+            ))
+            .execute(conn)?;
+        if updated == 0 {
+            return Ok(None);
+        }
+
+        sessions.find(session.id)
+            .first::<Session>(conn)
+            .map(Some)
+    })
+}
+
+/// Revokes a session so that none of its tokens can be used anymore
+pub fn revoke(conn: &DbConnection, session_id: i32) -> QueryResult<usize> {
+    use schema::sessions::dsl::{sessions, revoked_at};
+
+    diesel::update(sessions.find(session_id).filter(revoked_at.is_null()))
+        .set(revoked_at.eq(Some(Utc::now())))
+        .execute(conn)
+}
+
+/// Revokes every session of the given user, logging them out everywhere
+pub fn revoke_all(conn: &DbConnection, user: &User) -> QueryResult<usize> {
+    use schema::sessions::dsl::{sessions, user_id, revoked_at};
+
+    diesel::update(sessions.filter(user_id.eq(user.id)).filter(revoked_at.is_null()))
+        .set(revoked_at.eq(Some(Utc::now())))
+        .execute(conn)
+}
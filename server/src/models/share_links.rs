@@ -0,0 +1,103 @@
+// https://github.com/diesel-rs/diesel/issues/1785
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use diesel;
+use diesel::prelude::*;
+use chrono::{DateTime, Utc};
+
+use api::db::{self, DbConnection};
+use schema::*;
+use sql_types::UtcDateTime;
+use super::course_plans::CoursePlan;
+use super::users::User;
+
+#[derive(Debug, Clone, Identifiable, Queryable, Associations)]
+#[belongs_to(CoursePlan)]
+pub struct ShareLink {
+    pub id: i32,
+    pub course_plan_id: i32,
+    /// The unguessable token used to access the shared course plan
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+    /// The date after which this link can no longer be used, NULL if it never expires
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The date that this link was revoked, NULL if it has not been revoked
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable, Associations)]
+#[belongs_to(CoursePlan)]
+#[table_name="share_links"]
+struct NewShareLink {
+    pub course_plan_id: i32,
+    pub token: String,
+    pub expires_at: Option<UtcDateTime>,
+}
+
+/// Retrieve all the share links created for a given course plan
+pub fn all(conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<Vec<ShareLink>> {
+    use schema::share_links::dsl::*;
+
+    share_links.filter(course_plan_id.eq(course_plan.id))
+        .order(id)
+        .load::<ShareLink>(conn)
+}
+
+/// Retrieve a share link based on its identifier
+pub fn get(conn: &DbConnection, share_link_id: i32) -> QueryResult<ShareLink> {
+    use schema::share_links::dsl::share_links;
+
+    share_links.find(share_link_id)
+        .first::<ShareLink>(conn)
+}
+
+/// Retrieve the share link with the given token as long as it has not expired or been revoked
+pub fn get_active(conn: &DbConnection, share_token: &str) -> QueryResult<Option<ShareLink>> {
+    use schema::share_links::dsl::*;
+
+    share_links.filter(token.eq(share_token))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.is_null().or(expires_at.gt(Some(Utc::now()))))
+        .first::<ShareLink>(conn)
+        .optional()
+}
+
+/// Checks if the specified share link belongs to the given user
+pub fn belongs_to_user(conn: &DbConnection, share_link_id: i32, user: &User) -> QueryResult<bool> {
+    use schema::share_links::dsl::{share_links, id as share_link_id_column};
+    use schema::course_plans::dsl::{course_plans, user_id};
+
+    share_links.inner_join(course_plans)
+        .filter(share_link_id_column.eq(share_link_id))
+        .filter(user_id.eq(user.id))
+        .select(share_link_id_column)
+        .get_result(conn)
+        .optional()
+        .map(|res: Option<i32>| res.is_some())
+}
+
+/// Inserts a new share link in the database and returns that record
+pub fn create(
+    conn: &DbConnection,
+    course_plan: &CoursePlan,
+    token: String,
+    expires_at: Option<DateTime<Utc>>,
+) -> QueryResult<ShareLink> {
+    let new_share_link = NewShareLink {
+        course_plan_id: course_plan.id,
+        token,
+        expires_at: expires_at.map(UtcDateTime),
+    };
+
+    db::insert(conn, share_links::table, &new_share_link)
+}
+
+/// Revokes a share link so it can no longer be used to access the course plan
+pub fn revoke(conn: &DbConnection, share_link_id: i32) -> QueryResult<ShareLink> {
+    use schema::share_links::dsl::{share_links, revoked_at};
+
+    diesel::update(share_links.find(share_link_id))
+        .set(revoked_at.eq(Some(Utc::now())))
+        .execute(conn)?;
+    get(conn, share_link_id)
+}
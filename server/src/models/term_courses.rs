@@ -3,9 +3,9 @@
 
 use diesel;
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
 use chrono::{DateTime, Utc};
 
+use api::db::{self, DbConnection};
 use schema::*;
 use super::terms::Term;
 use super::users::User;
@@ -17,6 +17,8 @@ pub struct TermCourse {
     pub term_id: i32,
     pub name: String,
     pub created_at: DateTime<Utc>,
+    /// The date that this course was deleted, NULL if it has not been deleted
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Insertable, Associations)]
@@ -27,31 +29,66 @@ struct NewTermCourse {
     pub name: String,
 }
 
-/// Retrieve the list of courses for a given term of a specific course plan
-pub fn all(conn: &PgConnection, term: &Term) -> QueryResult<Vec<TermCourse>> {
+/// Retrieve the list of courses for a given term of a specific course plan in the order they were
+/// added, not including deleted courses
+pub fn all(conn: &DbConnection, term: &Term) -> QueryResult<Vec<TermCourse>> {
     use schema::term_courses::dsl::*;
 
     term_courses.filter(term_id.eq(term.id))
+        .filter(deleted_at.is_null())
+        .order(id)
         .load::<TermCourse>(conn)
 }
 
+/// Retrieve the courses of all of the given terms with a single query, grouped in the same order
+/// as the terms. Deleted courses are not included.
+pub fn all_for_terms(conn: &DbConnection, terms: &[Term]) -> QueryResult<Vec<Vec<TermCourse>>> {
+    use schema::term_courses::dsl::{id, deleted_at};
+
+    let courses = TermCourse::belonging_to(terms)
+        .filter(deleted_at.is_null())
+        .order(id)
+        .load::<TermCourse>(conn)?;
+    Ok(courses.grouped_by(terms))
+}
+
 /// Retrieve a term course based on the term course identifier
-pub fn get(conn: &PgConnection, term_course_id: i32) -> QueryResult<TermCourse> {
+pub fn get(conn: &DbConnection, term_course_id: i32) -> QueryResult<TermCourse> {
     use schema::term_courses::dsl::term_courses;
 
     term_courses.find(term_course_id)
         .first::<TermCourse>(conn)
 }
 
-/// Checks if a course belongs to the specified user
-pub fn belongs_to_user(conn: &PgConnection, term_course_id: i32, user: &User) -> QueryResult<bool> {
-    use schema::term_courses::dsl::{term_courses, id as term_course_id_column};
-    use schema::terms::dsl::terms;
+/// Checks if a course belongs to the specified user and neither the course nor its term have been
+/// deleted
+pub fn belongs_to_user(conn: &DbConnection, term_course_id: i32, user: &User) -> QueryResult<bool> {
+    use schema::term_courses::dsl::{term_courses, id as term_course_id_column, deleted_at};
+    use schema::terms::dsl::{terms, deleted_at as term_deleted_at};
+    use schema::course_plans::dsl::{course_plans, user_id};
+
+    term_courses.inner_join(terms.inner_join(course_plans))
+        .filter(term_course_id_column.eq(term_course_id))
+        .filter(user_id.eq(user.id))
+        .filter(deleted_at.is_null())
+        .filter(term_deleted_at.is_null())
+        .select(term_course_id_column)
+        .get_result(conn)
+        .optional()
+        .map(|res: Option<i32>| res.is_some())
+}
+
+/// Checks if a deleted course belongs to the specified user and its term has not been deleted
+pub fn deleted_belongs_to_user(conn: &DbConnection, term_course_id: i32, user: &User) -> QueryResult<bool> {
+    use schema::term_courses::dsl::{term_courses, id as term_course_id_column, deleted_at};
+    use schema::terms::dsl::{terms, deleted_at as term_deleted_at};
     use schema::course_plans::dsl::{course_plans, user_id};
 
     term_courses.inner_join(terms.inner_join(course_plans))
         .filter(term_course_id_column.eq(term_course_id))
         .filter(user_id.eq(user.id))
+        .filter(deleted_at.is_not_null())
+        .filter(term_deleted_at.is_null())
         .select(term_course_id_column)
         .get_result(conn)
         .optional()
@@ -59,29 +96,63 @@ pub fn belongs_to_user(conn: &PgConnection, term_course_id: i32, user: &User) ->
 }
 
 /// Inserts a term course in the database and returns that record
-pub fn create(conn: &PgConnection, term: &Term, name: String) -> QueryResult<TermCourse> {
+pub fn create(conn: &DbConnection, term: &Term, name: String) -> QueryResult<TermCourse> {
     let new_term_course = NewTermCourse {
         term_id: term.id,
         name: name,
     };
 
-    diesel::insert_into(term_courses::table)
-        .values(&new_term_course)
-        .get_result(conn)
+    db::insert(conn, term_courses::table, &new_term_course)
 }
 
-/// Delete a course from a specified term
-pub fn delete(conn: &PgConnection, term_course_id: i32) -> QueryResult<usize> {
-    use schema::term_courses::dsl::term_courses;
+/// Changes the name of a course and returns the updated record
+pub fn rename(conn: &DbConnection, term_course_id: i32, new_name: String) -> QueryResult<TermCourse> {
+    use schema::term_courses::dsl::{term_courses, name};
+
+    diesel::update(term_courses.find(term_course_id))
+        .set(name.eq(new_name))
+        .execute(conn)?;
+    get(conn, term_course_id)
+}
 
-    diesel::delete(term_courses.find(term_course_id))
+/// Moves a course to a different term and returns the updated record
+pub fn move_to_term(conn: &DbConnection, term_course_id: i32, new_term_id: i32) -> QueryResult<TermCourse> {
+    use schema::term_courses::dsl::{term_courses, term_id};
+
+    diesel::update(term_courses.find(term_course_id))
+        .set(term_id.eq(new_term_id))
+        .execute(conn)?;
+    get(conn, term_course_id)
+}
+
+/// Delete a course from a specified term. The course is only marked as deleted so that it can be
+/// restored until it is purged.
+pub fn delete(conn: &DbConnection, term_course_id: i32) -> QueryResult<usize> {
+    use schema::term_courses::dsl::{term_courses, deleted_at};
+
+    diesel::update(term_courses.find(term_course_id).filter(deleted_at.is_null()))
+        .set(deleted_at.eq(Some(Utc::now())))
         .execute(conn)
 }
 
-/// Delete all the courses associated with a specified term
-pub fn delete_all(conn: &PgConnection, term_id: i32) -> QueryResult<usize> {
-    use schema::term_courses::dsl::{term_courses, term_id as term_id_column};
+/// Restore a course that was previously deleted
+pub fn restore(conn: &DbConnection, term_course_id: i32) -> QueryResult<usize> {
+    use schema::term_courses::dsl::{term_courses, deleted_at};
 
-    diesel::delete(term_courses.filter(term_id_column.eq(term_id)))
+    diesel::update(term_courses.find(term_course_id).filter(deleted_at.is_not_null()))
+        .set(deleted_at.eq(None::<DateTime<Utc>>))
         .execute(conn)
 }
+
+/// Permanently removes all the courses that were deleted before the given date, returning each
+/// removed course along with its term
+pub fn purge_deleted(conn: &DbConnection, deleted_before: DateTime<Utc>) -> QueryResult<Vec<(TermCourse, Term)>> {
+    use schema::term_courses::dsl::{term_courses, deleted_at};
+
+    let purged = term_courses.inner_join(terms::table)
+        .filter(deleted_at.lt(Some(deleted_before)))
+        .load::<(TermCourse, Term)>(conn)?;
+    diesel::delete(term_courses.filter(deleted_at.lt(Some(deleted_before))))
+        .execute(conn)?;
+    Ok(purged)
+}
@@ -3,11 +3,12 @@
 
 use diesel;
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
 use chrono::{DateTime, Utc};
 
+use api::db::{self, DbConnection};
 use schema::*;
 use super::course_plans::CoursePlan;
+use super::term_courses::TermCourse;
 use super::users::User;
 
 #[derive(Debug, Clone, Identifiable, Queryable, Associations)]
@@ -17,6 +18,11 @@ pub struct Term {
     pub course_plan_id: i32,
     pub name: String,
     pub created_at: DateTime<Utc>,
+    /// The date that this term was deleted, NULL if it has not been deleted
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Terms are ordered by position and then by ID. Positions are only meaningful relative to the
+    /// other terms of the same course plan.
+    pub position: i32,
 }
 
 #[derive(Debug, Insertable, Associations)]
@@ -25,55 +31,158 @@ pub struct Term {
 struct NewTerm {
     pub course_plan_id: i32,
     pub name: String,
+    pub position: i32,
 }
 
-/// Retrieve the list of terms for a given course plan
-pub fn all(conn: &PgConnection, course_plan: &CoursePlan) -> QueryResult<Vec<Term>> {
+/// Retrieve the list of terms for a given course plan in order, not including deleted terms
+pub fn all(conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<Vec<Term>> {
     use schema::terms::dsl::*;
 
     terms.filter(course_plan_id.eq(course_plan.id))
+        .filter(deleted_at.is_null())
+        .order((position, id))
         .load::<Term>(conn)
 }
 
+/// Retrieve the identifiers of the terms of a course plan in order, not including deleted terms
+fn ordered_ids(conn: &DbConnection, plan_id: i32) -> QueryResult<Vec<i32>> {
+    use schema::terms::dsl::*;
+
+    terms.filter(course_plan_id.eq(plan_id))
+        .filter(deleted_at.is_null())
+        .order((position, id))
+        .select(id)
+        .load::<i32>(conn)
+}
+
+/// The index of a term among the terms of its course plan that have not been deleted
+pub fn index(conn: &DbConnection, term: &Term) -> QueryResult<i32> {
+    ordered_ids(conn, term.course_plan_id)?.iter()
+        .position(|&term_id| term_id == term.id)
+        .map(|index| index as i32)
+        .ok_or(diesel::result::Error::NotFound)
+}
+
 /// Retrieve a term for a given user's course plan based on the term identifier
-pub fn get(conn: &PgConnection, term_id: i32) -> QueryResult<Term> {
+pub fn get(conn: &DbConnection, term_id: i32) -> QueryResult<Term> {
     use schema::terms::dsl::terms;
 
     terms.find(term_id)
         .first::<Term>(conn)
 }
 
-/// Checks if the specified term belongs to the given user
-pub fn belongs_to_user(conn: &PgConnection, term_id: i32, user: &User) -> QueryResult<bool> {
-    use schema::terms::dsl::{terms, id as term_id_column};
+/// Checks if the specified term belongs to the given user and has not been deleted
+pub fn belongs_to_user(conn: &DbConnection, term_id: i32, user: &User) -> QueryResult<bool> {
+    use schema::terms::dsl::{terms, id as term_id_column, deleted_at};
+    use schema::course_plans::dsl::course_plans;
+    use schema::users::dsl::{users, id as user_id};
+
+    terms.inner_join(course_plans.inner_join(users))
+        .filter(term_id_column.eq(term_id))
+        .filter(user_id.eq(user.id))
+        .filter(deleted_at.is_null())
+        .select(term_id_column)
+        .get_result(conn)
+        .optional()
+        .map(|res: Option<i32>| res.is_some())
+}
+
+/// Checks if the specified term belongs to the given user and has been deleted
+pub fn deleted_belongs_to_user(conn: &DbConnection, term_id: i32, user: &User) -> QueryResult<bool> {
+    use schema::terms::dsl::{terms, id as term_id_column, deleted_at};
     use schema::course_plans::dsl::course_plans;
     use schema::users::dsl::{users, id as user_id};
 
     terms.inner_join(course_plans.inner_join(users))
         .filter(term_id_column.eq(term_id))
         .filter(user_id.eq(user.id))
+        .filter(deleted_at.is_not_null())
         .select(term_id_column)
         .get_result(conn)
         .optional()
         .map(|res: Option<i32>| res.is_some())
 }
 
-/// Inserts a new term in the database and returns that record
-pub fn create(conn: &PgConnection, course_plan: &CoursePlan, name: String) -> QueryResult<Term> {
+/// Inserts a new term after every other term of the course plan and returns that record
+pub fn create(conn: &DbConnection, course_plan: &CoursePlan, name: String) -> QueryResult<Term> {
+    use diesel::dsl::max;
+
+    let last_position = terms::table.filter(terms::course_plan_id.eq(course_plan.id))
+        .select(max(terms::position))
+        .first::<Option<i32>>(conn)?;
     let new_term = NewTerm {
         course_plan_id: course_plan.id,
         name,
+        position: last_position.map_or(0, |last| last + 1),
     };
 
-    diesel::insert_into(terms::table)
-        .values(&new_term)
-        .get_result(conn)
+    db::insert(conn, terms::table, &new_term)
 }
 
-/// Delete a term in a specified course plan
-pub fn delete(conn: &PgConnection, term_id: i32) -> QueryResult<usize> {
-    use schema::terms::dsl::terms;
+/// Changes the name of a term and returns the updated record
+pub fn rename(conn: &DbConnection, term_id: i32, new_name: String) -> QueryResult<Term> {
+    use schema::terms::dsl::{terms, name};
+
+    diesel::update(terms.find(term_id))
+        .set(name.eq(new_name))
+        .execute(conn)?;
+    get(conn, term_id)
+}
+
+/// Moves a term to the given index among the terms of its course plan that have not been deleted.
+/// An index past the last term moves the term to the end. Returns the index that the term was
+/// actually moved to.
+pub fn move_to(conn: &DbConnection, term: &Term, index: usize) -> QueryResult<i32> {
+    use schema::terms::dsl::{terms, position};
+
+    let mut ids = ordered_ids(conn, term.course_plan_id)?;
+    let current = ids.iter().position(|&term_id| term_id == term.id)
+        .ok_or(diesel::result::Error::NotFound)?;
+    ids.remove(current);
+    let index = index.min(ids.len());
+    ids.insert(index, term.id);
+
+    for (new_position, term_id) in ids.into_iter().enumerate() {
+        diesel::update(terms.find(term_id))
+            .set(position.eq(new_position as i32))
+            .execute(conn)?;
+    }
+    Ok(index as i32)
+}
 
-    diesel::delete(terms.find(term_id))
+/// Delete a term in a specified course plan. The term is only marked as deleted so that it can be
+/// restored until it is purged. The courses of the term are left as is so that they come back
+/// when the term is restored.
+pub fn delete(conn: &DbConnection, term_id: i32) -> QueryResult<usize> {
+    use schema::terms::dsl::{terms, deleted_at};
+
+    diesel::update(terms.find(term_id).filter(deleted_at.is_null()))
+        .set(deleted_at.eq(Some(Utc::now())))
+        .execute(conn)
+}
+
+/// Restore a term that was previously deleted
+pub fn restore(conn: &DbConnection, term_id: i32) -> QueryResult<usize> {
+    use schema::terms::dsl::{terms, deleted_at};
+
+    diesel::update(terms.find(term_id).filter(deleted_at.is_not_null()))
+        .set(deleted_at.eq(None::<DateTime<Utc>>))
         .execute(conn)
 }
+
+/// Permanently removes all the terms (and their courses) that were deleted before the given date,
+/// returning each removed term along with every course that was removed with it
+pub fn purge_deleted(conn: &DbConnection, deleted_before: DateTime<Utc>) -> QueryResult<Vec<(Term, Vec<TermCourse>)>> {
+    use schema::terms::dsl::{terms, deleted_at};
+
+    let purged = terms.filter(deleted_at.lt(Some(deleted_before)))
+        .load::<Term>(conn)?;
+    // Including the courses that were deleted but are not old enough to be purged on their own
+    let courses = TermCourse::belonging_to(&purged)
+        .load::<TermCourse>(conn)?
+        .grouped_by(&purged);
+    // The courses of each term are removed along with it by the database (ON DELETE CASCADE)
+    diesel::delete(terms.filter(deleted_at.lt(Some(deleted_before))))
+        .execute(conn)?;
+    Ok(purged.into_iter().zip(courses).collect())
+}
@@ -0,0 +1,310 @@
+//! Operations that modify a course plan.
+//!
+//! Every change made to a course plan should go through this module so that it is recorded in the
+//! history of that plan and can be undone later. Each operation runs in its own transaction so a
+//! change is never applied without also being recorded. Every change to the terms and courses
+//! (including undoing a change) is also recorded in the change log that clients sync from.
+//!
+//! Each course plan has a revision number that goes up by one with every change made through
+//! `revise`. Clients send the revision they last saw so that a change based on an out of date copy
+//! of the course plan (e.g. from another browser tab) is rejected instead of silently overwriting
+//! what changed in the meantime.
+
+use diesel::Connection;
+use diesel::result::{QueryResult, Error as QueryError};
+
+use api::db::DbConnection;
+use models::{users::User, course_plans::{self, CoursePlan}, terms, term_courses, plan_events, plan_changes};
+use models::plan_events::{PlanEvent, EventKind, Snapshot, TermSnapshot, CourseSnapshot};
+use models::plan_changes::Change;
+
+/// A change was based on a revision of a course plan that is no longer the current one
+#[derive(Debug)]
+pub struct StaleRevision {
+    pub expected: i32,
+    pub current: i32,
+}
+
+/// Makes a change to a course plan, incrementing its revision exactly once no matter how many
+/// operations the change is made up of. If an expected revision is given and the course plan is
+/// at a different revision, nothing is changed. Returns the result of the change along with the
+/// new revision.
+///
+/// The revision is updated before the change is made so that the row lock it takes makes any
+/// other change to the same course plan wait until this one is committed.
+pub fn revise<T, E, F>(conn: &DbConnection, course_plan_id: i32, expected_revision: Option<i32>, change: F) -> Result<(T, i32), E>
+    where F: FnOnce() -> Result<T, E>,
+          E: From<QueryError> + From<StaleRevision>,
+{
+    conn.transaction(|| {
+        let revision = match course_plans::increment_revision(conn, course_plan_id, expected_revision)? {
+            Some(revision) => revision,
+            None => match expected_revision {
+                Some(expected) => {
+                    let current = course_plans::find(conn, course_plan_id)?.revision;
+                    return Err(StaleRevision {expected, current}.into());
+                },
+                None => return Err(QueryError::NotFound.into()),
+            },
+        };
+        Ok((change()?, revision))
+    })
+}
+
+/// Fails the same way as revise if an expected revision is given and the course plan is at a
+/// different revision, without changing anything. Used when a change turns out to do nothing, so
+/// that it is still rejected if it was made to a stale revision.
+pub fn check_revision<E>(conn: &DbConnection, course_plan_id: i32, expected_revision: Option<i32>) -> Result<(), E>
+    where E: From<QueryError> + From<StaleRevision>,
+{
+    if let Some(expected) = expected_revision {
+        let current = course_plans::find(conn, course_plan_id)?.revision;
+        if current != expected {
+            return Err(StaleRevision {expected, current}.into());
+        }
+    }
+    Ok(())
+}
+
+/// Adds a new term to the given course plan
+pub fn create_term(conn: &DbConnection, user: &User, course_plan: &CoursePlan, name: String) -> QueryResult<terms::Term> {
+    conn.transaction(|| {
+        let term = terms::create(conn, course_plan, name)?;
+        let after = TermSnapshot::new(&term, &[]);
+        plan_events::record(conn, course_plan.id, user, EventKind::CreateTerm,
+            None, Some(Snapshot::Term(after)))?;
+        term_added(conn, &term, &[])?;
+        Ok(term)
+    })
+}
+
+/// Removes a term and all of its courses from its course plan, returning the courses that were
+/// removed along with it
+pub fn delete_term(conn: &DbConnection, user: &User, term: &terms::Term) -> QueryResult<Vec<term_courses::TermCourse>> {
+    conn.transaction(|| {
+        let courses = term_courses::all(conn, term)?;
+        let before = TermSnapshot::new(term, &courses);
+        expect_one(terms::delete(conn, term.id)?)?;
+        plan_events::record(conn, term.course_plan_id, user, EventKind::DeleteTerm,
+            Some(Snapshot::Term(before)), None)?;
+        plan_changes::record(conn, term.course_plan_id, Change::term_deleted(term.id))?;
+        Ok(courses)
+    })
+}
+
+/// Adds a new course to the given term
+pub fn create_term_course(conn: &DbConnection, user: &User, term: &terms::Term, name: String) -> QueryResult<term_courses::TermCourse> {
+    conn.transaction(|| {
+        let course = term_courses::create(conn, term, name)?;
+        let after = CourseSnapshot::from(&course);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::CreateTermCourse,
+            None, Some(Snapshot::Course(after)))?;
+        plan_changes::record(conn, term.course_plan_id, Change::course_added(&course))?;
+        Ok(course)
+    })
+}
+
+/// Removes a course from its term
+pub fn delete_term_course(conn: &DbConnection, user: &User, course: &term_courses::TermCourse) -> QueryResult<()> {
+    conn.transaction(|| {
+        let term = terms::get(conn, course.term_id)?;
+        let before = CourseSnapshot::from(course);
+        expect_one(term_courses::delete(conn, course.id)?)?;
+        plan_events::record(conn, term.course_plan_id, user, EventKind::DeleteTermCourse,
+            Some(Snapshot::Course(before)), None)?;
+        plan_changes::record(conn, term.course_plan_id, Change::course_deleted(course.id, course.term_id))?;
+        Ok(())
+    })
+}
+
+/// Restores a deleted term along with the courses it had when it was deleted
+pub fn restore_term(conn: &DbConnection, user: &User, term: &terms::Term) -> QueryResult<()> {
+    conn.transaction(|| {
+        expect_one(terms::restore(conn, term.id)?)?;
+        let courses = term_courses::all(conn, term)?;
+        let after = TermSnapshot::new(term, &courses);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::RestoreTerm,
+            None, Some(Snapshot::Term(after)))?;
+        term_added(conn, term, &courses)?;
+        Ok(())
+    })
+}
+
+/// Restores a deleted course to its term
+pub fn restore_term_course(conn: &DbConnection, user: &User, course: &term_courses::TermCourse) -> QueryResult<()> {
+    conn.transaction(|| {
+        let term = terms::get(conn, course.term_id)?;
+        expect_one(term_courses::restore(conn, course.id)?)?;
+        let after = CourseSnapshot::from(course);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::RestoreTermCourse,
+            None, Some(Snapshot::Course(after)))?;
+        plan_changes::record(conn, term.course_plan_id, Change::course_added(course))?;
+        Ok(())
+    })
+}
+
+/// Changes the name of a term, returning the renamed term
+pub fn rename_term(conn: &DbConnection, user: &User, term: &terms::Term, name: String) -> QueryResult<terms::Term> {
+    conn.transaction(|| {
+        let courses = term_courses::all(conn, term)?;
+        let renamed = terms::rename(conn, term.id, name)?;
+        let before = TermSnapshot::new(term, &courses);
+        let after = TermSnapshot::new(&renamed, &courses);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::RenameTerm,
+            Some(Snapshot::Term(before)), Some(Snapshot::Term(after)))?;
+        plan_changes::record(conn, term.course_plan_id, Change::term_renamed(&renamed))?;
+        Ok(renamed)
+    })
+}
+
+/// Moves a term to the given index among the other terms of its course plan, returning the index
+/// it was actually moved to (an index past the last term moves it to the end)
+pub fn move_term(conn: &DbConnection, user: &User, term: &terms::Term, position: usize) -> QueryResult<i32> {
+    conn.transaction(|| {
+        let courses = term_courses::all(conn, term)?;
+        let old_position = terms::index(conn, term)?;
+        let new_position = terms::move_to(conn, term, position)?;
+        let before = TermSnapshot::at(term, &courses, old_position);
+        let after = TermSnapshot::at(term, &courses, new_position);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::MoveTerm,
+            Some(Snapshot::Term(before)), Some(Snapshot::Term(after)))?;
+        plan_changes::record(conn, term.course_plan_id, Change::term_moved(term, new_position))?;
+        Ok(new_position)
+    })
+}
+
+/// Changes the name of a course, returning the renamed course
+pub fn rename_term_course(conn: &DbConnection, user: &User, course: &term_courses::TermCourse, name: String) -> QueryResult<term_courses::TermCourse> {
+    conn.transaction(|| {
+        let term = terms::get(conn, course.term_id)?;
+        let renamed = term_courses::rename(conn, course.id, name)?;
+        let before = CourseSnapshot::from(course);
+        let after = CourseSnapshot::from(&renamed);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::RenameTermCourse,
+            Some(Snapshot::Course(before)), Some(Snapshot::Course(after)))?;
+        plan_changes::record(conn, term.course_plan_id, Change::course_renamed(&renamed))?;
+        Ok(renamed)
+    })
+}
+
+/// Moves a course to a different term of the same course plan, returning the moved course
+pub fn move_term_course(conn: &DbConnection, user: &User, course: &term_courses::TermCourse, term: &terms::Term) -> QueryResult<term_courses::TermCourse> {
+    conn.transaction(|| {
+        let moved = term_courses::move_to_term(conn, course.id, term.id)?;
+        let before = CourseSnapshot::from(course);
+        let after = CourseSnapshot::from(&moved);
+        plan_events::record(conn, term.course_plan_id, user, EventKind::MoveTermCourse,
+            Some(Snapshot::Course(before)), Some(Snapshot::Course(after)))?;
+        plan_changes::record(conn, term.course_plan_id, Change::course_moved(&moved))?;
+        Ok(moved)
+    })
+}
+
+/// Reverts the most recent change to the course plan that has not already been undone. A change
+/// is every event of the same revision, so a batch of edits or a switch to another template is
+/// undone all at once. Returns the events that were undone, most recent first, or nothing if there
+/// was nothing to undo.
+pub fn undo(conn: &DbConnection, course_plan: &CoursePlan) -> QueryResult<Vec<PlanEvent>> {
+    conn.transaction(|| {
+        let events = match plan_events::last_active(conn, course_plan)? {
+            Some(PlanEvent {revision: Some(revision), ..}) => plan_events::active_in_revision(conn, course_plan.id, revision)?,
+            Some(event) => vec![event],
+            None => Vec::new(),
+        };
+        let mut undone = Vec::new();
+        for event in events {
+            undone.push(revert(conn, &event)?);
+        }
+        Ok(undone)
+    })
+}
+
+/// Reverts every change made to the course plan after the given event so that the course plan is
+/// exactly as it was right after the revision of that event. Returns the events that were undone,
+/// most recent first.
+pub fn restore_revision(conn: &DbConnection, revision: &PlanEvent) -> QueryResult<Vec<PlanEvent>> {
+    conn.transaction(|| {
+        let mut undone = Vec::new();
+        // Events are returned most recent first, so each inverse operation is applied to exactly
+        // the state that the original event produced
+        for event in plan_events::active_since(conn, revision)? {
+            undone.push(revert(conn, &event)?);
+        }
+        Ok(undone)
+    })
+}
+
+/// Applies the inverse of the given event and marks it as undone
+fn revert(conn: &DbConnection, event: &PlanEvent) -> QueryResult<PlanEvent> {
+    // Deleted records are only marked as deleted, so the inverse of adding or removing a term or
+    // course is either a delete or a restore of the same record
+    let course_plan_id = event.course_plan_id;
+    match event.kind()? {
+        EventKind::CreateTerm | EventKind::RestoreTerm => {
+            let term: TermSnapshot = event.after_snapshot()?;
+            expect_one(terms::delete(conn, term.id)?)?;
+            plan_changes::record(conn, course_plan_id, Change::term_deleted(term.id))?;
+        },
+        EventKind::DeleteTerm => {
+            let term: TermSnapshot = event.before_snapshot()?;
+            expect_one(terms::restore(conn, term.id)?)?;
+            let term = terms::get(conn, term.id)?;
+            let courses = term_courses::all(conn, &term)?;
+            term_added(conn, &term, &courses)?;
+        },
+        EventKind::CreateTermCourse | EventKind::RestoreTermCourse => {
+            let course: CourseSnapshot = event.after_snapshot()?;
+            expect_one(term_courses::delete(conn, course.id)?)?;
+            plan_changes::record(conn, course_plan_id, Change::course_deleted(course.id, course.term_id))?;
+        },
+        EventKind::DeleteTermCourse => {
+            let course: CourseSnapshot = event.before_snapshot()?;
+            expect_one(term_courses::restore(conn, course.id)?)?;
+            let course = term_courses::get(conn, course.id)?;
+            plan_changes::record(conn, course_plan_id, Change::course_added(&course))?;
+        },
+        EventKind::RenameTerm => {
+            let term: TermSnapshot = event.before_snapshot()?;
+            let term = terms::rename(conn, term.id, term.name)?;
+            plan_changes::record(conn, course_plan_id, Change::term_renamed(&term))?;
+        },
+        EventKind::MoveTerm => {
+            let term: TermSnapshot = event.before_snapshot()?;
+            let old_position = term.position.ok_or(QueryError::NotFound)?;
+            let term = terms::get(conn, term.id)?;
+            let position = terms::move_to(conn, &term, old_position as usize)?;
+            plan_changes::record(conn, course_plan_id, Change::term_moved(&term, position))?;
+        },
+        EventKind::MoveTermCourse => {
+            let course: CourseSnapshot = event.before_snapshot()?;
+            let course = term_courses::move_to_term(conn, course.id, course.term_id)?;
+            plan_changes::record(conn, course_plan_id, Change::course_moved(&course))?;
+        },
+        EventKind::RenameTermCourse => {
+            let course: CourseSnapshot = event.before_snapshot()?;
+            let course = term_courses::rename(conn, course.id, course.name)?;
+            plan_changes::record(conn, course_plan_id, Change::course_renamed(&course))?;
+        },
+    }
+
+    plan_events::mark_undone(conn, event.id)
+}
+
+/// Records that a term (along with the given courses) was added to its course plan
+fn term_added(conn: &DbConnection, term: &terms::Term, courses: &[term_courses::TermCourse]) -> QueryResult<()> {
+    let position = terms::index(conn, term)?;
+    plan_changes::record(conn, term.course_plan_id, Change::term_added(term, position))?;
+    for course in courses {
+        plan_changes::record(conn, term.course_plan_id, Change::course_added(course))?;
+    }
+    Ok(())
+}
+
+/// Ensures that a delete or restore actually changed a record. If it did not, the history does not
+/// match the data and the change cannot be safely applied.
+fn expect_one(count: usize) -> QueryResult<()> {
+    match count {
+        1 => Ok(()),
+        _ => Err(QueryError::NotFound),
+    }
+}
@@ -0,0 +1,502 @@
+//! Measuring how expensive a GraphQL query is before it is executed.
+//!
+//! Every field is resolved separately, so a client could otherwise make the server do an
+//! unbounded amount of work with one request by nesting fields deeply or by selecting the same
+//! fields over and over (which fragments make very cheap to write). A query is measured by:
+//!
+//! * depth: the deepest that fields are nested (`{ me { id } }` has a depth of 2)
+//! * complexity: the number of fields selected, counting a fragment again everywhere it is used
+//!
+//! This only needs enough of the GraphQL grammar to find the fields of each selection set, so
+//! arguments, variables and directives are skipped over rather than understood. Anything else
+//! about the query is validated by juniper when it is executed.
+//!
+//! Both parsing and measuring recurse once for every level of nesting, so they give up as soon as
+//! a query is nested much deeper than the deepest query that would be executed anyway.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The cost of the most expensive operation in a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryCost {
+    pub depth: usize,
+    pub complexity: usize,
+}
+
+/// How much deeper than the maximum depth selection sets may be nested before a query is
+/// rejected without being measured. Inline fragments and fragment spreads add a level of nesting
+/// without adding to the depth.
+const NESTING_MARGIN: usize = 20;
+
+/// The most expensive query that will be executed
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    pub max_depth: usize,
+    pub max_complexity: usize,
+}
+
+impl QueryLimits {
+    /// Returns a message describing which limit was exceeded, if any
+    pub fn check(&self, cost: &QueryCost) -> Result<(), String> {
+        if cost.depth > self.max_depth {
+            return Err(format!("Query is nested {} levels deep but at most {} levels are allowed",
+                cost.depth, self.max_depth));
+        }
+        if cost.complexity > self.max_complexity {
+            return Err(format!("Query selects {} fields but at most {} fields are allowed",
+                cost.complexity, self.max_complexity));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryCostError {
+    /// The query is not valid GraphQL
+    Syntax(String),
+    /// A fragment was used that is not defined in the query
+    UnknownFragment(String),
+    /// A fragment uses itself (directly or through other fragments)
+    FragmentCycle(String),
+    /// The query is nested so deeply that it was not measured (the maximum depth is given)
+    TooDeep(usize),
+}
+
+impl fmt::Display for QueryCostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryCostError::Syntax(err) => write!(f, "Unable to parse query: {}", err),
+            QueryCostError::UnknownFragment(name) => write!(f, "Unknown fragment \"{}\"", name),
+            QueryCostError::FragmentCycle(name) => write!(f, "Fragment \"{}\" uses itself", name),
+            QueryCostError::TooDeep(max_depth) => write!(f, "Query is nested too deeply, at most {} levels are allowed", max_depth),
+        }
+    }
+}
+
+/// Measures the most expensive operation in the given query. Queries nested much deeper than
+/// max_depth are rejected before they are measured.
+pub fn analyze(query: &str, max_depth: usize) -> Result<QueryCost, QueryCostError> {
+    let max_nesting = max_depth.saturating_add(NESTING_MARGIN);
+    let tokens = tokenize(query)?;
+    let document = Parser {tokens: &tokens, position: 0, nesting: 0, max_nesting, max_depth}.document()?;
+
+    let mut costs = Costs {
+        fragments: &document.fragments,
+        known: HashMap::new(),
+        visiting: Vec::new(),
+        nesting: 0,
+        max_nesting,
+        max_depth,
+    };
+    let mut most = QueryCost {depth: 0, complexity: 0};
+    for operation in &document.operations {
+        let cost = costs.selection_set(operation)?;
+        most.depth = most.depth.max(cost.depth);
+        most.complexity = most.complexity.max(cost.complexity);
+    }
+    Ok(most)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A single character punctuator like `{` or `:`
+    Punctuator(char),
+    /// `...`
+    Spread,
+    Name(String),
+    /// A number or string. Their values never matter here.
+    Value,
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryCostError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // Commas are insignificant in GraphQL, just like whitespace
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => {},
+            '#' => while chars.peek().map_or(false, |&c| c != '\n' && c != '\r') {
+                chars.next();
+            },
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '=' | '@' | '$' | '!' | '|' | '&' => {
+                tokens.push(Token::Punctuator(c));
+            },
+            '.' => {
+                if chars.next() != Some('.') || chars.next() != Some('.') {
+                    return Err(QueryCostError::Syntax("expected \"...\"".to_string()));
+                }
+                tokens.push(Token::Spread);
+            },
+            '"' => {
+                string(&mut chars)?;
+                tokens.push(Token::Value);
+            },
+            c if c == '-' || c.is_ascii_digit() => {
+                while chars.peek().map_or(false, |&c| c.is_ascii_alphanumeric() || c == '.' || c == '+' || c == '-') {
+                    chars.next();
+                }
+                tokens.push(Token::Value);
+            },
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c != '_' && !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            },
+            c => return Err(QueryCostError::Syntax(format!("unexpected character '{}'", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Skips over a string whose opening quote has already been read
+fn string(chars: &mut Peekable<Chars>) -> Result<(), QueryCostError> {
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(()),
+            // The escaped character can never end the string
+            '\\' => { chars.next(); },
+            '\n' | '\r' => break,
+            _ => {},
+        }
+    }
+    Err(QueryCostError::Syntax("unterminated string".to_string()))
+}
+
+#[derive(Debug)]
+enum Selection {
+    Field(Vec<Selection>),
+    FragmentSpread(String),
+    InlineFragment(Vec<Selection>),
+}
+
+#[derive(Debug, Default)]
+struct Document {
+    operations: Vec<Vec<Selection>>,
+    fragments: HashMap<String, Vec<Selection>>,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    /// The number of selection sets that the parser is currently inside of
+    nesting: usize,
+    max_nesting: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<&'a Token, QueryCostError> {
+        let token = self.tokens.get(self.position)
+            .ok_or_else(|| QueryCostError::Syntax("unexpected end of query".to_string()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Moves past the next token if it is the given punctuator
+    fn skip(&mut self, punctuator: char) -> bool {
+        if self.peek() == Some(&Token::Punctuator(punctuator)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punctuator: char) -> Result<(), QueryCostError> {
+        if self.skip(punctuator) {
+            Ok(())
+        } else {
+            Err(QueryCostError::Syntax(format!("expected '{}'", punctuator)))
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, QueryCostError> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            _ => Err(QueryCostError::Syntax("expected a name".to_string())),
+        }
+    }
+
+    fn document(mut self) -> Result<Document, QueryCostError> {
+        let mut document = Document::default();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punctuator('{') => document.operations.push(self.selection_set()?),
+                Token::Name(keyword) if keyword == "fragment" => {
+                    self.position += 1;
+                    let name = self.name()?.to_string();
+                    self.type_condition()?;
+                    self.directives()?;
+                    document.fragments.insert(name, self.selection_set()?);
+                },
+                Token::Name(keyword) if keyword == "query" || keyword == "mutation" || keyword == "subscription" => {
+                    self.position += 1;
+                    if let Some(Token::Name(_)) = self.peek() {
+                        self.position += 1;
+                    }
+                    // The variable definitions are skipped over
+                    self.arguments()?;
+                    self.directives()?;
+                    document.operations.push(self.selection_set()?);
+                },
+                _ => return Err(QueryCostError::Syntax("expected an operation or fragment".to_string())),
+            }
+        }
+        Ok(document)
+    }
+
+    fn type_condition(&mut self) -> Result<(), QueryCostError> {
+        if self.name()? != "on" {
+            return Err(QueryCostError::Syntax("expected \"on\"".to_string()));
+        }
+        self.name()?;
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection>, QueryCostError> {
+        self.expect('{')?;
+        if self.nesting >= self.max_nesting {
+            return Err(QueryCostError::TooDeep(self.max_depth));
+        }
+        self.nesting += 1;
+        let mut selections = Vec::new();
+        while !self.skip('}') {
+            selections.push(self.selection()?);
+        }
+        self.nesting -= 1;
+        Ok(selections)
+    }
+
+    fn selection(&mut self) -> Result<Selection, QueryCostError> {
+        if let Token::Spread = self.next()? {
+            return match self.peek() {
+                Some(Token::Name(name)) if name != "on" => {
+                    self.position += 1;
+                    self.directives()?;
+                    Ok(Selection::FragmentSpread(name.clone()))
+                },
+                _ => {
+                    if let Some(Token::Name(_)) = self.peek() {
+                        self.type_condition()?;
+                    }
+                    self.directives()?;
+                    Ok(Selection::InlineFragment(self.selection_set()?))
+                },
+            };
+        }
+
+        // The token just read is either the name of the field or its alias
+        self.position -= 1;
+        self.name()?;
+        if self.skip(':') {
+            self.name()?;
+        }
+        self.arguments()?;
+        self.directives()?;
+        let selections = match self.peek() {
+            Some(Token::Punctuator('{')) => self.selection_set()?,
+            _ => Vec::new(),
+        };
+        Ok(Selection::Field(selections))
+    }
+
+    /// Skips over a list of arguments or variable definitions, if there is one
+    fn arguments(&mut self) -> Result<(), QueryCostError> {
+        if !self.skip('(') {
+            return Ok(());
+        }
+        let mut open = 1;
+        while open > 0 {
+            match self.next()? {
+                Token::Punctuator('(') => open += 1,
+                Token::Punctuator(')') => open -= 1,
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn directives(&mut self) -> Result<(), QueryCostError> {
+        while self.skip('@') {
+            self.name()?;
+            self.arguments()?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the cost of selection sets, remembering the cost of each fragment so that a fragment
+/// used many times is only measured once
+struct Costs<'a> {
+    fragments: &'a HashMap<String, Vec<Selection>>,
+    known: HashMap<&'a str, QueryCost>,
+    /// The fragments currently being measured, used to find cycles
+    visiting: Vec<&'a str>,
+    /// The number of selection sets currently being measured. This is limited as well, since a
+    /// chain of fragments can nest far deeper than any one selection set in the query.
+    nesting: usize,
+    max_nesting: usize,
+    max_depth: usize,
+}
+
+impl<'a> Costs<'a> {
+    fn selection_set(&mut self, selections: &'a [Selection]) -> Result<QueryCost, QueryCostError> {
+        self.nested(|costs| {
+            let mut total = QueryCost {depth: 0, complexity: 0};
+            for selection in selections {
+                let cost = match selection {
+                    Selection::Field(selections) => {
+                        let cost = costs.selection_set(selections)?;
+                        QueryCost {depth: cost.depth + 1, complexity: cost.complexity.saturating_add(1)}
+                    },
+                    Selection::InlineFragment(selections) => costs.selection_set(selections)?,
+                    Selection::FragmentSpread(name) => costs.fragment(name)?,
+                };
+                total.depth = total.depth.max(cost.depth);
+                total.complexity = total.complexity.saturating_add(cost.complexity);
+            }
+            Ok(total)
+        })
+    }
+
+    /// Measures one more level of nesting, unless there are already too many
+    fn nested<F>(&mut self, measure: F) -> Result<QueryCost, QueryCostError>
+        where F: FnOnce(&mut Self) -> Result<QueryCost, QueryCostError>,
+    {
+        if self.nesting >= self.max_nesting {
+            return Err(QueryCostError::TooDeep(self.max_depth));
+        }
+        self.nesting += 1;
+        let cost = measure(self);
+        self.nesting -= 1;
+        cost
+    }
+
+    fn fragment(&mut self, name: &'a str) -> Result<QueryCost, QueryCostError> {
+        if let Some(&cost) = self.known.get(name) {
+            return Ok(cost);
+        }
+        if self.visiting.contains(&name) {
+            return Err(QueryCostError::FragmentCycle(name.to_string()));
+        }
+        let selections = self.fragments.get(name)
+            .ok_or_else(|| QueryCostError::UnknownFragment(name.to_string()))?;
+
+        self.visiting.push(name);
+        let cost = self.selection_set(selections)?;
+        self.visiting.pop();
+
+        self.known.insert(name, cost);
+        Ok(cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(query: &str) -> QueryCost {
+        analyze(query, 1000).expect("unable to analyze query")
+    }
+
+    #[test]
+    fn depth_and_complexity() {
+        assert_eq!(cost("{ me { id name } }"), QueryCost {depth: 2, complexity: 3});
+        assert_eq!(cost(r#"
+            # Comments, arguments, aliases and directives do not count
+            query Plan($id: Int!) {
+                plan: coursePlan(id: $id, filter: {name: "a } b", ids: [1, 2]}) @include(if: true) {
+                    terms { name courses { name } }
+                }
+            }
+        "#), QueryCost {depth: 4, complexity: 5});
+    }
+
+    #[test]
+    fn most_expensive_operation() {
+        assert_eq!(cost("query A { me { id } } query B { me { id name email } }"),
+            QueryCost {depth: 2, complexity: 4});
+    }
+
+    #[test]
+    fn fragments_count_every_time_they_are_used() {
+        let query = "
+            { a: me { ...User } b: me { ...User } c: me { ... on User { id } } }
+            fragment User on User { id name ...Ids }
+            fragment Ids on User { id }
+        ";
+        // Each me field using User selects itself and the three fields of User
+        assert_eq!(cost(query), QueryCost {depth: 2, complexity: 4 + 4 + 2});
+    }
+
+    #[test]
+    fn repeated_fragments_do_not_take_exponential_time() {
+        let mut query = "{ ...F0 }".to_string();
+        for i in 0..64 {
+            query.push_str(&format!(" fragment F{} on Query {{ a: me {{ ...F{} }} b: me {{ ...F{} }} }}", i, i + 1, i + 1));
+        }
+        query.push_str(" fragment F64 on Query { id }");
+        let cost = cost(&query);
+        assert_eq!(cost.depth, 65);
+        assert_eq!(cost.complexity, usize::max_value());
+    }
+
+    #[test]
+    fn invalid_queries() {
+        match analyze("{ ...A } fragment A on Query { ...B } fragment B on Query { ...A }", 100) {
+            Err(QueryCostError::FragmentCycle(_)) => {},
+            result => panic!("expected a fragment cycle, got {:?}", result),
+        }
+        match analyze("{ ...Missing }", 100) {
+            Err(QueryCostError::UnknownFragment(ref name)) if name == "Missing" => {},
+            result => panic!("expected an unknown fragment, got {:?}", result),
+        }
+        match analyze("{ me { id }", 100) {
+            Err(QueryCostError::Syntax(_)) => {},
+            result => panic!("expected a syntax error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn deeply_nested_queries_are_rejected_without_recursing() {
+        let nested_fields = "{a".repeat(100_000);
+        match analyze(&nested_fields, 15) {
+            Err(QueryCostError::TooDeep(15)) => {},
+            result => panic!("expected the query to be too deep, got {:?}", result),
+        }
+
+        let mut fragment_chain = "{ ...F0 }".to_string();
+        for i in 0..10_000 {
+            fragment_chain.push_str(&format!(" fragment F{} on Query {{ ...F{} }}", i, i + 1));
+        }
+        fragment_chain.push_str(" fragment F10000 on Query { id }");
+        match analyze(&fragment_chain, 15) {
+            Err(QueryCostError::TooDeep(15)) => {},
+            result => panic!("expected the query to be too deep, got {:?}", result),
+        }
+
+        // A little nesting beyond the maximum depth is still measured so it can be reported
+        assert_eq!(analyze("{ a { b { c } } }", 2).unwrap().depth, 3);
+    }
+
+    #[test]
+    fn limits() {
+        let limits = QueryLimits {max_depth: 2, max_complexity: 3};
+        assert!(limits.check(&QueryCost {depth: 2, complexity: 3}).is_ok());
+        assert!(limits.check(&QueryCost {depth: 3, complexity: 3}).is_err());
+        assert!(limits.check(&QueryCost {depth: 2, complexity: 4}).is_err());
+    }
+}
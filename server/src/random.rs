@@ -0,0 +1,14 @@
+//! Generation of unguessable tokens for use in URLs and credentials
+
+use ring::error::Unspecified;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// The number of random bytes in each token (256 bits)
+const TOKEN_BYTES: usize = 32;
+
+/// Generates a random hex-encoded token using a cryptographically secure random number generator
+pub fn token() -> Result<String, Unspecified> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    SystemRandom::new().fill(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
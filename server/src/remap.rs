@@ -0,0 +1,223 @@
+//! Switching a course plan to a different program or stream template without losing progress.
+//!
+//! Terms that are completed or in progress are kept as they are, along with any terms without a
+//! date that come before the first planned term. Every later term is replaced by the terms of the
+//! target template that the kept terms do not already cover (matched by level, e.g. "2A"), dated
+//! one after another from the term after the last kept term.
+//!
+//! Courses that were planned in the replaced terms are carried over wherever the target template
+//! has the same course (matched by course code) and are reported as dropped otherwise. Courses of
+//! the template that were already taken in a kept term are not planned again.
+
+use diesel::Connection;
+use diesel::result::QueryResult;
+use chrono::NaiveDate;
+
+use api::db::DbConnection;
+use models::{users::User, course_plans::{self, CoursePlan}, terms, term_courses};
+use template::{self, CoursePlanTemplate};
+use term_name::{TermName, TermStatus};
+use diff::course_key;
+use plan;
+
+/// A description of what happened to each term and course. Courses are described as
+/// "<course> (<term>)".
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RemapSummary {
+    /// The terms that were kept because they are completed or in progress
+    pub kept_terms: Vec<String>,
+    /// The planned terms that were replaced
+    pub removed_terms: Vec<String>,
+    /// The terms that were added from the target template
+    pub added_terms: Vec<String>,
+    /// The planned courses that the target template also has, in their new terms
+    pub carried_over_courses: Vec<String>,
+    /// The planned courses that the target template does not have, in their old terms
+    pub dropped_courses: Vec<String>,
+    /// The courses of the target template that were not planned because they were already taken
+    pub skipped_courses: Vec<String>,
+}
+
+/// What remapping changes, decided from the names of the terms and courses alone
+#[derive(Debug)]
+struct Remap {
+    /// The number of terms at the start of the course plan that are kept
+    keep: usize,
+    /// The terms that replace every term after the kept ones
+    terms: Vec<template::Term>,
+    summary: RemapSummary,
+}
+
+/// A course planned in a term that is replaced
+struct Planned<'a> {
+    key: String,
+    course: &'a template::TermCourse,
+    term: &'a template::Term,
+    /// Whether the course was carried over to a new term
+    used: bool,
+}
+
+/// The part of a term name that terms of different templates are matched by
+fn level(name: &str) -> String {
+    TermName::parse(name).label.split_whitespace().collect::<Vec<_>>().join(" ").to_uppercase()
+}
+
+fn describe(course: &str, term: &str) -> String {
+    format!("{} ({})", course, term)
+}
+
+/// Decides how the existing terms are replaced with the remaining terms of the target template
+fn remap_terms(existing: &[template::Term], target: &CoursePlanTemplate, today: NaiveDate) -> Remap {
+    let mut summary = RemapSummary::default();
+    let keep = existing.iter()
+        .position(|term| TermName::parse(&term.name).status(today) == TermStatus::Planned)
+        .unwrap_or(existing.len());
+    let (kept, replaced) = existing.split_at(keep);
+
+    // The remaining terms follow the last kept term, or take the place of the first replaced term
+    // if no kept term has a date
+    let kept_dates = kept.iter().filter_map(|term| TermName::parse(&term.name).date);
+    let mut date = match kept_dates.last() {
+        Some(date) => Some(date.next()),
+        None => replaced.iter().filter_map(|term| TermName::parse(&term.name).date).next(),
+    };
+
+    let kept_levels: Vec<_> = kept.iter().map(|term| level(&term.name)).collect();
+    let taken: Vec<_> = kept.iter()
+        .flat_map(|term| term.courses.iter().map(|course| course_key(&course.name)))
+        .collect();
+    let mut planned: Vec<_> = replaced.iter()
+        .flat_map(|term| term.courses.iter().map(move |course| Planned {key: course_key(&course.name), course, term, used: false}))
+        .collect();
+
+    let mut terms = Vec::new();
+    for term in target.terms.iter().filter(|term| !kept_levels.contains(&level(&term.name))) {
+        let name = match date {
+            Some(term_date) => {
+                date = Some(term_date.next());
+                TermName {label: TermName::parse(&term.name).label, date: Some(term_date)}.to_string()
+            },
+            None => term.name.clone(),
+        };
+
+        let mut courses = Vec::new();
+        for course in &term.courses {
+            let key = course_key(&course.name);
+            if taken.contains(&key) {
+                summary.skipped_courses.push(describe(&course.name, &name));
+                continue;
+            }
+            let course_name = match planned.iter_mut().find(|planned| !planned.used && planned.key == key) {
+                Some(planned) => {
+                    planned.used = true;
+                    summary.carried_over_courses.push(describe(&planned.course.name, &name));
+                    planned.course.name.clone()
+                },
+                None => course.name.clone(),
+            };
+            courses.push(template::TermCourse {name: course_name});
+        }
+
+        summary.added_terms.push(name.clone());
+        terms.push(template::Term {name, courses});
+    }
+
+    summary.kept_terms = kept.iter().map(|term| term.name.clone()).collect();
+    summary.removed_terms = replaced.iter().map(|term| term.name.clone()).collect();
+    summary.dropped_courses = planned.into_iter()
+        .filter(|planned| !planned.used)
+        .map(|planned| describe(&planned.course.name, &planned.term.name))
+        .collect();
+
+    Remap {keep, terms, summary}
+}
+
+/// Switches a course plan to the given template, keeping the terms that are completed or in
+/// progress. All changes happen in a single transaction. Every term and course is changed through
+/// the `plan` module so that, as part of the same revision, they are undone together. The template
+/// that the course plan is based on is not changed back by undo.
+pub fn remap(
+    conn: &DbConnection,
+    user: &User,
+    course_plan: &CoursePlan,
+    template_id: &str,
+    target: &CoursePlanTemplate,
+    today: NaiveDate,
+) -> QueryResult<RemapSummary> {
+    conn.transaction(|| {
+        let existing_terms = terms::all(conn, course_plan)?;
+        let existing_courses = term_courses::all_for_terms(conn, &existing_terms)?;
+        let existing: Vec<_> = existing_terms.iter().zip(&existing_courses).map(|(term, courses)| template::Term {
+            name: term.name.clone(),
+            courses: courses.iter().map(|course| template::TermCourse {name: course.name.clone()}).collect(),
+        }).collect();
+        let Remap {keep, terms: new_terms, summary} = remap_terms(&existing, target, today);
+
+        for term in &existing_terms[keep..] {
+            plan::delete_term(conn, user, term)?;
+        }
+        for term in new_terms {
+            let created = plan::create_term(conn, user, course_plan, term.name)?;
+            for course in term.courses {
+                plan::create_term_course(conn, user, &created, course.name)?;
+            }
+        }
+        course_plans::set_template(conn, course_plan.id, template_id)?;
+
+        Ok(summary)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use template::names;
+
+    #[test]
+    fn keeps_progress_and_replaces_planned_terms() {
+        let existing = CoursePlanTemplate::from_names(&[
+            ("1A F18", &["CS 137", "MATH 135"]),
+            ("1B W19", &["CS 138"]),
+            ("2A F19", &["CS 241", "STAT 206"]),
+            ("2B S20", &["SE 212"]),
+        ]);
+        let target = CoursePlanTemplate::from_names(&[
+            ("1A F18", &["CS 137"]),
+            ("1B W19", &["CS 138"]),
+            ("WT1 S19", &[]),
+            ("2A F19", &["CS 241", "MATH 239", "CS 137"]),
+        ]);
+        let today = NaiveDate::from_ymd(2019, 3, 1);
+        let remap = remap_terms(&existing.terms, &target, today);
+
+        assert_eq!(remap.keep, 2);
+        let terms: Vec<_> = remap.terms.iter()
+            .map(|term| (term.name.as_str(), term.courses.iter().map(|course| course.name.as_str()).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(terms, vec![
+            ("WT1 S19", vec![]),
+            ("2A F19", vec!["CS 241", "MATH 239"]),
+        ]);
+        assert_eq!(remap.summary, RemapSummary {
+            kept_terms: names(&["1A F18", "1B W19"]),
+            removed_terms: names(&["2A F19", "2B S20"]),
+            added_terms: names(&["WT1 S19", "2A F19"]),
+            carried_over_courses: names(&["CS 241 (2A F19)"]),
+            dropped_courses: names(&["STAT 206 (2A F19)", "SE 212 (2B S20)"]),
+            skipped_courses: names(&["CS 137 (2A F19)"]),
+        });
+    }
+
+    #[test]
+    fn redates_remaining_terms() {
+        let existing = CoursePlanTemplate::from_names(&[("1A F18", &[]), ("1B W19", &[])]);
+        let target = CoursePlanTemplate::from_names(&[("1A F17", &[]), ("1B W18", &[]), ("2A F18", &[]), ("2B W19", &[])]);
+        let remap = remap_terms(&existing.terms, &target, NaiveDate::from_ymd(2019, 2, 1));
+        assert_eq!(remap.summary.added_terms, names(&["2A S19", "2B F19"]));
+
+        // Nothing is kept before the plan starts, so the remaining terms start where it started
+        let remap = remap_terms(&existing.terms, &target, NaiveDate::from_ymd(2018, 6, 1));
+        assert_eq!(remap.keep, 0);
+        assert_eq!(remap.summary.added_terms, names(&["1A F18", "1B W19", "2A S19", "2B F19"]));
+    }
+}
@@ -1,45 +1,154 @@
 table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
     course_plans (id) {
         id -> Int4,
         user_id -> Int4,
         created_at -> Timestamptz,
+        template_id -> Nullable<Varchar>,
+        revision -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
+    identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Varchar,
+        subject -> Varchar,
+        email -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
+    plan_changes (id) {
+        id -> Int4,
+        course_plan_id -> Int4,
+        revision -> Int4,
+        kind -> Varchar,
+        term_id -> Int4,
+        term_course_id -> Nullable<Int4>,
+        name -> Nullable<Varchar>,
+        position -> Nullable<Int4>,
+        created_at -> Timestamptz,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
+    plan_events (id) {
+        id -> Int4,
+        course_plan_id -> Int4,
+        user_id -> Int4,
+        kind -> Varchar,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+        undone_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        revision -> Nullable<Int4>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        jti -> Varchar,
+        refresh_token_hash -> Varchar,
+        previous_refresh_token_hash -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        last_used_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
+    share_links (id) {
+        id -> Int4,
+        course_plan_id -> Int4,
+        token -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
     term_courses (id) {
         id -> Int4,
         term_id -> Int4,
         name -> Varchar,
         created_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
     terms (id) {
         id -> Int4,
         course_plan_id -> Int4,
         name -> Varchar,
         created_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+        position -> Int4,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use sql_types::Timestamptz;
+
     users (id) {
         id -> Int4,
         name -> Varchar,
         email -> Varchar,
         created_at -> Timestamptz,
-        google_id -> Nullable<Varchar>,
+        is_admin -> Bool,
+        disabled_at -> Nullable<Timestamptz>,
     }
 }
 
 joinable!(course_plans -> users (user_id));
+joinable!(identities -> users (user_id));
+joinable!(plan_changes -> course_plans (course_plan_id));
+joinable!(plan_events -> course_plans (course_plan_id));
+joinable!(plan_events -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(share_links -> course_plans (course_plan_id));
 joinable!(term_courses -> terms (term_id));
 joinable!(terms -> course_plans (course_plan_id));
 
 allow_tables_to_appear_in_same_query!(
     course_plans,
+    identities,
+    plan_changes,
+    plan_events,
+    sessions,
+    share_links,
     term_courses,
     terms,
     users,
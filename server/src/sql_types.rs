@@ -0,0 +1,85 @@
+//! SQL types used by the schema that not every database backend provides.
+//!
+//! Diesel only supports timestamps with time zones (`DateTime<Utc>` values) with PostgreSQL. With
+//! SQLite, they are stored as text in UTC using the same format as SQLite's own date and time
+//! functions (e.g. "2018-10-08 17:05:24.123"), so they sort correctly and can be compared with
+//! the values produced by `strftime` in the migrations.
+
+// https://github.com/diesel-rs/diesel/issues/1785
+#![allow(proc_macro_derive_resolution_fallback)]
+
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use diesel::backend::Backend;
+use diesel::serialize::{self, Output, ToSql};
+
+#[cfg(feature = "postgres")]
+pub use diesel::sql_types::Timestamptz;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::Timestamptz;
+
+/// A date and time used as a value in a query, e.g. `expires_at.gt(UtcDateTime(Utc::now()))`.
+///
+/// This is only needed for columns that cannot be NULL. Values for nullable columns can be given
+/// as `Option<DateTime<Utc>>` directly, e.g. `deleted_at.lt(Some(deleted_before))`. Diesel does not
+/// allow `DateTime<Utc>` itself to be used as a value outside of PostgreSQL.
+#[derive(Debug, Clone, Copy, AsExpression)]
+#[sql_type = "Timestamptz"]
+pub struct UtcDateTime(pub DateTime<Utc>);
+
+impl<DB: Backend> ToSql<Timestamptz, DB> for UtcDateTime
+    where DateTime<Utc>: ToSql<Timestamptz, DB>,
+{
+    fn to_sql<W: Write>(&self, out: &mut Output<W, DB>) -> serialize::Result {
+        self.0.to_sql(out)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::io::Write;
+
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use diesel::backend::Backend;
+    use diesel::deserialize::{self, FromSql};
+    use diesel::serialize::{self, Output, ToSql};
+    use diesel::sql_types::{ops, NotNull, Timestamp};
+    use diesel::sqlite::Sqlite;
+
+    /// A date and time in UTC, stored as text
+    #[derive(Debug, Clone, Copy, Default, SqlType, QueryId)]
+    #[sqlite_type = "Text"]
+    pub struct Timestamptz;
+
+    /// SQLite has no interval type, so nothing can be added to or subtracted from a timestamp in
+    /// a query. The table! macro still expects every Timestamptz column to support it.
+    #[derive(Debug, Clone, Copy)]
+    pub enum NoInterval {}
+
+    impl NotNull for NoInterval {}
+
+    impl ops::Add for Timestamptz {
+        type Rhs = NoInterval;
+        type Output = Timestamptz;
+    }
+
+    impl ops::Sub for Timestamptz {
+        type Rhs = NoInterval;
+        type Output = Timestamptz;
+    }
+
+    impl ToSql<Timestamptz, Sqlite> for DateTime<Utc> {
+        fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+            ToSql::<Timestamp, Sqlite>::to_sql(&self.naive_utc(), out)
+        }
+    }
+
+    impl FromSql<Timestamptz, Sqlite> for DateTime<Utc> {
+        fn from_sql(value: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+            let naive = <NaiveDateTime as FromSql<Timestamp, Sqlite>>::from_sql(value)?;
+            Ok(DateTime::from_utc(naive, Utc))
+        }
+    }
+}
@@ -1,33 +1,78 @@
 use std::fs::{self, File};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::fmt;
+use std::io;
 
 use serde_json;
 use serde_json::error::Error as SerdeError;
 
+use term_name::{TermName, TermDate};
+
 #[derive(Debug)]
-pub struct UnknownTemplate<'a>(&'a str);
+pub enum TemplateError {
+    /// There is no template with this identifier
+    Unknown(String),
+    /// The template directory or file could not be read
+    Io(io::Error),
+    /// The template file is not a valid template
+    Invalid(SerdeError),
+}
 
-impl<'a> fmt::Display for UnknownTemplate<'a> {
+impl fmt::Display for TemplateError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Unknown course plan template: {}", self.0)
+        match self {
+            TemplateError::Unknown(template_id) => write!(f, "Unknown course plan template: {}", template_id),
+            TemplateError::Io(err) => write!(f, "Unable to read template: {}", err),
+            TemplateError::Invalid(err) => write!(f, "Unable to deserialize template: {}", err),
+        }
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Term {
     pub name: String,
     pub courses: Vec<TermCourse>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TermCourse {
     pub name: String,
 }
 
+/// The directory that program templates are loaded from
+#[derive(Debug, Clone)]
+pub struct Templates {
+    dir: PathBuf,
+}
+
+impl Templates {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Templates {dir: dir.into()}
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The identifiers of all of the templates in the directory (the names of the JSON files
+    /// without their extension)
+    pub fn ids(&self) -> io::Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            // Filenames that are not valid unicode can never match a template identifier
+            if let Ok(filename) = file?.file_name().into_string() {
+                if filename.ends_with(".json") {
+                    ids.push(filename[..filename.len() - ".json".len()].to_string());
+                }
+            }
+        }
+        Ok(ids)
+    }
+}
+
 /// Represents a template course plan for a given program
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoursePlanTemplate {
     pub terms: Vec<Term>,
 }
@@ -35,32 +80,61 @@ pub struct CoursePlanTemplate {
 impl CoursePlanTemplate {
     /// Parses a course plan template from the template associated with the given template
     /// identifier.
-    pub fn from_template(template_id: &str) -> Result<Self, UnknownTemplate> {
-        let template_dir = Path::new("templates");
+    pub fn from_template(templates: &Templates, template_id: &str) -> Result<Self, TemplateError> {
+        let template_dir = templates.dir();
         // It is very important to validate that this is a recognized course plan template because
         // the template identifier provided may be from an untrusted source. It would be very bad
         // if someone found a way to open an arbitrary file on the file system.
-        let template_file = String::from(template_id) + ".json";
-        let mut found = false;
-        for file in fs::read_dir(template_dir).expect("Unable to read template directory") {
-            let filename = file.expect("Unable to read template filename").file_name()
-                .into_string().expect("Invalid unicode in template filename");
-            if filename == template_file {
-                found = true;
-            }
+        if !templates.ids().map_err(TemplateError::Io)?.iter().any(|id| id == template_id) {
+            return Err(TemplateError::Unknown(template_id.to_string()));
         }
 
-        if !found {
-            return Err(UnknownTemplate(template_id));
+        let mut path = template_dir.to_path_buf();
+        path.push(String::from(template_id) + ".json");
+        let file = File::open(path).map_err(TemplateError::Io)?;
+        serde_json::from_reader(file).map_err(TemplateError::Invalid)
+    }
+}
+
+impl CoursePlanTemplate {
+    /// Moves the template to a different intake by shifting the date of every term so that the
+    /// first dated term is the given term. The sequence of seasons between terms stays the same and
+    /// terms without a date (e.g. "Co-op 1") are left as they are.
+    pub fn starting_at(mut self, start: TermDate) -> Self {
+        let first = self.terms.iter().filter_map(|term| TermName::parse(&term.name).date).next();
+        if let Some(first) = first {
+            let shift = first.terms_until(start);
+            for term in &mut self.terms {
+                let name = match TermName::parse(&term.name) {
+                    TermName {label, date: Some(date)} => TermName {label, date: Some(date.offset(shift))}.to_string(),
+                    TermName {date: None, ..} => continue,
+                };
+                term.name = name;
+            }
         }
+        self
+    }
+}
 
-        let mut path = template_dir.to_path_buf();
-        path.push(template_file);
-        let file = File::open(path).expect("Unable to open template");
-        Ok(serde_json::from_reader(file).expect("Unable to deserialize template"))
+#[cfg(test)]
+impl CoursePlanTemplate {
+    /// Builds a template for tests from the names of its terms and their courses
+    pub fn from_names(terms: &[(&str, &[&str])]) -> Self {
+        CoursePlanTemplate {
+            terms: terms.iter().map(|&(name, courses)| Term {
+                name: name.to_string(),
+                courses: courses.iter().map(|&name| TermCourse {name: name.to_string()}).collect(),
+            }).collect(),
+        }
     }
 }
 
+/// Converts a list of names for comparing with the names in a template in tests
+#[cfg(test)]
+pub fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
 impl FromStr for CoursePlanTemplate {
     type Err = SerdeError;
 
@@ -68,3 +142,21 @@ impl FromStr for CoursePlanTemplate {
         serde_json::from_str(text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starting_at_a_different_term() {
+        let template = CoursePlanTemplate::from_names(&[
+            ("1A F18", &[]), ("1B W19", &[]), ("Co-op 1", &[]), ("2A F19", &[]), ("2B S19", &[]),
+        ]);
+        let term_names = |template: CoursePlanTemplate| template.terms.into_iter().map(|term| term.name).collect::<Vec<_>>();
+
+        let start = TermDate::parse("W26").unwrap();
+        assert_eq!(term_names(template.clone().starting_at(start)), names(&["1A W26", "1B S26", "Co-op 1", "2A W27", "2B F26"]));
+        let start = TermDate::parse("F18").unwrap();
+        assert_eq!(term_names(template.clone().starting_at(start)), term_names(template));
+    }
+}
@@ -0,0 +1,201 @@
+//! Utilities for understanding the names of terms in a course plan.
+//!
+//! Term names are free-form, but the names generated from templates follow the format
+//! `<label> <season><year>` where the season is one of F (Fall), W (Winter) or S (Spring) and the
+//! year has two digits (e.g. "1A F18"). Terms without a date (e.g. "Co-op 1") are also supported.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::{NaiveDate, Datelike};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Season {
+    Winter,
+    Spring,
+    Fall,
+}
+
+impl Season {
+    /// The single letter used for this season in term names
+    pub fn short(self) -> char {
+        match self {
+            Season::Winter => 'W',
+            Season::Spring => 'S',
+            Season::Fall => 'F',
+        }
+    }
+
+    fn from_short(c: char) -> Option<Season> {
+        match c {
+            'W' => Some(Season::Winter),
+            'S' => Some(Season::Spring),
+            'F' => Some(Season::Fall),
+            _ => None,
+        }
+    }
+
+    /// The order of this season within a calendar year
+    fn index(self) -> u32 {
+        match self {
+            Season::Winter => 0,
+            Season::Spring => 1,
+            Season::Fall => 2,
+        }
+    }
+
+    fn from_index(index: u32) -> Season {
+        match index {
+            0 => Season::Winter,
+            1 => Season::Spring,
+            _ => Season::Fall,
+        }
+    }
+}
+
+/// The season and calendar year of a term (e.g. F18)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermDate {
+    pub season: Season,
+    pub year: i32,
+}
+
+impl TermDate {
+    /// Parses a date in the format used by term names (e.g. F18)
+    pub fn parse(text: &str) -> Option<TermDate> {
+        let mut chars = text.chars();
+        let season = chars.next().and_then(Season::from_short)?;
+        let year = chars.as_str();
+        if year.len() != 2 || !year.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(TermDate {
+            season,
+            year: 2000 + year.parse::<i32>().ok()?,
+        })
+    }
+
+    /// The term that the given date falls within
+    pub fn containing(date: NaiveDate) -> TermDate {
+        let season = match date.month() {
+            1...4 => Season::Winter,
+            5...8 => Season::Spring,
+            _ => Season::Fall,
+        };
+
+        TermDate {season, year: date.year()}
+    }
+
+    /// The term that immediately follows this one
+    pub fn next(self) -> TermDate {
+        match self.season {
+            Season::Winter => TermDate {season: Season::Spring, year: self.year},
+            Season::Spring => TermDate {season: Season::Fall, year: self.year},
+            Season::Fall => TermDate {season: Season::Winter, year: self.year + 1},
+        }
+    }
+}
+
+impl TermDate {
+    /// The number of terms since year 0, so that terms can be counted across years
+    fn ordinal(self) -> i32 {
+        self.year * 3 + self.season.index() as i32
+    }
+
+    /// The number of terms from this term until the given term (negative if it is earlier)
+    pub fn terms_until(self, other: TermDate) -> i32 {
+        other.ordinal() - self.ordinal()
+    }
+
+    /// The term that is the given number of terms after this one (or before it if negative)
+    pub fn offset(self, terms: i32) -> TermDate {
+        // Term names only have years from 2000 on, so the ordinal never becomes negative
+        let ordinal = self.ordinal() + terms;
+        TermDate {season: Season::from_index((ordinal % 3) as u32), year: ordinal / 3}
+    }
+}
+
+impl Ord for TermDate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.year, self.season.index()).cmp(&(other.year, other.season.index()))
+    }
+}
+
+impl PartialOrd for TermDate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for TermDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{:02}", self.season.short(), self.year % 100)
+    }
+}
+
+/// The status of a term relative to the current date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermStatus {
+    Completed,
+    InProgress,
+    Planned,
+    /// The term name does not contain a date
+    Unknown,
+}
+
+impl TermStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TermStatus::Completed => "completed",
+            TermStatus::InProgress => "in progress",
+            TermStatus::Planned => "planned",
+            TermStatus::Unknown => "",
+        }
+    }
+}
+
+/// A term name split into its label (e.g. "1A") and its date (e.g. F18), if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermName<'a> {
+    pub label: &'a str,
+    pub date: Option<TermDate>,
+}
+
+impl<'a> TermName<'a> {
+    pub fn parse(name: &'a str) -> TermName<'a> {
+        let name = name.trim();
+        // The date is always the last word of the name
+        if let Some(index) = name.rfind(' ') {
+            if let Some(date) = TermDate::parse(&name[index+1..]) {
+                return TermName {label: name[..index].trim_right(), date: Some(date)};
+            }
+        }
+
+        match TermDate::parse(name) {
+            Some(date) => TermName {label: "", date: Some(date)},
+            None => TermName {label: name, date: None},
+        }
+    }
+
+    /// Determines whether this term is over, currently happening or still to come
+    pub fn status(&self, today: NaiveDate) -> TermStatus {
+        let current = TermDate::containing(today);
+        match self.date {
+            Some(date) if date < current => TermStatus::Completed,
+            Some(date) if date == current => TermStatus::InProgress,
+            Some(_) => TermStatus::Planned,
+            None => TermStatus::Unknown,
+        }
+    }
+}
+
+impl<'a> fmt::Display for TermName<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.label, self.date) {
+            ("", Some(date)) => write!(f, "{}", date),
+            (label, Some(date)) => write!(f, "{} {}", label, date),
+            (label, None) => write!(f, "{}", label),
+        }
+    }
+}
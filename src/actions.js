@@ -51,9 +51,49 @@ const receiveLogout = () => {
   }
 }
 
+const storeTokens = ({ token, refreshToken, expiresAt }) => {
+  localStorage.setItem('access_token', token)
+  localStorage.setItem('refresh_token', refreshToken)
+  localStorage.setItem('access_token_expires_at', expiresAt)
+}
+
+const clearTokens = () => {
+  localStorage.removeItem('id_token')
+  localStorage.removeItem('access_token')
+  localStorage.removeItem('refresh_token')
+  localStorage.removeItem('access_token_expires_at')
+}
+
+// Access tokens are short-lived, so they are exchanged for a new one shortly before they expire.
+// Each refresh token can only be used once, so concurrent callers share the same request.
+let pendingRefresh = null
+
+export function refreshAccessToken () {
+  const refreshToken = localStorage.getItem('refresh_token')
+  if (!refreshToken) {
+    return Promise.resolve(null)
+  }
+
+  if (!pendingRefresh) {
+    pendingRefresh = axios.post(`${BASE_URL}/refresh`, JSON.stringify({ refreshToken }), { headers: {
+      'Content-Type': 'application/json'
+    } }).then(({ data }) => {
+      storeTokens(data)
+      return data.token
+    }, () => {
+      clearTokens()
+      return null
+    }).then((token) => {
+      pendingRefresh = null
+      return token
+    })
+  }
+  return pendingRefresh
+}
+
 export function loginUser (response) {
-  const { profileObj } = response
-  const payload = { email: profileObj.email, googleId: profileObj.googleId, name: profileObj.name }
+  // The server verifies the ID token and takes the user's identity from it
+  const payload = { idToken: response.tokenId }
 
   return async dispatch => {
     dispatch(requestLogin())
@@ -69,7 +109,7 @@ export function loginUser (response) {
     console.log(apiResp)
 
     const { token } = apiResp.data
-    localStorage.setItem('access_token', token)
+    storeTokens(apiResp.data)
     dispatch(receiveLogin({ access_token: token }))
   }
 }
@@ -95,8 +135,7 @@ export function logoutUser () {
     } catch (ex) {
       // Ignore
     }
-    localStorage.removeItem('id_token')
-    localStorage.removeItem('access_token')
+    clearTokens()
     dispatch(receiveLogout())
   }
 }
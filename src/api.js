@@ -3,7 +3,7 @@ import { createHttpLink } from 'apollo-link-http'
 import { setContext } from 'apollo-link-context'
 import { InMemoryCache } from 'apollo-cache-inmemory'
 import { onError } from 'apollo-link-error'
-import { logoutUser } from './actions'
+import { logoutUser, refreshAccessToken } from './actions'
 import { history, store } from './index'
 
 export const BASE_URL = 'http://local.uwcourseplan.com:8000'
@@ -12,8 +12,15 @@ const httpLink = createHttpLink({
   uri: `${BASE_URL}/graphql`
 })
 
-const authLink = setContext((_, { headers }) => {
-  const token = localStorage.getItem('access_token') || null
+// Refresh the access token if it expires within this many milliseconds
+const REFRESH_MARGIN_MS = 60 * 1000
+
+const authLink = setContext(async (_, { headers }) => {
+  let token = localStorage.getItem('access_token') || null
+  const expiresAt = Date.parse(localStorage.getItem('access_token_expires_at'))
+  if (token && !(expiresAt - Date.now() > REFRESH_MARGIN_MS)) {
+    token = await refreshAccessToken()
+  }
   return {
     headers: {
       ...headers,